@@ -1,6 +1,10 @@
-use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use criterion::{criterion_group, criterion_main, Criterion};
 
+#[cfg(feature = "image")]
+use criterion::BenchmarkId;
+#[cfg(feature = "image")]
 use image::DynamicImage;
+#[cfg(feature = "image")]
 use jpeg2k::*;
 
 #[inline]
@@ -47,11 +51,18 @@ pub fn criterion_benchmark(c: &mut Criterion) {
     })
   });
 
+  #[cfg(feature = "image")]
+  bench_to_dynamic_image(c);
+}
+
+#[cfg(feature = "image")]
+fn bench_to_dynamic_image(c: &mut Criterion) {
   let file_name =
     "samples/Hadley_Crater_provides_deep_insight_into_martian_geology_(7942261196).jp2";
-  let jp2_img = Image::from_file(&file_name).expect("Failed to load sample image");
+  let buf = std::fs::read(file_name).expect("Failed to read sample image");
+  let jp2_img = Image::from_bytes(&buf).expect("Failed to load sample image");
   c.bench_with_input(
-    BenchmarkId::new("jp2_to_DynamicImage", &file_name),
+    BenchmarkId::new("jp2_to_DynamicImage", file_name),
     &jp2_img,
     |bench, jp2| {
       bench.iter_with_large_drop(|| {
@@ -60,6 +71,22 @@ pub fn criterion_benchmark(c: &mut Criterion) {
       })
     },
   );
+  // The generic path: interleave with `get_pixels`, then wrap in an `ImageBuffer`.
+  c.bench_with_input(
+    BenchmarkId::new("jp2_get_pixels_to_DynamicImage", file_name),
+    &jp2_img,
+    |bench, jp2| {
+      bench.iter_with_large_drop(|| {
+        let data = jp2.get_pixels(None).expect("Failed to get pixels");
+        let jpeg2k::ImagePixelData::Rgb8(pixels) = data.data else {
+          panic!("Expected RGB8 pixels");
+        };
+        let rgb = image::RgbImage::from_vec(data.width, data.height, pixels)
+          .expect("Failed to create image");
+        DynamicImage::ImageRgb8(rgb)
+      })
+    },
+  );
 }
 
 criterion_group!(benches, criterion_benchmark);
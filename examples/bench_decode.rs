@@ -42,16 +42,16 @@ fn main() -> Result<()> {
   #[cfg(not(feature = "threads"))]
   let range = (0..repeat).into_par_iter();
   #[cfg(feature = "threads")]
-  let range = (0..repeat).into_iter();
+  let range = 0..repeat;
 
   let imgs = range
     .map(|_i| {
-      let img = Image::from_bytes_with(buf.as_slice(), params.clone())
+      let img = Image::from_bytes_with(buf.as_slice(), params)
         .expect("Image decode")
         .get_pixels(None)
         .expect("Pixels");
 
-      (img.data.len(), img.width, img.height)
+      ((img.width * img.height) as usize, img.width, img.height)
     })
     .collect::<Vec<_>>();
 
@@ -40,8 +40,7 @@ fn main() -> Result<()> {
   let imgs = (0..repeat)
     .into_par_iter()
     .map(|_i| {
-      let jp2 =
-        DumpImage::from_bytes_with(buf.as_slice(), params.clone()).expect("Image read header.");
+      let jp2 = DumpImage::from_bytes_with(buf.as_slice(), params).expect("Image read header.");
 
       (jp2.img.num_components(), jp2.img.width(), jp2.img.height())
     })
@@ -3,6 +3,7 @@ use std::path::Path;
 
 use anyhow::Result;
 
+#[cfg(feature = "image")]
 use image::DynamicImage;
 
 use jpeg2k::*;
@@ -56,10 +57,15 @@ fn main() -> Result<()> {
     Some("j2k") | Some("jp2") => {
       jp2_image.save_as_file(out_name)?;
     }
+    #[cfg(feature = "image")]
     _ => {
       let img: DynamicImage = (&jp2_image).try_into()?;
       img.save(&savename)?;
     }
+    #[cfg(not(feature = "image"))]
+    _ => {
+      anyhow::bail!("Saving as {savename:?} requires the `image` feature");
+    }
   }
 
   println!("Saved to: {out_name:?}");
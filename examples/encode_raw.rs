@@ -0,0 +1,61 @@
+use std::env;
+
+use anyhow::Result;
+
+use jpeg2k::*;
+
+fn main() -> Result<()> {
+  dotenv::dotenv().ok();
+  env_logger::init();
+
+  let savename = env::args()
+    .nth(1)
+    .unwrap_or_else(|| "gradient.jp2".to_string());
+  let width = 256;
+  let height = 256;
+
+  // Synthesize a 8bit RGB gradient.
+  let red = (0..height).flat_map(|_| 0..width).collect::<Vec<i32>>();
+  let green = (0..height)
+    .flat_map(|y| (0..width).map(move |_| y))
+    .collect::<Vec<i32>>();
+  let blue = red
+    .iter()
+    .zip(&green)
+    .map(|(r, g)| 255 - (r + g) / 2)
+    .collect::<Vec<i32>>();
+
+  let img = Image::from_components(
+    width as u32,
+    height as u32,
+    ColorSpace::SRGB,
+    vec![
+      ComponentData::new(red, 8),
+      ComponentData::new(green, 8),
+      ComponentData::new(blue, 8),
+    ],
+  )?;
+
+  // Lossless encode.
+  img.save_as_file_with(&savename, EncodeParameters::preset_archival())?;
+  println!("Saved to: {}", savename);
+
+  // Re-read and verify the samples.
+  let decoded = Image::from_file(&savename)?;
+  assert_eq!(decoded.num_components(), img.num_components());
+  for (idx, (orig, comp)) in img
+    .components()
+    .iter()
+    .zip(decoded.components())
+    .enumerate()
+  {
+    assert_eq!(orig.data(), comp.data(), "component {idx} doesn't match");
+  }
+  println!(
+    "Verified: width={}, height={}, components={}",
+    decoded.width(),
+    decoded.height(),
+    decoded.num_components()
+  );
+  Ok(())
+}
@@ -1,4 +1,6 @@
-use std::ffi::CStr;
+use std::cell::Cell;
+use std::ffi::{CStr, CString};
+use std::ops::Range;
 use std::os::raw::{c_char, c_void};
 use std::ptr;
 
@@ -12,13 +14,18 @@ use super::*;
 /// very large image.
 ///
 /// ```rust
+/// # use std::str::FromStr;
+/// # use jpeg2k::DecodeArea;
+/// # fn main() -> anyhow::Result<()> {
 /// let area = DecodeArea::new(10, 10, 200, 200);
 ///
 /// // or from a string:
 /// let area: DecodeArea = "10:10:200:200".parse()?;
 /// let area = DecodeArea::from_str("10:10:200:200")?;
+/// # Ok(())
+/// # }
 /// ```
-#[derive(Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy)]
 pub struct DecodeArea {
   start_x: u32,
   start_y: u32,
@@ -34,7 +41,7 @@ impl std::str::FromStr for DecodeArea {
       .map(|v| v.parse::<u32>())
       .collect::<Result<Vec<u32>, _>>()?;
     Ok(Self {
-      start_x: dim.get(0).copied().unwrap_or(0),
+      start_x: dim.first().copied().unwrap_or(0),
       start_y: dim.get(1).copied().unwrap_or(0),
       end_x: dim.get(2).copied().unwrap_or(0),
       end_y: dim.get(3).copied().unwrap_or(0),
@@ -51,6 +58,51 @@ impl DecodeArea {
       end_y,
     }
   }
+
+  /// An all zero area means the whole image.
+  fn is_whole_image(&self) -> bool {
+    self.start_x == 0 && self.start_y == 0 && self.end_x == 0 && self.end_y == 0
+  }
+
+  /// Clamp the area to the image bounds (from the header).
+  ///
+  /// Returns an error if the area is outside the image.
+  fn clamp_to(&self, img: &Image) -> Result<Self> {
+    if self.is_whole_image() {
+      return Ok(*self);
+    }
+    let (x0, y0) = (img.x_offset(), img.y_offset());
+    let (x1, y1) = (x0 + img.orig_width(), y0 + img.orig_height());
+    let area = Self {
+      start_x: self.start_x.max(x0),
+      start_y: self.start_y.max(y0),
+      end_x: self.end_x.min(x1),
+      end_y: self.end_y.min(y1),
+    };
+    if area.end_x <= area.start_x || area.end_y <= area.start_y {
+      return Err(Error::InvalidParametersError(format!(
+        "decode area {self} is outside the image bounds {x0}:{y0}:{x1}:{y1}"
+      )));
+    }
+    if area.start_x != self.start_x
+      || area.start_y != self.start_y
+      || area.end_x != self.end_x
+      || area.end_y != self.end_y
+    {
+      log::debug!("Decode area {self} clamped to the image bounds: {area}");
+    }
+    Ok(area)
+  }
+}
+
+impl std::fmt::Display for DecodeArea {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "{}:{}:{}:{}",
+      self.start_x, self.start_y, self.end_x, self.end_y
+    )
+  }
 }
 
 #[derive(Clone, Copy)]
@@ -58,6 +110,7 @@ pub struct DecodeParameters {
   params: sys::opj_dparameters,
   area: Option<DecodeArea>,
   strict: bool,
+  format_fallback: bool,
 }
 
 impl Default for DecodeParameters {
@@ -71,6 +124,7 @@ impl Default for DecodeParameters {
       params,
       area: Default::default(),
       strict: false,
+      format_fallback: false,
     }
   }
 }
@@ -84,6 +138,9 @@ impl DecodeParameters {
   ///
   /// If `reduce == 0`, image is decoded to the full resolution.  This is the default.
   /// If `reduce > 0`, then original dimension divided by 2^(reduce)
+  ///
+  /// This is applied before the header is read.  A resolution factor set after reading
+  /// the header ([`DumpImage::set_resolution_factor`]) takes precedence.
   pub fn reduce(mut self, reduce: u32) -> Self {
     self.params.cp_reduce = reduce;
     self
@@ -98,6 +155,37 @@ impl DecodeParameters {
     self
   }
 
+  /// Enable/disable retrying with another format when decoding fails.
+  ///
+  /// Only used when the format is given ([`Image::from_file_as`]), detected formats are
+  /// never retried.  If enabled and decoding fails with the given format, the image is
+  /// decoded again as a raw codestream (for JP2/JPX) or as JP2 (for a raw codestream).
+  /// This salvages valid files with a misleading file extension.
+  /// Disabled by default.
+  ///
+  /// ```rust
+  /// # use jpeg2k::*;
+  /// # use jpeg2k::format::J2KFormat;
+  /// # fn main() -> anyhow::Result<()> {
+  /// // `p0_03.j2k` is a raw codestream, not a JP2 file.
+  /// let params = DecodeParameters::new().auto_format_fallback(true);
+  /// let img = Image::from_file_as("samples/p0_03.j2k", J2KFormat::JP2, params)?;
+  /// assert_eq!((img.width(), img.height()), (256, 256));
+  ///
+  /// assert!(Image::from_file_as("samples/p0_03.j2k", J2KFormat::JP2, DecodeParameters::new()).is_err());
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn auto_format_fallback(mut self, enable: bool) -> Self {
+    self.format_fallback = enable;
+    self
+  }
+
+  #[cfg(feature = "file-io")]
+  pub(crate) fn is_format_fallback(&self) -> bool {
+    self.format_fallback
+  }
+
   /// The number of quality layers to decode.
   ///
   /// If there are less quality layers than the specified number,
@@ -113,26 +201,964 @@ impl DecodeParameters {
   /// The area to decode.
   ///
   /// If `area == None`, then the whole image will be decoded.  This is the defult.
+  /// An area partly outside the image is clamped to the image bounds, an area
+  /// outside the image is an error.
+  ///
+  /// ```rust
+  /// # use jpeg2k::*;
+  /// # fn main() -> anyhow::Result<()> {
+  /// // `p0_03.j2k` is 256x256.
+  /// let params = DecodeParameters::new().decode_area(Some(DecodeArea::new(200, 200, 400, 300)));
+  /// let img = Image::from_file_with("samples/p0_03.j2k", params)?;
+  /// assert_eq!((img.width(), img.height()), (56, 56));
+  ///
+  /// let params = DecodeParameters::new().decode_area(Some(DecodeArea::new(300, 300, 400, 400)));
+  /// assert!(Image::from_file_with("samples/p0_03.j2k", params).is_err());
+  /// # Ok(())
+  /// # }
+  /// ```
   pub fn decode_area(mut self, area: Option<DecodeArea>) -> Self {
     self.area = area;
     self
   }
 
+  /// Check that the parameters are consistent.
+  ///
+  /// This is called when decoding, but can be used to check user provided parameters up front.
+  ///
+  /// Only the parameters themselves are checked.  Constraints that depend on the image
+  /// (`reduce` vs. the number of resolutions, the decode area vs. the image bounds)
+  /// can only be checked after the header has been read.
+  ///
+  /// ```rust
+  /// # use jpeg2k::*;
+  /// let params = DecodeParameters::new().decode_area(Some(DecodeArea::new(10, 10, 5, 200)));
+  /// assert!(params.validate().is_err());
+  /// ```
+  pub fn validate(&self) -> Result<()> {
+    // openjpeg supports at most 33 resolution levels (`OPJ_J2K_MAXRLVLS`).
+    if self.params.cp_reduce >= 33 {
+      return Err(Error::InvalidParametersError(format!(
+        "reduce {} is larger than the max number of resolutions (33)",
+        self.params.cp_reduce
+      )));
+    }
+    if let Some(area) = self.area.filter(|a| !a.is_whole_image()) {
+      let max = i32::MAX as u32;
+      if area.start_x > max || area.start_y > max || area.end_x > max || area.end_y > max {
+        return Err(Error::InvalidParametersError(format!(
+          "decode area {area} is out of range (max {max})"
+        )));
+      }
+      if area.end_x <= area.start_x || area.end_y <= area.start_y {
+        return Err(Error::InvalidParametersError(format!(
+          "decode area {area} is empty, the end must be larger than the start"
+        )));
+      }
+    }
+    Ok(())
+  }
+
   pub(crate) fn as_ptr(&mut self) -> &mut sys::opj_dparameters {
     &mut self.params
   }
 }
 
-#[derive(Clone, Copy)]
-pub struct EncodeParameters(sys::opj_cparameters);
+/// Fixed layer allocation matrix.
+#[derive(Clone)]
+struct FixedAllocation {
+  matrix: Vec<i32>,
+  layers: u32,
+  resolutions: u32,
+}
+
+#[derive(Clone)]
+pub struct EncodeParameters {
+  params: sys::opj_cparameters,
+  plt: bool,
+  tlm: bool,
+  component_precision: Option<u32>,
+  fixed_alloc: Option<FixedAllocation>,
+  comment: Option<String>,
+  /// Frame rate of the digital cinema presets.
+  cinema_fps: u32,
+}
 
 impl Default for EncodeParameters {
   fn default() -> Self {
-    Self(unsafe {
+    let params = unsafe {
       let mut ptr = std::mem::zeroed::<sys::opj_cparameters>();
       sys::opj_set_default_encoder_parameters(&mut ptr as *mut _);
       ptr
-    })
+    };
+    Self {
+      params,
+      plt: false,
+      tlm: false,
+      component_precision: None,
+      fixed_alloc: None,
+      comment: None,
+      cinema_fps: 0,
+    }
+  }
+}
+
+impl EncodeParameters {
+  /// Preset for images served on the web.
+  ///
+  /// Lossy (about 20:1) with the irreversible 9/7 wavelet, `RPCL` progression
+  /// (resolution-progressive streaming) and small 256x256 tiles.
+  pub fn preset_web() -> Self {
+    let mut params = Self::default();
+    let p = &mut params.params;
+    p.irreversible = 1;
+    p.tcp_numlayers = 1;
+    p.tcp_rates[0] = 20.0;
+    p.cp_disto_alloc = 1;
+    p.prog_order = ProgressionOrder::RPCL.into();
+    p.tile_size_on = 1;
+    p.cp_tdx = 256;
+    p.cp_tdy = 256;
+    params
+  }
+
+  /// Preset for archival storage.
+  ///
+  /// Lossless with the reversible 5/3 wavelet, 1024x1024 tiles and
+  /// 6 resolution levels.
+  pub fn preset_archival() -> Self {
+    let mut params = Self::default();
+    let p = &mut params.params;
+    p.irreversible = 0;
+    p.tcp_numlayers = 1;
+    p.tcp_rates[0] = 0.0;
+    p.cp_disto_alloc = 1;
+    p.numresolution = 6;
+    p.tile_size_on = 1;
+    p.cp_tdx = 1024;
+    p.cp_tdy = 1024;
+    params
+  }
+
+  /// Preset for small thumbnails.
+  ///
+  /// Aggressive lossy compression (about 50:1) with the irreversible 9/7 wavelet
+  /// and only 3 resolution levels, so small images can be encoded.
+  pub fn preset_thumbnail() -> Self {
+    let mut params = Self::default();
+    let p = &mut params.params;
+    p.irreversible = 1;
+    p.tcp_numlayers = 1;
+    p.tcp_rates[0] = 50.0;
+    p.cp_disto_alloc = 1;
+    p.numresolution = 3;
+    params
+  }
+
+  /// Preset for digital cinema 2K (DCI) at `fps` frames per second, 24 or 48.
+  ///
+  /// The image must have 3 components (X'Y'Z'), unsigned 12 bits without subsampling,
+  /// and be at most 2048x1080.  Encoding fails otherwise.
+  ///
+  /// Sets the `Cinema2K` profile (`rsiz`) and the DCI rate limits: at most 250 Mbit/s for
+  /// the codestream and 200 Mbit/s per component.  openjpeg applies the other profile
+  /// constraints (single tile, 9/7 wavelet, 32x32 code-blocks, precincts, `CPRL`, one
+  /// layer, TLM marker and one tile-part per component).  The multi-component transform
+  /// is enabled.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # use jpeg2k::format::J2KFormat;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let comp = |v: i32| ComponentData::new((0..256 * 128).map(|i| (i * 16 + v) % 4096).collect(), 12);
+  /// let img = Image::from_components(256, 128, ColorSpace::SRGB, vec![comp(0), comp(1), comp(2)])?;
+  /// let buf = img.to_bytes(J2KFormat::J2K, EncodeParameters::cinema2k(24))?;
+  /// assert_eq!(DumpImage::from_bytes(&buf)?.check_profile(Rsiz::Cinema2K)?, []);
+  ///
+  /// // 8 bit samples aren't allowed.
+  /// let img = Image::from_components(256, 128, ColorSpace::Gray, vec![
+  ///   ComponentData::new(vec![0; 256 * 128], 8),
+  /// ])?;
+  /// assert!(img.to_bytes(J2KFormat::J2K, EncodeParameters::cinema2k(24)).is_err());
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn cinema2k(fps: u32) -> Self {
+    Self::cinema(Rsiz::Cinema2K, fps)
+  }
+
+  /// Preset for digital cinema 4K (DCI) at 24 frames per second.
+  ///
+  /// Like [`cinema2k`](Self::cinema2k), with images of at most 4096x2160.
+  pub fn cinema4k() -> Self {
+    Self::cinema(Rsiz::Cinema4K, 24)
+  }
+
+  fn cinema(profile: Rsiz, fps: u32) -> Self {
+    let mut params = Self::default()
+      .lossless(false)
+      .code_block_size(32, 32)
+      .mct(true);
+    params.cinema_fps = fps;
+    let p = &mut params.params;
+    p.rsiz = profile.to_raw();
+    p.prog_order = ProgressionOrder::CPRL.into();
+    // DCI: 250 Mbit/s for the codestream, 200 Mbit/s per component.
+    p.max_cs_size = (250_000_000 / 8u32).checked_div(fps).unwrap_or(0) as i32;
+    p.max_comp_size = (200_000_000 / 8u32).checked_div(fps).unwrap_or(0) as i32;
+    params
+  }
+
+  /// Check that the image meets the digital cinema requirements.  openjpeg would
+  /// silently encode a non-cinema codestream.
+  fn check_cinema(&self, img: &Image) -> Result<()> {
+    let profile = Rsiz::from_raw(self.params.rsiz);
+    let (max_w, max_h, fps): (_, _, &[u32]) = match profile {
+      Rsiz::Cinema2K => (2048, 1080, &[24, 48]),
+      Rsiz::Cinema4K => (4096, 2160, &[24]),
+      _ => return Ok(()),
+    };
+    if !fps.contains(&self.cinema_fps) {
+      return Err(Error::CreateCodecError(format!(
+        "{profile:?} at {} fps, supported frame rates: {fps:?}",
+        self.cinema_fps
+      )));
+    }
+    let comps = img.components();
+    if comps.len() != 3 {
+      return Err(Error::UnsupportedComponentsError(comps.len() as u32));
+    }
+    for comp in comps {
+      if comp.precision() != 12 || comp.is_signed() || comp.subsampling() != (1, 1) {
+        return Err(Error::CreateCodecError(format!(
+          "{profile:?} needs unsigned 12 bit components without subsampling"
+        )));
+      }
+    }
+    let (width, height) = (img.orig_width(), img.orig_height());
+    if width > max_w || height > max_h {
+      return Err(Error::CreateCodecError(format!(
+        "{profile:?} image size {width}x{height}, at most {max_w}x{max_h}"
+      )));
+    }
+    Ok(())
+  }
+
+  /// Lossy with the irreversible 9/7 wavelet and one layer at compression `ratio`.
+  pub(crate) fn lossy(ratio: f64) -> Self {
+    Self::default()
+      .lossless(false)
+      .compression_ratio(ratio.max(1.0) as f32)
+  }
+
+  /// Lossless (reversible 5/3 wavelet) or lossy (irreversible 9/7 wavelet) encoding.
+  ///
+  /// `lossless(true)` uses the reversible wavelet without quantization and a single
+  /// layer without rate limit, so the samples round-trip bit-exact.  This is the default.
+  /// `lossless(false)` switches to the irreversible wavelet, the layers and rates are
+  /// kept.  Without a rate the loss is only from the 9/7 wavelet quantization.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # use jpeg2k::format::J2KFormat;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let samples = (0..64 * 64).map(|v| (v * 7919) % 256).collect::<Vec<i32>>();
+  /// let img = Image::from_components(64, 64, ColorSpace::Gray, vec![
+  ///   ComponentData::new(samples.clone(), 8),
+  /// ])?;
+  /// let buf = img.to_bytes(J2KFormat::J2K, EncodeParameters::preset_web().lossless(true))?;
+  /// assert_eq!(DumpImage::from_bytes(&buf)?.is_reversible(), Some(true));
+  /// assert_eq!(Image::from_bytes(&buf)?.components()[0].data(), samples);
+  ///
+  /// let buf = img.to_bytes(J2KFormat::J2K, EncodeParameters::default().lossless(false))?;
+  /// assert_eq!(DumpImage::from_bytes(&buf)?.is_reversible(), Some(false));
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn lossless(mut self, lossless: bool) -> Self {
+    let p = &mut self.params;
+    if lossless {
+      p.irreversible = 0;
+      p.tcp_numlayers = 1;
+      p.tcp_rates[0] = 0.0;
+      p.cp_disto_alloc = 1;
+      p.cp_fixed_quality = 0;
+      p.cp_fixed_alloc = 0;
+      self.fixed_alloc = None;
+    } else {
+      p.irreversible = 1;
+    }
+    self
+  }
+
+  /// Encode one quality layer at compression `ratio`, for example `20.0` for 20:1.
+  ///
+  /// The ratio is relative to the uncompressed size of the components.  A ratio of `1.0`
+  /// or less is lossless (if the reversible wavelet is used).  Same as
+  /// `compression_ratios(&[ratio])`.
+  pub fn compression_ratio(self, ratio: f32) -> Self {
+    self.compression_ratios(&[ratio])
+  }
+
+  /// Encode one quality layer for each compression ratio.
+  ///
+  /// The ratios must be strictly decreasing (each layer adds quality), for example
+  /// `[80.0, 20.0, 5.0]`.  openjpeg supports at most 100 layers.  The ratios are checked
+  /// when encoding, invalid ratios are a `CreateCodecError`.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # use jpeg2k::format::J2KFormat;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let samples = (0..128 * 128).map(|v| (v * 7919) % 256).collect::<Vec<i32>>();
+  /// let img = Image::from_components(128, 128, ColorSpace::Gray, vec![
+  ///   ComponentData::new(samples, 8),
+  /// ])?;
+  /// let params = EncodeParameters::default().lossless(false).compression_ratio(20.0);
+  /// let buf = img.to_bytes(J2KFormat::J2K, params)?;
+  /// assert!(buf.len() <= 128 * 128 / 20 + 512, "{}", buf.len());
+  ///
+  /// let params = EncodeParameters::default().lossless(false).compression_ratios(&[40.0, 20.0, 10.0]);
+  /// let buf = img.to_bytes(J2KFormat::J2K, params)?;
+  /// let layer1 = Image::from_bytes_with(&buf, DecodeParameters::new().layers(1))?;
+  /// assert_eq!(layer1.width(), 128);
+  ///
+  /// // Not decreasing.
+  /// let params = EncodeParameters::default().compression_ratios(&[10.0, 20.0]);
+  /// assert!(matches!(
+  ///   img.to_bytes(J2KFormat::J2K, params),
+  ///   Err(jpeg2k::error::Error::CreateCodecError(_))
+  /// ));
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn compression_ratios(mut self, ratios: &[f32]) -> Self {
+    let p = &mut self.params;
+    let len = ratios.len().min(p.tcp_rates.len());
+    p.tcp_rates.fill(0.0);
+    p.tcp_rates[..len].copy_from_slice(&ratios[..len]);
+    p.tcp_numlayers = ratios.len().try_into().unwrap_or(i32::MAX);
+    p.cp_disto_alloc = 1;
+    p.cp_fixed_quality = 0;
+    p.cp_fixed_alloc = 0;
+    self.fixed_alloc = None;
+    self
+  }
+
+  /// Encode one quality layer for each target PSNR (in dB).
+  ///
+  /// The PSNRs must be strictly increasing (each layer adds quality), the last one can
+  /// be `0.0` for a lossless last layer (if the reversible wavelet is used), for example
+  /// `[30.0, 40.0, 0.0]`.  openjpeg supports at most 100 layers.  The PSNRs are checked
+  /// when encoding, invalid values are a `CreateCodecError`.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # use jpeg2k::format::J2KFormat;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let samples = (0..128 * 128).map(|v| (v % 128 + v / 128) % 256).collect::<Vec<i32>>();
+  /// let img = Image::from_components(128, 128, ColorSpace::Gray, vec![
+  ///   ComponentData::new(samples.clone(), 8),
+  /// ])?;
+  /// let params = EncodeParameters::default().quality_layers(&[30.0, 40.0, 0.0]);
+  /// let buf = img.to_bytes(J2KFormat::J2K, params)?;
+  /// // The last layer is lossless.
+  /// assert_eq!(Image::from_bytes(&buf)?.components()[0].data(), samples);
+  /// let layer1 = Image::from_bytes_with(&buf, DecodeParameters::new().layers(1))?;
+  /// assert_ne!(layer1.components()[0].data(), samples);
+  ///
+  /// // Not increasing.
+  /// let params = EncodeParameters::default().quality_layers(&[40.0, 30.0]);
+  /// assert!(img.to_bytes(J2KFormat::J2K, params).is_err());
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn quality_layers(mut self, psnr_db: &[f32]) -> Self {
+    let p = &mut self.params;
+    let len = psnr_db.len().min(p.tcp_distoratio.len());
+    p.tcp_distoratio.fill(0.0);
+    p.tcp_distoratio[..len].copy_from_slice(&psnr_db[..len]);
+    p.tcp_numlayers = psnr_db.len().try_into().unwrap_or(i32::MAX);
+    p.cp_fixed_quality = 1;
+    p.cp_disto_alloc = 0;
+    p.cp_fixed_alloc = 0;
+    self.fixed_alloc = None;
+    self
+  }
+
+  /// Check the target PSNRs of the layers.
+  fn check_quality_layers(&self) -> Result<()> {
+    let p = &self.params;
+    if p.cp_fixed_quality == 0 {
+      return Ok(());
+    }
+    let max = p.tcp_distoratio.len();
+    let num_layers = p.tcp_numlayers as usize;
+    if num_layers > max {
+      return Err(Error::CreateCodecError(format!(
+        "{num_layers} quality layers, at most {max} layers are supported"
+      )));
+    }
+    let psnrs = &p.tcp_distoratio[..num_layers];
+    if psnrs.iter().any(|q| !q.is_finite() || *q < 0.0) {
+      return Err(Error::CreateCodecError(format!(
+        "quality layers {psnrs:?}, the PSNRs must be positive"
+      )));
+    }
+    // A lossless (0.0) last layer.
+    let lossy = match psnrs.split_last() {
+      Some((0.0, lossy)) => lossy,
+      _ => psnrs,
+    };
+    if lossy.contains(&0.0) || lossy.windows(2).any(|w| w[1] <= w[0]) {
+      return Err(Error::CreateCodecError(format!(
+        "quality layers {psnrs:?}, PSNRs must be increasing, only the last can be 0.0"
+      )));
+    }
+    Ok(())
+  }
+
+  /// Check the compression ratios of the layers.
+  fn check_compression_ratios(&self) -> Result<()> {
+    let p = &self.params;
+    if p.cp_disto_alloc == 0 {
+      return Ok(());
+    }
+    let max = p.tcp_rates.len();
+    let num_layers = p.tcp_numlayers as usize;
+    if num_layers > max {
+      return Err(Error::CreateCodecError(format!(
+        "{num_layers} compression ratios, at most {max} layers are supported"
+      )));
+    }
+    let rates = &p.tcp_rates[..num_layers];
+    if rates.iter().any(|r| !r.is_finite() || *r < 0.0) {
+      return Err(Error::CreateCodecError(format!(
+        "compression ratios {rates:?}, they must be positive"
+      )));
+    }
+    if rates.windows(2).any(|w| w[1] >= w[0]) {
+      return Err(Error::CreateCodecError(format!(
+        "compression ratios {rates:?}, they must be strictly decreasing"
+      )));
+    }
+    Ok(())
+  }
+
+  /// Number of resolution levels (`1..=33`), the number of wavelet decompositions plus
+  /// one.  The default is 6.
+  ///
+  /// openjpeg needs tiles (or the image, without tiling) of at least `2^(n - 1)` pixels
+  /// in each dimension.  When encoding, the number of resolutions is reduced to the
+  /// maximum the tile size allows (except with a [fixed allocation](Self::fixed_allocation)),
+  /// so small images can be encoded with the default.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// let params = EncodeParameters::default().resolutions(3);
+  /// ```
+  pub fn resolutions(mut self, n: u32) -> Self {
+    self.params.numresolution = n.try_into().unwrap_or(i32::MAX);
+    self
+  }
+
+  /// Check the number of resolutions and clamp it to the tile size.
+  fn clamp_resolutions(&mut self, img: &Image) -> Result<()> {
+    let p = &mut self.params;
+    // openjpeg supports at most 33 resolution levels (`OPJ_J2K_MAXRLVLS`).
+    if !(1..=33).contains(&p.numresolution) {
+      return Err(Error::InvalidParametersError(format!(
+        "{} resolutions, it must be 1 to 33",
+        p.numresolution
+      )));
+    }
+    if self.fixed_alloc.is_some() {
+      // The allocation matrix depends on the number of resolutions.
+      return Ok(());
+    }
+    // Without tiling the tile is the image from the tile origin.
+    let (tdx, tdy) = if p.tile_size_on != 0 {
+      (p.cp_tdx as u32, p.cp_tdy as u32)
+    } else {
+      let x1 = img.x_offset() + img.orig_width();
+      let y1 = img.y_offset() + img.orig_height();
+      (
+        x1.saturating_sub(p.cp_tx0 as u32),
+        y1.saturating_sub(p.cp_ty0 as u32),
+      )
+    };
+    let min = tdx.min(tdy);
+    if min == 0 {
+      return Ok(());
+    }
+    // `floor(log2(min)) + 1`
+    let max = (u32::BITS - min.leading_zeros()) as i32;
+    if p.numresolution > max {
+      log::debug!(
+        "{} resolutions clamped to {max} for {tdx}x{tdy} tiles",
+        p.numresolution
+      );
+      p.numresolution = max;
+    }
+    Ok(())
+  }
+
+  /// Enable/disable the multi-component transform (RGB to YCC, `tcp_mct`).
+  ///
+  /// The transform decorrelates the color components, which improves the compression of
+  /// photographic RGB images.  It can hurt false-color imagery (for example remote
+  /// sensing bands) where the components aren't correlated.  Disabled by default.
+  ///
+  /// It is only applied to images with exactly 3 components of the same size, for other
+  /// images it is ignored.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # use jpeg2k::format::J2KFormat;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let comp = |v: i32| ComponentData::new((0..64 * 64).map(|i| (i + v) % 256).collect(), 8);
+  /// let rgb = Image::from_components(64, 64, ColorSpace::SRGB, vec![comp(0), comp(1), comp(2)])?;
+  /// let has_mct = |buf: &[u8]| -> jpeg2k::error::Result<bool> {
+  ///   let issues = DumpImage::from_bytes(buf)?.check_profile(Rsiz::Cinema2K)?;
+  ///   Ok(issues.iter().all(|i| i.parameter != "multi-component transform"))
+  /// };
+  /// let buf = rgb.to_bytes(J2KFormat::J2K, EncodeParameters::default().mct(true))?;
+  /// assert!(has_mct(&buf)?);
+  /// let buf = rgb.to_bytes(J2KFormat::J2K, EncodeParameters::default())?;
+  /// assert!(!has_mct(&buf)?);
+  ///
+  /// // Ignored for grayscale.
+  /// let gray = Image::from_components(64, 64, ColorSpace::Gray, vec![comp(0)])?;
+  /// let buf = gray.to_bytes(J2KFormat::J2K, EncodeParameters::default().mct(true))?;
+  /// assert!(!has_mct(&buf)?);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn mct(mut self, enabled: bool) -> Self {
+    self.params.tcp_mct = enabled as _;
+    self
+  }
+
+  /// Precinct sizes `(width, height)`, from the highest resolution down (`prcw_init`,
+  /// `prch_init`).
+  ///
+  /// Precincts split the packets of each resolution spatially, so a decoder can read the
+  /// packets of a region without the rest of the tile.  Resolutions without an entry use
+  /// the last size, halved for each lower resolution.  An empty slice disables precincts,
+  /// this is the default (one precinct per resolution).
+  ///
+  /// The sizes must be powers of two from 2 to 32768, with at most one entry per
+  /// resolution.  This is checked when encoding.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// // Digital cinema: 256x256 precincts, 128x128 for the lowest of the 6 resolutions.
+  /// let mut sizes = vec![(256, 256); 5];
+  /// sizes.push((128, 128));
+  /// let params = EncodeParameters::default().resolutions(6).precincts(&sizes);
+  /// ```
+  pub fn precincts(mut self, sizes: &[(u32, u32)]) -> Self {
+    let p = &mut self.params;
+    if sizes.is_empty() {
+      p.csty &= !0x01;
+    } else {
+      p.csty |= 0x01;
+    }
+    p.res_spec = sizes.len().try_into().unwrap_or(i32::MAX);
+    for (idx, &(w, h)) in sizes.iter().take(p.prcw_init.len()).enumerate() {
+      p.prcw_init[idx] = w.try_into().unwrap_or(i32::MAX);
+      p.prch_init[idx] = h.try_into().unwrap_or(i32::MAX);
+    }
+    self
+  }
+
+  fn check_precincts(&self) -> Result<()> {
+    let p = &self.params;
+    if p.csty & 0x01 == 0 {
+      return Ok(());
+    }
+    if p.res_spec > p.numresolution {
+      return Err(Error::InvalidParametersError(format!(
+        "{} precinct sizes, at most one per resolution ({})",
+        p.res_spec, p.numresolution
+      )));
+    }
+    let valid = |v: i32| (2..=32768).contains(&v) && (v as u32).is_power_of_two();
+    let sizes = p.prcw_init.iter().zip(&p.prch_init);
+    for (&w, &h) in sizes.take(p.res_spec as usize) {
+      if !valid(w) || !valid(h) {
+        return Err(Error::InvalidParametersError(format!(
+          "precinct size {w}x{h}, must be powers of two from 2 to 32768"
+        )));
+      }
+    }
+    Ok(())
+  }
+
+  /// Write SOP (start of packet) markers before each packet.
+  ///
+  /// With SOP and [EPH](Self::eph) markers a decoder can resynchronize after corrupted
+  /// data, for example when streaming over a lossy transport.  Disabled by default.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// let params = EncodeParameters::default().sop(true).eph(true);
+  /// ```
+  pub fn sop(mut self, sop: bool) -> Self {
+    if sop {
+      self.params.csty |= 0x02;
+    } else {
+      self.params.csty &= !0x02;
+    }
+    self
+  }
+
+  /// Write EPH (end of packet header) markers after each packet header.  Disabled by
+  /// default.
+  pub fn eph(mut self, eph: bool) -> Self {
+    if eph {
+      self.params.csty |= 0x04;
+    } else {
+      self.params.csty &= !0x04;
+    }
+    self
+  }
+
+  /// Comment written in a COM marker, for example a provenance string.
+  ///
+  /// The comment must be at most 65531 bytes (the size of a COM marker) without NUL
+  /// characters.  Longer comments aren't truncated, the encode fails with an error.  By
+  /// default openjpeg writes "Created by OpenJPEG version ...".
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// let params = EncodeParameters::default().comment("Scanned by the city archive");
+  /// ```
+  pub fn comment(mut self, text: &str) -> Self {
+    self.comment = Some(text.into());
+    self
+  }
+
+  /// The comment as a C string.
+  fn comment_cstring(&self) -> Result<Option<CString>> {
+    let Some(comment) = &self.comment else {
+      return Ok(None);
+    };
+    // The marker length (`Lcom`) is 16 bits and includes itself and `Rcom`.
+    let max = u16::MAX as usize - 4;
+    if comment.len() > max {
+      return Err(Error::InvalidParametersError(format!(
+        "comment of {} bytes, at most {max} bytes are supported",
+        comment.len()
+      )));
+    }
+    CString::new(comment.as_str())
+      .map(Some)
+      .map_err(|_| Error::InvalidParametersError("comment contains a NUL character".into()))
+  }
+
+  /// Region of interest: upshift the coefficients of `component` by `upshift` bit-planes
+  /// (`roi_compno`, `roi_shift`) to encode it at a higher fidelity.  This writes an RGN
+  /// marker.
+  ///
+  /// The component index is checked against the image when encoding, the upshift must
+  /// be at most 37.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// // Encode the first component with 6 more bit-planes.
+  /// let params = EncodeParameters::default().roi(0, 6);
+  /// ```
+  pub fn roi(mut self, component: u32, upshift: u32) -> Self {
+    self.params.roi_compno = component.try_into().unwrap_or(i32::MAX);
+    self.params.roi_shift = upshift.try_into().unwrap_or(i32::MAX);
+    self
+  }
+
+  fn check_roi(&self, img: &Image) -> Result<()> {
+    let p = &self.params;
+    if p.roi_compno < 0 {
+      return Ok(());
+    }
+    let numcomps = img.num_components();
+    if p.roi_compno as u32 >= numcomps {
+      return Err(Error::InvalidParametersError(format!(
+        "region of interest component {}, the image has {numcomps} components",
+        p.roi_compno
+      )));
+    }
+    if !(0..=37).contains(&p.roi_shift) {
+      return Err(Error::InvalidParametersError(format!(
+        "region of interest upshift {}, it must be at most 37",
+        p.roi_shift
+      )));
+    }
+    Ok(())
+  }
+
+  /// Disable the multi-component transform for images without 3 components.
+  fn check_mct(&mut self, img: &Image) {
+    let numcomps = img.num_components();
+    if self.params.tcp_mct == 1 && numcomps != 3 {
+      log::debug!("Multi-component transform disabled for {numcomps} components");
+      self.params.tcp_mct = 0;
+    }
+  }
+
+  /// Progression order of the packets.  The default is `LRCP` (quality progressive).
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// let params = EncodeParameters::default().progression_order(ProgressionOrder::CPRL);
+  /// ```
+  pub fn progression_order(mut self, order: ProgressionOrder) -> Self {
+    self.params.prog_order = order.into();
+    self
+  }
+
+  /// Split the image into `tile_w x tile_h` tiles, each tile is encoded on its own.
+  ///
+  /// Tiling bounds the encoder memory for large images and lets decoders decode a region
+  /// without decoding the whole image.  This sets `tile_size_on` with the tile size
+  /// (`cp_tdx`, `cp_tdy`), and the tile grid origin (`cp_tx0`, `cp_ty0`) to `(0, 0)`.
+  /// The tiles on the right and bottom edges are smaller if the image size isn't a
+  /// multiple of the tile size.  The default is a single tile, `tiles(0, 0)` disables
+  /// tiling again.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// let params = EncodeParameters::default().tiles(1024, 1024);
+  /// ```
+  pub fn tiles(mut self, tile_w: u32, tile_h: u32) -> Self {
+    let p = &mut self.params;
+    if tile_w == 0 || tile_h == 0 {
+      p.tile_size_on = 0;
+      p.cp_tdx = 0;
+      p.cp_tdy = 0;
+    } else {
+      p.tile_size_on = 1;
+      p.cp_tdx = tile_w.try_into().unwrap_or(i32::MAX);
+      p.cp_tdy = tile_h.try_into().unwrap_or(i32::MAX);
+    }
+    p.cp_tx0 = 0;
+    p.cp_ty0 = 0;
+    self
+  }
+
+  /// Code-block size (`cblockw_init`, `cblockh_init`).  The default is 64x64.
+  ///
+  /// Smaller code-blocks make decoding a small region cheaper, at the cost of
+  /// compression efficiency.  The width and height must be powers of two from 4 to 1024,
+  /// with at most 4096 samples per code-block.  This is checked when encoding.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// let params = EncodeParameters::default().code_block_size(32, 32);
+  /// ```
+  pub fn code_block_size(mut self, w: u32, h: u32) -> Self {
+    self.params.cblockw_init = w.try_into().unwrap_or(i32::MAX);
+    self.params.cblockh_init = h.try_into().unwrap_or(i32::MAX);
+    self
+  }
+
+  fn check_code_block_size(&self) -> Result<()> {
+    let (w, h) = (self.params.cblockw_init, self.params.cblockh_init);
+    let valid = |v: i32| (4..=1024).contains(&v) && (v as u32).is_power_of_two();
+    if !valid(w) || !valid(h) || w * h > 4096 {
+      return Err(Error::CreateCodecError(format!(
+        "code-block size {w}x{h}, must be powers of two from 4 to 1024 with at most 4096 samples"
+      )));
+    }
+    Ok(())
+  }
+
+  /// Write PLT (packet length) markers.  Requires openjpeg 2.4 or newer.
+  ///
+  /// PLT markers let decoders and JPIP servers find the packets of a tile
+  /// without parsing the whole tile.  Disabled by default.
+  ///
+  /// The options are passed to `opj_encoder_set_extra_options` after the encoder setup,
+  /// encoding fails if openjpeg rejects them.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// let params = EncodeParameters::default()
+  ///   .tiles(1024, 1024)
+  ///   .write_plt(true)
+  ///   .write_tlm(true);
+  /// ```
+  pub fn write_plt(mut self, plt: bool) -> Self {
+    self.plt = plt;
+    self
+  }
+
+  /// Write a TLM (tile-part length) marker.  Requires openjpeg 2.4 or newer.
+  ///
+  /// The TLM marker lets decoders seek to a tile without scanning all tile-parts.
+  /// Disabled by default.
+  pub fn write_tlm(mut self, tlm: bool) -> Self {
+    self.tlm = tlm;
+    self
+  }
+
+  /// Subsampling (`dx`, `dy`) of the subsampled components, for example `(2, 2)` for
+  /// YCbCr 4:2:0.
+  ///
+  /// When set (not `(1, 1)`), the encoder checks that each image component is either
+  /// not subsampled or subsampled by `(dx, dy)`.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # use jpeg2k::format::J2KFormat;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let luma = (0..64 * 64).map(|v| v % 256).collect::<Vec<i32>>();
+  /// let chroma = (0..32 * 32).map(|v| v % 256).collect::<Vec<i32>>();
+  /// let new_image = || Image::from_components(64, 64, ColorSpace::SYCC, vec![
+  ///   ComponentData::new(luma.clone(), 8),
+  ///   ComponentData::new(chroma.clone(), 8).subsampling(2, 2),
+  ///   ComponentData::new(chroma.clone(), 8).subsampling(2, 2),
+  /// ]);
+  /// let params = EncodeParameters::default().subsampling(2, 2);
+  /// let buf = new_image()?.into_bytes(J2KFormat::J2K, params)?;
+  /// let img = Image::from_bytes(&buf)?;
+  /// let comps = img.components();
+  /// assert_eq!(comps[0].data(), luma);
+  /// assert_eq!(comps[1].subsampling(), (2, 2));
+  /// assert_eq!(comps[2].data(), chroma);
+  ///
+  /// // Doesn't match the image.
+  /// let params = EncodeParameters::default().subsampling(4, 4);
+  /// assert!(new_image()?.into_bytes(J2KFormat::J2K, params).is_err());
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn subsampling(mut self, dx: u32, dy: u32) -> Self {
+    self.params.subsampling_dx = dx.try_into().unwrap_or(i32::MAX);
+    self.params.subsampling_dy = dy.try_into().unwrap_or(i32::MAX);
+    self
+  }
+
+  /// Encode all components with a precision of `prec` (`1..=16`) bits.
+  ///
+  /// A rescue option for images with a precision openjpeg can't encode (for example
+  /// 24bit).  Only the precision is changed, samples that don't fit in `prec` bits are
+  /// clamped (a warning is logged).  Use [`Image::normalize_precision`] to rescale the
+  /// samples instead.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # use jpeg2k::format::J2KFormat;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// // Declared as 20bit, but the samples fit in 16bit.
+  /// let samples = [0, 1000, 40000, 65535].repeat(32 * 32);
+  /// let img = Image::from_components(64, 64, ColorSpace::Gray, vec![
+  ///   ComponentData::new(samples.clone(), 20),
+  /// ])?;
+  /// let params = EncodeParameters::default().component_precision(16);
+  /// let img = Image::from_bytes(&img.into_bytes(J2KFormat::J2K, params)?)?;
+  /// assert_eq!(img.components()[0].precision(), 16);
+  /// assert_eq!(img.components()[0].data(), samples);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn component_precision(mut self, prec: u32) -> Self {
+    self.component_precision = Some(prec);
+    self
+  }
+
+  /// The component precision override, checked to be `1..=16`.
+  pub(crate) fn get_component_precision(&self) -> Result<Option<u32>> {
+    match self.component_precision {
+      Some(prec) if !(1..=16).contains(&prec) => Err(Error::InvalidParametersError(format!(
+        "component precision {prec}, it must be 1 to 16 bits"
+      ))),
+      prec => Ok(prec),
+    }
+  }
+
+  /// Fixed layer allocation: the number of bit-planes of each layer, resolution and band.
+  ///
+  /// Replaces the rate/quality allocation with an explicit allocation, so the output
+  /// only depends on the image and the parameters.  `matrix` has `layers * resolutions * 3`
+  /// entries in `[layer][resolution][band]` order, where the bands are `LL` (at
+  /// resolution 0 only), or `HL`, `LH`, `HH`.  Each entry is the cumulative number of
+  /// bit-planes included up to that layer, in units of a 16bit component (scaled by
+  /// `precision / 16`).  This sets the number of layers and resolutions.
+  ///
+  /// The matrix size is checked when encoding.  openjpeg doesn't clamp the entries to the
+  /// bit-planes of each code-block, the encode fails if an entry asks for more bit-planes
+  /// than a code-block has.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// // 2 layers of 3 resolutions: 2 and then 4 bit-planes of an 8bit component.
+  /// let matrix = [[4; 9], [8; 9]].concat();
+  /// let params = EncodeParameters::default().fixed_allocation(matrix, 2, 3);
+  /// ```
+  pub fn fixed_allocation(mut self, matrix: Vec<i32>, layers: u32, resolutions: u32) -> Self {
+    self.fixed_alloc = Some(FixedAllocation {
+      matrix,
+      layers,
+      resolutions,
+    });
+    let p = &mut self.params;
+    p.tcp_numlayers = layers as i32;
+    p.numresolution = resolutions as i32;
+    p.cp_fixed_alloc = 1;
+    p.cp_disto_alloc = 0;
+    p.cp_fixed_quality = 0;
+    self
+  }
+
+  /// Check the fixed allocation matrix size.
+  fn check_fixed_allocation(&self) -> Result<()> {
+    let Some(fixed) = &self.fixed_alloc else {
+      return Ok(());
+    };
+    let expected = fixed.layers as u64 * fixed.resolutions as u64 * 3;
+    if fixed.matrix.len() as u64 != expected {
+      return Err(Error::InvalidParametersError(format!(
+        "fixed allocation matrix has {} entries, expected {expected} (layers * resolutions * 3)",
+        fixed.matrix.len()
+      )));
+    }
+    Ok(())
+  }
+
+  /// Check that the subsampling matches the image components.
+  fn check_subsampling(&self, img: &Image) -> Result<()> {
+    let (dx, dy) = (self.params.subsampling_dx, self.params.subsampling_dy);
+    if dx < 1 || dy < 1 {
+      return Err(Error::InvalidParametersError(format!(
+        "subsampling {dx}x{dy}, it must be at least 1"
+      )));
+    }
+    if (dx, dy) == (1, 1) {
+      return Ok(());
+    }
+    let (dx, dy) = (dx as u32, dy as u32);
+    for (idx, comp) in img.components().iter().enumerate() {
+      let sub = comp.subsampling();
+      if sub != (1, 1) && sub != (dx, dy) {
+        return Err(Error::InvalidParametersError(format!(
+          "subsampling {dx}x{dy} doesn't match component {idx} subsampling {}x{}",
+          sub.0, sub.1
+        )));
+      }
+    }
+    Ok(())
+  }
+
+  /// Options for `opj_encoder_set_extra_options`.
+  fn extra_options(&self) -> Vec<&'static CStr> {
+    let mut options = Vec::new();
+    if self.plt {
+      options.push(c"PLT=YES");
+    }
+    if self.tlm {
+      options.push(c"TLM=YES");
+    }
+    options
   }
 }
 
@@ -199,7 +1225,7 @@ impl std::fmt::Debug for TileCodingParamInfo {
 
 impl TileCodingParamInfo {
   fn as_ref(&self) -> &sys::opj_tccp_info_t {
-    unsafe { &(*self.0.as_ref()) }
+    unsafe { self.0.as_ref() }
   }
 }
 
@@ -220,7 +1246,7 @@ impl<'a> std::fmt::Debug for TileInfo<'a> {
 
 impl<'a> TileInfo<'a> {
   fn tccp_info(&self) -> Option<TileCodingParamInfo> {
-    ptr::NonNull::new(self.0.tccp_info).map(|info| TileCodingParamInfo(info))
+    ptr::NonNull::new(self.0.tccp_info).map(TileCodingParamInfo)
   }
 }
 
@@ -246,6 +1272,9 @@ impl std::fmt::Debug for CodestreamTileIndex {
 impl CodestreamTileIndex {
   /// Tile part index.
   pub fn tile_parts(&self) -> &[CodestreamTilePartIndex] {
+    if self.0.tp_index.is_null() {
+      return &[];
+    }
     let num = self.0.nb_tps;
     unsafe {
       std::slice::from_raw_parts(
@@ -257,12 +1286,18 @@ impl CodestreamTileIndex {
 
   /// Tile markers.
   pub fn markers(&self) -> &[CodestreamMarker] {
+    if self.0.marker.is_null() {
+      return &[];
+    }
     let num = self.0.marknum;
     unsafe { std::slice::from_raw_parts(self.0.marker as *mut CodestreamMarker, num as usize) }
   }
 
   /// Codestream packet info.
   pub fn packets(&self) -> &[CodestreamPacketInfo] {
+    if self.0.packet_index.is_null() {
+      return &[];
+    }
     let num = self.0.nb_packet;
     unsafe {
       std::slice::from_raw_parts(
@@ -301,12 +1336,15 @@ impl std::fmt::Debug for CodestreamIndex {
 
 impl CodestreamIndex {
   fn as_ref(&self) -> &sys::opj_codestream_index_t {
-    unsafe { &(*self.0.as_ref()) }
+    unsafe { self.0.as_ref() }
   }
 
   /// Codestream markers.
   pub fn markers(&self) -> &[CodestreamMarker] {
     let idx = self.as_ref();
+    if idx.marker.is_null() {
+      return &[];
+    }
     let num = idx.marknum;
     unsafe { std::slice::from_raw_parts(idx.marker as *mut CodestreamMarker, num as usize) }
   }
@@ -314,9 +1352,41 @@ impl CodestreamIndex {
   /// Codestream tile indices.
   pub fn tile_indices(&self) -> &[CodestreamTileIndex] {
     let idx = self.as_ref();
+    if idx.tile_index.is_null() {
+      return &[];
+    }
     let num = idx.nb_of_tiles;
     unsafe { std::slice::from_raw_parts(idx.tile_index as *mut CodestreamTileIndex, num as usize) }
   }
+
+  /// Byte range of each quality layer, from the tile-part index.
+  ///
+  /// Needs one tile-part per layer in each tile.
+  pub(crate) fn layer_byte_ranges(&self, num_layers: u32) -> Result<Vec<Range<u64>>> {
+    let tiles = self.tile_indices();
+    if tiles.is_empty() || num_layers == 0 {
+      return Err(Error::CodecError("no tile index".into()));
+    }
+    let mut ranges: Vec<Option<Range<u64>>> = vec![None; num_layers as usize];
+    for tile in tiles {
+      let parts = tile.tile_parts();
+      if parts.len() != num_layers as usize {
+        return Err(Error::CodecError(format!(
+          "tile {} has {} tile-parts, layer byte ranges need one tile-part per layer ({num_layers})",
+          tile.0.tileno,
+          parts.len()
+        )));
+      }
+      for (range, part) in ranges.iter_mut().zip(parts) {
+        let (start, end) = (part.0.start_pos as u64, part.0.end_pos as u64);
+        *range = Some(match range.take() {
+          Some(r) => r.start.min(start)..r.end.max(end),
+          None => start..end,
+        });
+      }
+    }
+    Ok(ranges.into_iter().flatten().collect())
+  }
 }
 
 pub struct CodestreamInfo(ptr::NonNull<sys::opj_codestream_info_v2_t>);
@@ -332,11 +1402,7 @@ impl Drop for CodestreamInfo {
 impl std::fmt::Debug for CodestreamInfo {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     let info = self.as_ref();
-    let tile_info = if info.tile_info.is_null() {
-      TileInfo(&info.m_default_tile_info)
-    } else {
-      TileInfo(unsafe { &*info.tile_info })
-    };
+    let tile_info = self.tile_info();
     f.debug_struct("CodestreamInfo")
       .field("tx0", &info.tx0)
       .field("ty0", &info.ty0)
@@ -352,7 +1418,61 @@ impl std::fmt::Debug for CodestreamInfo {
 
 impl CodestreamInfo {
   fn as_ref(&self) -> &sys::opj_codestream_info_v2_t {
-    unsafe { &(*self.0.as_ref()) }
+    unsafe { self.0.as_ref() }
+  }
+
+  pub(crate) fn tile_info(&self) -> TileInfo<'_> {
+    let info = self.as_ref();
+    if info.tile_info.is_null() {
+      TileInfo(&info.m_default_tile_info)
+    } else {
+      TileInfo(unsafe { &*info.tile_info })
+    }
+  }
+
+  /// Default tile coding parameters for each component.
+  pub(crate) fn tccp_info(&self) -> &[sys::opj_tccp_info_t] {
+    let info = self.as_ref();
+    let tccp_info = self.tile_info().0.tccp_info;
+    if tccp_info.is_null() {
+      return &[];
+    }
+    unsafe { std::slice::from_raw_parts(tccp_info, info.nbcomps as usize) }
+  }
+
+  /// Number of tiles.
+  pub(crate) fn num_tiles(&self) -> u32 {
+    let info = self.as_ref();
+    info.tw * info.th
+  }
+
+  /// Nominal tile size.
+  pub(crate) fn tile_size(&self) -> (u32, u32) {
+    let info = self.as_ref();
+    (info.tdx, info.tdy)
+  }
+
+  /// Number of quality layers.
+  pub(crate) fn num_layers(&self) -> u32 {
+    self.tile_info().0.numlayers
+  }
+
+  /// Number of resolution levels.  The smallest number over all components.
+  pub(crate) fn num_resolutions(&self) -> Option<u32> {
+    self
+      .tccp_info()
+      .iter()
+      .map(|tccp| tccp.numresolutions)
+      .min()
+  }
+
+  /// Are all components encoded with the reversible 5/3 wavelet.
+  pub(crate) fn is_reversible(&self) -> Option<bool> {
+    let tccp_info = self.tccp_info();
+    if tccp_info.is_empty() {
+      return None;
+    }
+    Some(tccp_info.iter().all(|tccp| tccp.qmfbid == 1))
   }
 }
 
@@ -419,7 +1539,8 @@ impl Codec {
       Ok(Self { codec: ptr })
     } else {
       Err(Error::CreateCodecError(format!(
-        "Codec not supported: {:?}",
+        "Codec not supported: openjpeg can't {} the {:?} format",
+        if is_decoder { "decode" } else { "encode" },
         fmt
       )))
     }
@@ -433,6 +1554,10 @@ impl Codec {
 pub(crate) struct Decoder<'a> {
   codec: Codec,
   stream: Stream<'a>,
+  /// openjpeg applied the JP2 channel definitions, it only does that on the first decode.
+  cdef_applied: Cell<bool>,
+  /// The `reduce` decode parameter.
+  reduce: Cell<u32>,
 }
 
 impl<'a> Decoder<'a> {
@@ -440,7 +1565,12 @@ impl<'a> Decoder<'a> {
     assert!(stream.is_input());
     let fmt = stream.format();
     let codec = Codec::new(fmt, true)?;
-    Ok(Self { codec, stream })
+    Ok(Self {
+      codec,
+      stream,
+      cdef_applied: Cell::new(false),
+      reduce: Cell::new(0),
+    })
   }
 
   #[cfg(feature = "strict-mode")]
@@ -461,14 +1591,16 @@ impl<'a> Decoder<'a> {
   }
 
   pub(crate) fn setup(&self, params: &mut DecodeParameters) -> Result<()> {
+    params.validate()?;
+    self.reduce.set(params.params.cp_reduce);
     let res = unsafe { sys::opj_setup_decoder(self.as_ptr(), params.as_ptr()) == 1 };
     if res {
       self.set_strict_mode(params.strict)?;
       Ok(())
     } else {
-      Err(Error::CreateCodecError(format!(
-        "Failed to setup decoder with parameters."
-      )))
+      Err(Error::CreateCodecError(
+        "Failed to setup decoder with parameters.".to_string(),
+      ))
     }
   }
 
@@ -476,14 +1608,69 @@ impl<'a> Decoder<'a> {
     let mut img: *mut sys::opj_image_t = ptr::null_mut();
 
     let res = unsafe { sys::opj_read_header(self.stream.as_ptr(), self.as_ptr(), &mut img) };
-    // Try wrapping the image pointer before handling any errors.
+    // Wrap the image pointer before handling any errors.
     // Since the read header function might have allocated the image structure.
-    let img = Image::new(img)?;
-    if res == 1 {
-      Ok(img)
-    } else {
-      Err(Error::CodecError("Failed to read header".into()))
+    let mut img = match Image::new(img).ok() {
+      Some(img) if res == 1 => img,
+      _ => {
+        // openjpeg fails to read the header if `reduce` isn't less than the number of
+        // resolutions, check the `COD` marker for a better error.
+        let reduce = self.reduce.get();
+        let num_resolutions = self
+          .header_bytes()
+          .ok()
+          .and_then(|buf| read_cod_precincts(&buf))
+          .map(|precincts| precincts.len() as u32);
+        return Err(match num_resolutions {
+          Some(num_resolutions) if reduce >= num_resolutions => {
+            Error::InvalidParametersError(format!(
+              "reduce {reduce} must be less than the number of resolutions ({num_resolutions})"
+            ))
+          }
+          _ => Error::CodecError("Failed to read header".into()),
+        });
+      }
+    };
+    // Reject malformed headers early, before any decoding.
+    if img.num_components() == 0 {
+      return Err(Error::CodecError("image has no components".into()));
+    }
+    let raw = unsafe { &*img.as_ptr() };
+    if raw.x1 < raw.x0 || raw.y1 < raw.y0 {
+      return Err(Error::CodecError("invalid image extent".into()));
     }
+    // The JP2 header and the metadata boxes, read in one pass.
+    let boxes = self.stream.boxes(&[b"jp2h", b"xml ", b"asoc", b"uuid"]);
+    if let Some((_, jp2h)) = boxes.iter().find(|(box_type, _)| box_type == b"jp2h") {
+      let channel_defs = parse_channel_definitions(jp2h).unwrap_or_else(|err| {
+        log::warn!("Failed to read channel definitions: {err}");
+        None
+      });
+      img.set_channel_definitions(channel_defs);
+      let palette = parse_palette(jp2h).unwrap_or_else(|err| {
+        log::warn!("Failed to read palette: {err}");
+        None
+      });
+      // openjpeg only applies a palette with a component mapping (`cmap`) box.
+      let applied = find_box(jp2h, b"cmap").is_some();
+      img.set_palette(palette, applied);
+    }
+    let num_resolutions = self
+      .get_codestream_info()
+      .ok()
+      .and_then(|info| info.num_resolutions());
+    img.set_num_resolutions(num_resolutions.unwrap_or(0));
+    let mut xml_boxes = Vec::new();
+    let mut uuid_boxes = Vec::new();
+    for (box_type, content) in &boxes {
+      match box_type {
+        b"uuid" => uuid_boxes.extend(parse_uuid_box(content)),
+        _ => collect_xml_box(box_type, content, &mut xml_boxes),
+      }
+    }
+    img.set_xml_boxes(xml_boxes);
+    img.set_uuid_boxes(uuid_boxes);
+    Ok(img)
   }
 
   pub(crate) fn get_codestream_index(&self) -> Result<CodestreamIndex> {
@@ -492,72 +1679,272 @@ impl<'a> Decoder<'a> {
     Ok(CodestreamIndex(index))
   }
 
+  /// The start of the stream, for parsing the main header.
+  pub(crate) fn header_bytes(&self) -> Result<std::borrow::Cow<'_, [u8]>> {
+    self.stream.header_bytes()
+  }
+
   pub(crate) fn get_codestream_info(&self) -> Result<CodestreamInfo> {
     let info = ptr::NonNull::new(unsafe { sys::opj_get_cstr_info(self.as_ptr()) })
       .ok_or_else(|| Error::CodecError("Failed to get codestream info".into()))?;
     Ok(CodestreamInfo(info))
   }
 
+  /// Set the resolution factor after the header was read.  Overrides `cp_reduce`.
+  pub(crate) fn set_resolution_factor(&self, factor: u32) -> Result<()> {
+    let res = unsafe { sys::opj_set_decoded_resolution_factor(self.as_ptr(), factor) };
+    if res == 1 {
+      Ok(())
+    } else {
+      Err(Error::CodecError(format!(
+        "Failed to set resolution factor {factor}, it must be less than the number of resolutions"
+      )))
+    }
+  }
+
   pub(crate) fn set_decode_area(&self, img: &Image, params: &DecodeParameters) -> Result<()> {
     if let Some(area) = &params.area {
-      let res = unsafe {
-        sys::opj_set_decode_area(
-          self.as_ptr(),
-          img.as_ptr(),
-          area.start_x as i32,
-          area.start_y as i32,
-          area.end_x as i32,
-          area.end_y as i32,
-        )
-      };
-      if res != 1 {
-        return Err(Error::CreateCodecError(format!(
-          "Failed to set decode area."
-        )));
+      self.set_area(img, area)?;
+    }
+    Ok(())
+  }
+
+  /// Set the decode area of `img`, after clamping it to the image bounds.
+  pub(crate) fn set_area(&self, img: &Image, area: &DecodeArea) -> Result<()> {
+    let area = area.clamp_to(img)?;
+    let res = unsafe {
+      sys::opj_set_decode_area(
+        self.as_ptr(),
+        img.as_ptr(),
+        area.start_x as i32,
+        area.start_y as i32,
+        area.end_x as i32,
+        area.end_y as i32,
+      )
+    };
+    if res != 1 {
+      return Err(Error::CreateCodecError(
+        "Failed to set decode area.".to_string(),
+      ));
+    }
+    Ok(())
+  }
+
+  /// Decode the image tile by tile.  `check` is called before each tile, decoding stops
+  /// if it returns an error.
+  pub(crate) fn decode_tiles(
+    &self,
+    img: &mut Image,
+    mut check: impl FnMut() -> Result<()>,
+  ) -> Result<()> {
+    img.alloc_component_data()?;
+    let mut buf = Vec::new();
+    loop {
+      check()?;
+      match self.decode_next_tile(buf)? {
+        Some(tile) => {
+          img.copy_tile_data(&tile.data, (tile.x0, tile.y0, tile.x1, tile.y1))?;
+          buf = tile.data;
+        }
+        None => break,
       }
     }
+    self.end_decompress()?;
+    // openjpeg only applies the JP2 header (palette, channel definitions and ICC profile)
+    // in `opj_decode`.  The palette is expanded by `get_pixels`.
+    img.set_palette_applied(false);
+    if !img.has_palette() {
+      // With a palette the channel definitions refer to the palette columns.
+      img.apply_channel_definitions(false);
+    }
+    let boxes = self.stream.boxes(&[b"jp2h"]);
+    if let Some(icc) = boxes.first().and_then(|(_, jp2h)| parse_icc_profile(jp2h)) {
+      img.set_shared_icc_profile(icc.into());
+    }
     Ok(())
   }
 
-  pub(crate) fn decode(&self, img: &Image) -> Result<()> {
+  /// Decode the next tile, reusing `buf` for the tile data.  Returns `None` after the last
+  /// tile.
+  pub(crate) fn decode_next_tile(&self, mut buf: Vec<u8>) -> Result<Option<DecodedTile>> {
+    let mut tile_index = 0;
+    let mut data_size = 0;
+    let (mut x0, mut y0, mut x1, mut y1) = (0, 0, 0, 0);
+    let mut nb_comps = 0;
+    let mut should_go_on = 0;
     let res = unsafe {
-      sys::opj_decode(self.as_ptr(), self.stream.as_ptr(), img.as_ptr()) == 1
-        && sys::opj_end_decompress(self.as_ptr(), self.stream.as_ptr()) == 1
+      sys::opj_read_tile_header(
+        self.as_ptr(),
+        self.stream.as_ptr(),
+        &mut tile_index,
+        &mut data_size,
+        &mut x0,
+        &mut y0,
+        &mut x1,
+        &mut y1,
+        &mut nb_comps,
+        &mut should_go_on,
+      )
     };
-    if res {
+    if res != 1 {
+      return Err(Error::CodecError("Failed to read tile header".into()));
+    }
+    if should_go_on == 0 {
+      return Ok(None);
+    }
+    buf.resize(data_size as usize, 0u8);
+    let res = unsafe {
+      sys::opj_decode_tile_data(
+        self.as_ptr(),
+        tile_index,
+        buf.as_mut_ptr(),
+        data_size,
+        self.stream.as_ptr(),
+      )
+    };
+    if res != 1 {
+      return Err(Error::CodecError(format!(
+        "Failed to decode tile {tile_index}"
+      )));
+    }
+    Ok(Some(DecodedTile {
+      index: tile_index,
+      x0: x0 as u32,
+      y0: y0 as u32,
+      x1: x1 as u32,
+      y1: y1 as u32,
+      data: buf,
+    }))
+  }
+
+  /// Finish decoding, after the last tile.
+  pub(crate) fn end_decompress(&self) -> Result<()> {
+    let res = unsafe { sys::opj_end_decompress(self.as_ptr(), self.stream.as_ptr()) };
+    if res == 1 {
       Ok(())
     } else {
       Err(Error::CodecError("Failed to decode image".into()))
     }
   }
 
+  pub(crate) fn decode(&self, img: &Image) -> Result<()> {
+    let res = unsafe {
+      sys::opj_decode(self.as_ptr(), self.stream.as_ptr(), img.as_ptr()) == 1
+        && sys::opj_end_decompress(self.as_ptr(), self.stream.as_ptr()) == 1
+    };
+    if !res {
+      return Err(Error::CodecError("Failed to decode image".into()));
+    }
+    img.apply_channel_definitions(!self.cdef_applied.replace(true));
+    Ok(())
+  }
+
   pub(crate) fn as_ptr(&self) -> *mut sys::opj_codec_t {
     self.codec.as_ptr()
   }
 }
 
-#[cfg(feature = "file-io")]
 pub(crate) struct Encoder<'a> {
   codec: Codec,
   stream: Stream<'a>,
+  /// Tile grid `(tx0, ty0, tdx, tdy)`, set by `setup`.
+  tiles: Cell<(u32, u32, u32, u32)>,
+  /// Image area `(x0, y0, x1, y1)`, set by `setup`.
+  area: Cell<(u32, u32, u32, u32)>,
 }
 
-#[cfg(feature = "file-io")]
 impl<'a> Encoder<'a> {
   pub(crate) fn new(stream: Stream<'a>) -> Result<Self> {
     assert!(!stream.is_input());
     let fmt = stream.format();
     let codec = Codec::new(fmt, false)?;
-    Ok(Self { codec, stream })
+    Ok(Self {
+      codec,
+      stream,
+      tiles: Cell::new(Default::default()),
+      area: Cell::new(Default::default()),
+    })
   }
 
   pub(crate) fn setup(&self, mut params: EncodeParameters, img: &Image) -> Result<()> {
-    let res = unsafe { sys::opj_setup_encoder(self.as_ptr(), &mut params.0, img.as_ptr()) };
+    params.check_subsampling(img)?;
+    params.check_cinema(img)?;
+    params.check_fixed_allocation()?;
+    params.check_compression_ratios()?;
+    params.check_quality_layers()?;
+    params.check_code_block_size()?;
+    params.check_precincts()?;
+    params.check_roi(img)?;
+    params.clamp_resolutions(img)?;
+    params.check_mct(img);
+    // openjpeg copies the matrix and the comment in `opj_setup_encoder`.
+    if let Some(fixed) = &mut params.fixed_alloc {
+      params.params.cp_matrice = fixed.matrix.as_mut_ptr();
+    }
+    let comment = params.comment_cstring()?;
+    if let Some(comment) = &comment {
+      params.params.cp_comment = comment.as_ptr() as *mut _;
+    }
+    let res = unsafe { sys::opj_setup_encoder(self.as_ptr(), &mut params.params, img.as_ptr()) };
+    if res != 1 {
+      return Err(Error::CreateCodecError(
+        "Failed to setup encoder with parameters.".to_string(),
+      ));
+    }
+    // The tile grid, `opj_setup_encoder` can disable tiling (cinema profiles).
+    let p = &params.params;
+    let (x0, y0) = (img.x_offset(), img.y_offset());
+    let (x1, y1) = (x0 + img.orig_width(), y0 + img.orig_height());
+    let (tx0, ty0) = (p.cp_tx0 as u32, p.cp_ty0 as u32);
+    let (tdx, tdy) = if p.tile_size_on != 0 {
+      (p.cp_tdx as u32, p.cp_tdy as u32)
+    } else {
+      (x1.saturating_sub(tx0), y1.saturating_sub(ty0))
+    };
+    self.tiles.set((tx0, ty0, tdx, tdy));
+    self.area.set((x0, y0, x1, y1));
+    self.set_extra_options(&params)
+  }
+
+  /// Number of tiles `(columns, rows)`, after `setup`.
+  fn tile_grid(&self) -> (u32, u32) {
+    let (tx0, ty0, tdx, tdy) = self.tiles.get();
+    let (_, _, x1, y1) = self.area.get();
+    (
+      x1.saturating_sub(tx0).div_ceil(tdx.max(1)),
+      y1.saturating_sub(ty0).div_ceil(tdy.max(1)),
+    )
+  }
+
+  /// Number of tiles, after `setup`.
+  pub(crate) fn num_tiles(&self) -> u64 {
+    let (tw, th) = self.tile_grid();
+    tw as u64 * th as u64
+  }
+
+  /// Set the extra options, must be called after `opj_setup_encoder`.
+  fn set_extra_options(&self, params: &EncodeParameters) -> Result<()> {
+    let options = params.extra_options();
+    if options.is_empty() {
+      return Ok(());
+    }
+    if assert_min_version(2, 4).is_err() {
+      log::warn!(
+        "Extra encoder options {:?} need openjpeg 2.4 or newer, linked version: {}",
+        options,
+        openjpeg_version()
+      );
+      return Ok(());
+    }
+    let mut ptrs = options.iter().map(|o| o.as_ptr()).collect::<Vec<_>>();
+    ptrs.push(ptr::null());
+    let res = unsafe { sys::opj_encoder_set_extra_options(self.as_ptr(), ptrs.as_ptr()) };
     if res == 1 {
       Ok(())
     } else {
       Err(Error::CreateCodecError(format!(
-        "Failed to setup encoder with parameters."
+        "Failed to set extra encoder options: {:?}",
+        options
       )))
     }
   }
@@ -575,6 +1962,63 @@ impl<'a> Encoder<'a> {
     }
   }
 
+  /// Encode the samples of `src` tile by tile (`opj_write_tile`).
+  ///
+  /// `header` is the image given to `setup`, without component data.  openjpeg copies
+  /// each tile, so `src` isn't modified and only one tile is copied at a time.
+  pub(crate) fn encode_tiles(&self, header: &Image, src: &Image) -> Result<()> {
+    let (tx0, ty0, tdx, tdy) = self.tiles.get();
+    let (x0, y0, x1, y1) = self.area.get();
+    let (tw, th) = self.tile_grid();
+    let res =
+      unsafe { sys::opj_start_compress(self.as_ptr(), header.as_ptr(), self.stream.as_ptr()) };
+    if res != 1 {
+      return Err(Error::CodecError("Failed to encode image".into()));
+    }
+    let mut buf = Vec::new();
+    for (index, (q, p)) in (0..th)
+      .flat_map(|q| (0..tw).map(move |p| (q, p)))
+      .enumerate()
+    {
+      // Tile area, clipped to the image area.
+      let start =
+        |t: u32, i: u32, d: u32, min: u32| (t as u64 + i as u64 * d as u64).max(min as u64);
+      let end =
+        |t: u32, i: u32, d: u32, max: u32| (t as u64 + (i as u64 + 1) * d as u64).min(max as u64);
+      let tile = (
+        start(tx0, p, tdx, x0) as u32,
+        start(ty0, q, tdy, y0) as u32,
+        end(tx0, p, tdx, x1) as u32,
+        end(ty0, q, tdy, y1) as u32,
+      );
+      src.write_tile_data(&mut buf, tile)?;
+      let len =
+        u32::try_from(buf.len()).map_err(|_| Error::CodecError("Tile data is too large".into()))?;
+      let res = unsafe {
+        sys::opj_write_tile(
+          self.as_ptr(),
+          index as u32,
+          buf.as_mut_ptr(),
+          len,
+          self.stream.as_ptr(),
+        )
+      };
+      if res != 1 {
+        return Err(Error::CodecError(format!("Failed to encode tile {index}")));
+      }
+    }
+    let res = unsafe { sys::opj_end_compress(self.as_ptr(), self.stream.as_ptr()) };
+    if res != 1 {
+      return Err(Error::CodecError("Failed to encode image".into()));
+    }
+    Ok(())
+  }
+
+  /// Release the codec and return the output stream.
+  pub(crate) fn into_stream(self) -> Stream<'a> {
+    self.stream
+  }
+
   pub(crate) fn as_ptr(&self) -> *mut sys::opj_codec_t {
     self.codec.as_ptr()
   }
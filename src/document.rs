@@ -0,0 +1,114 @@
+use std::ops::Range;
+
+use super::*;
+
+/// A Jpeg 2000 file with one or more codestreams (pages).
+///
+/// Supported structures:
+/// * Raw J2K codestreams: one page.
+/// * JP2/JPX files with one or more top-level contiguous codestream (`jp2c`) boxes, one
+///   page per box.
+///
+/// The first page of a JP2/JPX file is decoded with the JP2 header (color space, ICC
+/// profile and channel definitions).  The other pages are decoded as raw codestreams.
+///
+/// JPM compound documents (`jpm ` brand) and codestreams in fragment tables (`ftbl`)
+/// aren't supported, `from_bytes` returns an error for them.
+///
+/// ```rust
+/// # use jpeg2k::*;
+/// # fn main() -> anyhow::Result<()> {
+/// // Append a second codestream to a JP2 file.
+/// let mut buf = std::fs::read("samples/file6.jp2")?;
+/// let j2k = std::fs::read("samples/p0_03.j2k")?;
+/// buf.extend_from_slice(&(j2k.len() as u32 + 8).to_be_bytes());
+/// buf.extend_from_slice(b"jp2c");
+/// buf.extend_from_slice(&j2k);
+///
+/// let doc = Jp2Document::from_bytes(&buf)?;
+/// assert_eq!(doc.num_pages(), 2);
+/// let pages = doc.pages().collect::<Result<Vec<_>, _>>()?;
+/// assert_eq!((pages[0].width(), pages[0].height()), (768, 512));
+/// assert_eq!((pages[1].width(), pages[1].height()), (256, 256));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Jp2Document<'a> {
+  buf: &'a [u8],
+  format: J2KFormat,
+  pages: Vec<Range<usize>>,
+}
+
+impl<'a> Jp2Document<'a> {
+  /// Find the pages of a Jpeg 2000 file.  It will detect the J2K format.
+  ///
+  /// The pages aren't decoded.
+  pub fn from_bytes(buf: &'a [u8]) -> Result<Self> {
+    let format = j2k_detect_format(buf)?;
+    if !format.is_container() {
+      return Ok(Self {
+        buf,
+        format,
+        pages: vec![Range {
+          start: 0,
+          end: buf.len(),
+        }],
+      });
+    }
+    if find_box(buf, b"ftyp").and_then(|ftyp| ftyp.get(0..4)) == Some(b"jpm ") {
+      return Err(Error::UnknownFormatError(
+        "JPM compound documents aren't supported".into(),
+      ));
+    }
+    let mut pages = Vec::new();
+    for (box_type, range) in BoxIter::new(buf) {
+      match &box_type {
+        b"jp2c" => pages.push(range),
+        b"ftbl" => {
+          return Err(Error::UnknownFormatError(
+            "Codestreams in fragment tables (ftbl) aren't supported".into(),
+          ));
+        }
+        _ => (),
+      }
+    }
+    if pages.is_empty() {
+      return Err(Error::CodecError(
+        "JP2 file has no codestream (jp2c) box".into(),
+      ));
+    }
+    Ok(Self { buf, format, pages })
+  }
+
+  /// Number of pages (codestreams).
+  pub fn num_pages(&self) -> usize {
+    self.pages.len()
+  }
+
+  /// Decode page `idx`.
+  pub fn page(&self, idx: usize) -> Result<Image> {
+    self.page_with(idx, DecodeParameters::default())
+  }
+
+  /// Decode page `idx` with the decode parameters.
+  pub fn page_with(&self, idx: usize, params: DecodeParameters) -> Result<Image> {
+    let range = self.pages.get(idx).ok_or_else(|| {
+      Error::InvalidParametersError(format!(
+        "page {idx}, the document has {} pages",
+        self.pages.len()
+      ))
+    })?;
+    if idx == 0 && self.format.is_container() {
+      // Decode the first page with the JP2 header.
+      Image::from_bytes_with(self.buf, params)
+    } else {
+      Image::from_bytes_with(&self.buf[range.clone()], params)
+    }
+  }
+
+  /// Decode the pages in order.
+  pub fn pages(&self) -> impl Iterator<Item = Result<Image>> + '_ {
+    (0..self.pages.len()).map(|idx| self.page(idx))
+  }
+}
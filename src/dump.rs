@@ -1,3 +1,5 @@
+use std::cell::{Cell, OnceCell};
+use std::ops::Range;
 #[cfg(feature = "file-io")]
 use std::path::Path;
 
@@ -6,10 +8,30 @@ use super::*;
 pub struct DumpImage<'a> {
   decoder: Decoder<'a>,
   pub img: Image,
+  /// Cached codestream info.
+  info: OnceCell<CodestreamInfo>,
+  /// The codestream was decoded, by `decode`, `decode_region` or `tiles`.
+  decoded: Cell<bool>,
 }
 
 impl<'a> DumpImage<'a> {
   /// Load a Jpeg 2000 image from bytes.  It will detect the J2K format.
+  ///
+  /// Only the header is read.  A header without components is an error.
+  ///
+  /// ```rust
+  /// # use jpeg2k::*;
+  /// # use jpeg2k::error::Error;
+  /// # fn main() -> anyhow::Result<()> {
+  /// let mut buf = std::fs::read("samples/p0_03.j2k")?;
+  /// // Patch `Csiz` (the number of components) of the SIZ marker to 0.
+  /// buf[40..42].copy_from_slice(&[0, 0]);
+  /// let err = DumpImage::from_bytes(&buf).err().unwrap();
+  /// assert!(matches!(err, Error::CodecError(_)), "{err}");
+  /// assert!(matches!(Image::from_bytes(&buf), Err(Error::CodecError(_))));
+  /// # Ok(())
+  /// # }
+  /// ```
   pub fn from_bytes(buf: &'a [u8]) -> Result<Self> {
     let stream = Stream::from_bytes(buf)?;
     Self::from_stream(stream, Default::default())
@@ -41,13 +63,132 @@ impl<'a> DumpImage<'a> {
 
     let img = decoder.read_header()?;
 
-    Ok(Self { decoder, img })
+    decoder.set_decode_area(&img, &params)?;
+
+    Ok(Self {
+      decoder,
+      img,
+      info: OnceCell::new(),
+      decoded: Cell::new(false),
+    })
+  }
+
+  /// Set the resolution factor (reduce) for the decode, after the header was read.
+  ///
+  /// This overrides [`DecodeParameters::reduce`]: the last value set is used.
+  /// It fails if `factor` isn't less than the number of resolutions.
+  ///
+  /// ```rust
+  /// # use jpeg2k::*;
+  /// # fn main() -> anyhow::Result<()> {
+  /// // `j2k32.j2k` is 256x256 with 6 resolutions.
+  /// let params = DecodeParameters::new().reduce(2);
+  /// let mut dump = DumpImage::from_file_with("samples/j2k32.j2k", params)?;
+  /// dump.set_resolution_factor(1)?;
+  /// dump.decode()?;
+  /// assert_eq!(dump.img.width(), 128);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn set_resolution_factor(&mut self, factor: u32) -> Result<()> {
+    self.decoder.set_resolution_factor(factor)?;
+    self.img.set_resolution_factor(factor);
+    Ok(())
   }
 
   pub fn decode(&self) -> Result<()> {
+    self.decoded.set(true);
     self.decoder.decode(&self.img)
   }
 
+  /// Decode only `area` of the image, using the header that was already read.
+  ///
+  /// The area is on the reference grid (full resolution), parts outside the image are
+  /// clamped and an area completely outside the image is an error.  Only the tiles that
+  /// intersect the area are decoded.  `dump.img` isn't changed, the region is returned as a
+  /// new image without the ICC profile.
+  ///
+  /// openjpeg keeps the decoded data of single tile images, so they can be decoded again
+  /// (other regions or the whole image).  Images with more than one tile can only be
+  /// decoded once, a second decode fails.
+  ///
+  /// ```rust
+  /// # use jpeg2k::*;
+  /// # fn main() -> anyhow::Result<()> {
+  /// // `p0_03.j2k` is 256x256 with 128x128 tiles.
+  /// let dump = DumpImage::from_file("samples/p0_03.j2k")?;
+  /// assert!(dump.decode_region(DecodeArea::new(300, 300, 400, 400)).is_err());
+  /// let region = dump.decode_region(DecodeArea::new(64, 64, 192, 160))?;
+  /// assert_eq!((region.width(), region.height()), (128, 96));
+  ///
+  /// let params = DecodeParameters::new().decode_area(Some(DecodeArea::new(64, 64, 192, 160)));
+  /// let img = Image::from_file_with("samples/p0_03.j2k", params)?;
+  /// assert_eq!(region.components()[0].data(), img.components()[0].data());
+  ///
+  /// // `file6.jp2` has one tile.
+  /// let dump = DumpImage::from_file("samples/file6.jp2")?;
+  /// let top = dump.decode_region(DecodeArea::new(0, 0, 768, 256))?;
+  /// let bottom = dump.decode_region(DecodeArea::new(0, 256, 768, 512))?;
+  /// assert_eq!((top.height(), bottom.height()), (256, 256));
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn decode_region(&self, area: DecodeArea) -> Result<Image> {
+    let img = self.img.header_copy()?;
+    self.decoder.set_area(&img, &area)?;
+    self.decoded.set(true);
+    self.decoder.decode(&img)?;
+    Ok(img)
+  }
+
+  /// Decode the image tile by tile.
+  ///
+  /// The returned [`TileDecoder`] decodes one tile per call to `next`, only the current
+  /// tile is kept in memory.  Use it to process huge images with bounded memory, instead
+  /// of decoding the whole image with [`decode`](Self::decode).  The decode area and
+  /// resolution factor of the decode parameters are used, tiles outside the decode area
+  /// are skipped (the tiles inside it are decoded whole).
+  ///
+  /// The codestream can only be decoded once: after `decode`, `decode_region` or a
+  /// previous call to `tiles`, the first item is an error.  `decode` fails after `tiles`.
+  ///
+  /// ```rust
+  /// # use jpeg2k::*;
+  /// # fn main() -> anyhow::Result<()> {
+  /// // `p0_03.j2k` is 256x256 with 128x128 tiles and 1 signed 4bit component.
+  /// let dump = DumpImage::from_file("samples/p0_03.j2k")?;
+  /// let img = Image::from_file("samples/p0_03.j2k")?;
+  /// let samples = img.components()[0].data();
+  /// for tile in dump.tiles() {
+  ///   let tile = tile?;
+  ///   assert_eq!((tile.x1 - tile.x0, tile.y1 - tile.y0), (128, 128));
+  ///   assert_eq!(tile.index, tile.y0 / 128 * 2 + tile.x0 / 128);
+  ///   // The first row of the tile.
+  ///   let row = &samples[(tile.y0 * 256 + tile.x0) as usize..][..128];
+  ///   assert!(tile.data[..128].iter().zip(row).all(|(&t, &s)| t as i8 as i32 == s));
+  /// }
+  /// assert!(dump.tiles().next().unwrap().is_err());
+  ///
+  /// // Only the tiles in the decode area.
+  /// let params = DecodeParameters::new().decode_area(Some(DecodeArea::new(140, 0, 200, 100)));
+  /// let dump = DumpImage::from_file_with("samples/p0_03.j2k", params)?;
+  /// let tiles = dump.tiles().collect::<Result<Vec<_>, _>>()?;
+  /// assert_eq!(tiles.iter().map(|tile| tile.index).collect::<Vec<_>>(), [1]);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn tiles(&self) -> TileDecoder<'_, 'a> {
+    let error = self
+      .decoded
+      .replace(true)
+      .then(|| Error::CodecError("The image was already decoded".into()));
+    TileDecoder {
+      decoder: &self.decoder,
+      error,
+      done: false,
+    }
+  }
+
   pub fn get_codestream_index(&self) -> Result<CodestreamIndex> {
     self.decoder.get_codestream_index()
   }
@@ -55,4 +196,213 @@ impl<'a> DumpImage<'a> {
   pub fn get_codestream_info(&self) -> Result<CodestreamInfo> {
     self.decoder.get_codestream_info()
   }
+
+  /// The codestream info, only queried from openjpeg once.
+  fn codestream_info(&self) -> Result<&CodestreamInfo> {
+    if let Some(info) = self.info.get() {
+      return Ok(info);
+    }
+    let info = self.decoder.get_codestream_info()?;
+    Ok(self.info.get_or_init(|| info))
+  }
+
+  /// Number of tiles.
+  ///
+  /// The codestream info is cached, so this is cheap to call repeatedly.
+  ///
+  /// ```rust
+  /// # use jpeg2k::*;
+  /// # fn main() -> anyhow::Result<()> {
+  /// // `p0_03.j2k` is 256x256 with 128x128 tiles.
+  /// let dump = DumpImage::from_file("samples/p0_03.j2k")?;
+  /// assert_eq!(dump.num_tiles()?, 4);
+  /// assert_eq!(dump.tile_size()?, (128, 128));
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn num_tiles(&self) -> Result<u32> {
+    Ok(self.codestream_info()?.num_tiles())
+  }
+
+  /// Nominal tile size `(width, height)`.  Tiles on the right and bottom edges can
+  /// be smaller.
+  pub fn tile_size(&self) -> Result<(u32, u32)> {
+    Ok(self.codestream_info()?.tile_size())
+  }
+
+  /// Check the codestream parameters against the constraints of `profile`.
+  ///
+  /// Returns the parameters that don't conform, an empty vec for compliant files.
+  /// Supports the Part 1 profiles (`Profile0`, `Profile1`) and digital cinema
+  /// (`Cinema2K`, `Cinema4K`), other profiles return an error.  Only the main header
+  /// is checked (default coding parameters), not tile-part headers.  An error is returned
+  /// if the main header can't be read.
+  ///
+  /// ```rust
+  /// # use jpeg2k::*;
+  /// # fn main() -> anyhow::Result<()> {
+  /// let dump = DumpImage::from_file("samples/p0_03.j2k")?;
+  /// assert_eq!(dump.check_profile(Rsiz::Profile0)?, []);
+  ///
+  /// let issues = dump.check_profile(Rsiz::Cinema2K)?;
+  /// assert!(issues.iter().any(|i| i.to_string() == "progression order must be CPRL, found PCRL"));
+  ///
+  /// // A file with a large box before the codestream.
+  /// let jp2 = std::fs::read("samples/file6.jp2")?;
+  /// let jp2c = jp2.windows(4).position(|w| w == b"jp2c").unwrap() - 4;
+  /// let xml = [&70_008u32.to_be_bytes()[..], b"xml ", &[b' '; 70_000]].concat();
+  /// let buf = [&jp2[..jp2c], &xml, &jp2[jp2c..]].concat();
+  /// let issues = DumpImage::from_bytes(&buf)?.check_profile(Rsiz::Profile1)?;
+  ///
+  /// let path = std::env::temp_dir().join(format!("jpeg2k-profile-{}.jp2", std::process::id()));
+  /// std::fs::write(&path, &buf)?;
+  /// let res = DumpImage::from_file(&path).and_then(|dump| dump.check_profile(Rsiz::Profile1));
+  /// std::fs::remove_file(&path)?;
+  /// assert_eq!(res?, issues);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn check_profile(&self, profile: Rsiz) -> Result<Vec<ComplianceIssue>> {
+    let header = self.decoder.header_bytes()?;
+    check_profile(profile, &self.img, self.codestream_info()?, &header)
+  }
+
+  /// Byte range of each quality layer in the file.
+  ///
+  /// The range of layer `n` covers the tile-parts of layer `n` in all tiles, so a client
+  /// needs the bytes up to the end of layer `n` to decode `n + 1` layers.  This needs
+  /// codestreams with one tile-part per layer in each tile (tile-parts split by layer),
+  /// otherwise an error is returned.  openjpeg doesn't provide a packet index.
+  ///
+  /// ```rust
+  /// # use jpeg2k::*;
+  /// # fn main() -> anyhow::Result<()> {
+  /// let dump = DumpImage::from_file("samples/file6.jp2")?;
+  /// dump.decode()?;
+  /// assert_eq!(dump.layer_byte_ranges()?, [169..394540]);
+  ///
+  /// // `p0_03.j2k` has 8 layers in 1 tile-part per tile.
+  /// let dump = DumpImage::from_file("samples/p0_03.j2k")?;
+  /// dump.decode()?;
+  /// assert!(dump.layer_byte_ranges().is_err());
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn layer_byte_ranges(&self) -> Result<Vec<Range<u64>>> {
+    let num_layers = self.codestream_info()?.num_layers();
+    self.get_codestream_index()?.layer_byte_ranges(num_layers)
+  }
+
+  /// Is the image encoded losslessly.
+  ///
+  /// Returns `Some(true)` if all components use the reversible 5/3 wavelet (lossless),
+  /// `Some(false)` if the irreversible 9/7 wavelet (lossy) is used, or `None` if the
+  /// codestream info isn't available.
+  pub fn is_reversible(&self) -> Option<bool> {
+    self.codestream_info().ok()?.is_reversible()
+  }
+
+  /// Estimate the memory needed to decode the image at resolution factor `reduce`, in bytes.
+  ///
+  /// Only the header is used, nothing is decoded.  Use it to limit the number of
+  /// concurrent decodes to a memory budget.
+  ///
+  /// Assumptions:
+  /// * openjpeg stores each sample as an `i32` (4 bytes), the image buffers need
+  ///   `width * height * 4` bytes per component at the reduced resolution.
+  /// * The tile being decoded needs its own buffer, one tile's samples are added.
+  /// * Code-blocks, precincts and the compressed data add about 1/8 more.
+  ///
+  /// The estimate doesn't include the compressed file itself or converting the decoded
+  /// image to pixels (`get_pixels`, `to_dynamic_image`).
+  ///
+  /// ```rust
+  /// # use jpeg2k::*;
+  /// # fn main() -> anyhow::Result<()> {
+  /// // `p0_03.j2k` is 256x256 with 1 component.
+  /// let dump = DumpImage::from_file("samples/p0_03.j2k")?;
+  /// let full = dump.estimated_decode_bytes(0);
+  /// assert!(full >= 256 * 256 * 4);
+  /// assert!(dump.estimated_decode_bytes(1) < full / 3);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn estimated_decode_bytes(&self, reduce: u32) -> u64 {
+    const BYTES_PER_SAMPLE: u64 = std::mem::size_of::<i32>() as u64;
+    let reduce = reduce.min(32);
+    let scale = |v: u32, d: u32| (v.div_ceil(d) as u64).div_ceil(1 << reduce);
+    let (x0, y0) = (self.img.x_offset(), self.img.y_offset());
+    let (x1, y1) = (x0 + self.img.orig_width(), y0 + self.img.orig_height());
+    let (tdx, tdy) = self
+      .codestream_info()
+      .map(|info| info.tile_size())
+      .unwrap_or((x1 - x0, y1 - y0));
+    let (tdx, tdy) = (tdx.min(x1 - x0), tdy.min(y1 - y0));
+    let samples = self
+      .img
+      .components()
+      .iter()
+      .map(|comp| {
+        let (dx, dy) = comp.subsampling();
+        let image = (scale(x1, dx) - scale(x0, dx)) * (scale(y1, dy) - scale(y0, dy));
+        let tile = scale(tdx, dx) * scale(tdy, dy);
+        image + tile
+      })
+      .sum::<u64>();
+    let bytes = samples * BYTES_PER_SAMPLE;
+    bytes + bytes / 8
+  }
+}
+
+/// A decoded tile, from [`TileDecoder`].
+#[derive(Debug, Clone)]
+pub struct DecodedTile {
+  /// Tile index, in raster order.
+  pub index: u32,
+  /// Tile area on the reference grid (full resolution).
+  pub x0: u32,
+  pub y0: u32,
+  pub x1: u32,
+  pub y1: u32,
+  /// The samples of the tile, as decoded by openjpeg.
+  ///
+  /// The components are stored one after the other, each with the whole tile at the
+  /// decoded resolution (subsampled components are smaller).  Samples use 1, 2 or 4
+  /// bytes, in native endian, for precisions up to 8, up to 16 and above 16 bits.
+  pub data: Vec<u8>,
+}
+
+/// Decode an image tile by tile, see [`DumpImage::tiles`].
+///
+/// Iterates over the decoded tiles.  Decoding stops after the first error.
+pub struct TileDecoder<'d, 'a> {
+  decoder: &'d Decoder<'a>,
+  /// Error returned by the first call to `next`.
+  error: Option<Error>,
+  done: bool,
+}
+
+impl Iterator for TileDecoder<'_, '_> {
+  type Item = Result<DecodedTile>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.done {
+      return None;
+    }
+    if let Some(err) = self.error.take() {
+      self.done = true;
+      return Some(Err(err));
+    }
+    match self.decoder.decode_next_tile(Vec::new()).transpose() {
+      Some(Ok(tile)) => Some(Ok(tile)),
+      Some(Err(err)) => {
+        self.done = true;
+        Some(Err(err))
+      }
+      None => {
+        self.done = true;
+        self.decoder.end_decompress().err().map(Err)
+      }
+    }
+  }
 }
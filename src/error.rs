@@ -7,6 +7,15 @@ pub enum Error {
   #[error("Unsupported components")]
   UnsupportedComponentsError(u32),
 
+  #[error("Unsupported precision: {0}")]
+  UnsupportedPrecisionError(u32),
+
+  #[error("Invalid parameters: {0}")]
+  InvalidParametersError(String),
+
+  #[error("Invalid component: {0}")]
+  InvalidComponentError(String),
+
   #[error("Unsupported color space: {0:?}")]
   UnsupportedColorSpaceError(ColorSpace),
 
@@ -25,6 +34,9 @@ pub enum Error {
   #[error("Bad filename: {0}")]
   BadFilenameError(String),
 
+  #[error("Unsupported openjpeg version: {0}")]
+  UnsupportedVersionError(String),
+
   #[error("Null pointer from openjpeg-sys")]
   NullPointerError(&'static str),
 
@@ -32,4 +44,16 @@ pub enum Error {
   Other(#[from] anyhow::Error),
 }
 
+/// Error from [`Image::from_bytes_diagnostic`](crate::Image::from_bytes_diagnostic), with the layer that failed.
+#[derive(Error, Debug)]
+pub enum LayeredError {
+  /// The file format wasn't detected or the JP2 container (boxes) is invalid.
+  #[error("Invalid container: {0}")]
+  Container(Error),
+
+  /// The J2K codestream is invalid or failed to decode.
+  #[error("Invalid codestream: {0}")]
+  Codestream(Error),
+}
+
 pub type Result<T, E = Error> = std::result::Result<T, E>;
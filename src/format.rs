@@ -1,23 +1,157 @@
+use std::ffi::OsStr;
+use std::ops::Range;
+
 use super::*;
 
 /// Magic bytes for JP2 RFC3745.
-pub const JP2_RFC3745_MAGIC: &'static [u8] = &[
+pub const JP2_RFC3745_MAGIC: &[u8] = &[
   0x00, 0x00, 0x00, 0x0c, 0x6a, 0x50, 0x20, 0x20, 0x0d, 0x0a, 0x87, 0x0a,
 ];
 /// Magic bytes for J2K Codestream.
-pub const J2K_CODESTREAM_MAGIC: &'static [u8] = &[0xff, 0x4f, 0xff, 0x51];
+pub const J2K_CODESTREAM_MAGIC: &[u8] = &[0xff, 0x4f, 0xff, 0x51];
 
-/// Supported Jpeg 2000 formats.
-#[derive(Debug, Clone, Copy)]
+/// Jpeg 2000 formats.
+///
+/// openjpeg only implements the `JP2` and `J2K` codecs, the other formats are
+/// recognized but can't be decoded or encoded.  Use [`J2KFormat::is_decode_supported`]
+/// to check at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum J2KFormat {
   JP2,
   J2K,
+  /// JPX (JPEG 2000 Part 2) container that isn't JP2 compatible.
+  JPX,
+  /// JPIP precinct data-bin stream.
+  JPP,
+  /// JPIP tile data-bin stream.
+  JPT,
+}
+
+impl J2KFormat {
+  /// Get the format from a file extension (case-insensitive).
+  ///
+  /// Raw codestreams: `j2k`, `j2c`, `jpc`.
+  /// JP2 containers: `jp2`, `jpx`, `jpf`.
+  /// JPIP streams: `jpp`, `jpt`.
+  ///
+  /// `jpx` files are usually JP2 compatible, so they map to `JP2`.
+  pub fn from_extension(ext: &OsStr) -> Option<J2KFormat> {
+    let ext = ext.to_str()?.to_ascii_lowercase();
+    match ext.as_str() {
+      "jp2" | "jpx" | "jpf" => Some(J2KFormat::JP2),
+      "j2k" | "j2c" | "jpc" => Some(J2KFormat::J2K),
+      "jpp" => Some(J2KFormat::JPP),
+      "jpt" => Some(J2KFormat::JPT),
+      _ => None,
+    }
+  }
+
+  /// The default file extension for this format.
+  pub fn default_extension(&self) -> &'static str {
+    match self {
+      J2KFormat::JP2 => "jp2",
+      J2KFormat::J2K => "j2k",
+      J2KFormat::JPX => "jpx",
+      J2KFormat::JPP => "jpp",
+      J2KFormat::JPT => "jpt",
+    }
+  }
+
+  /// Can openjpeg decode this format.  Checked at runtime by creating a decoder.
+  ///
+  /// ```
+  /// # use jpeg2k::format::J2KFormat;
+  /// assert!(J2KFormat::JP2.is_decode_supported());
+  /// assert!(J2KFormat::J2K.is_decode_supported());
+  /// assert!(!J2KFormat::JPX.is_decode_supported());
+  /// ```
+  pub fn is_decode_supported(&self) -> bool {
+    unsafe {
+      let codec = sys::opj_create_decompress((*self).into());
+      if codec.is_null() {
+        return false;
+      }
+      sys::opj_destroy_codec(codec);
+    }
+    true
+  }
+
+  /// Can openjpeg encode this format.  Checked at runtime by creating an encoder.
+  pub fn is_encode_supported(&self) -> bool {
+    unsafe {
+      let codec = sys::opj_create_compress((*self).into());
+      if codec.is_null() {
+        return false;
+      }
+      sys::opj_destroy_codec(codec);
+    }
+    true
+  }
+
+  /// Is this format a JP2 family box container.
+  pub(crate) fn is_container(&self) -> bool {
+    matches!(self, J2KFormat::JP2 | J2KFormat::JPX)
+  }
+
+  /// The format to try when decoding fails: a raw codestream for containers, JP2 for raw
+  /// codestreams.
+  #[cfg(feature = "file-io")]
+  pub(crate) fn fallback(&self) -> Option<J2KFormat> {
+    match self {
+      J2KFormat::J2K => Some(J2KFormat::JP2),
+      J2KFormat::JP2 | J2KFormat::JPX => Some(J2KFormat::J2K),
+      J2KFormat::JPP | J2KFormat::JPT => None,
+    }
+  }
+}
+
+/// Is the JP2 file a JPX file that doesn't list `jp2 ` as a compatible brand.
+fn is_jpx_only(buf: &[u8]) -> bool {
+  // The File Type box follows the signature box.
+  let ftyp = &buf[JP2_RFC3745_MAGIC.len()..];
+  let len = match read_u32(ftyp, 0) {
+    Some(len) if ftyp.get(4..8) == Some(b"ftyp") => len as usize,
+    _ => return false,
+  };
+  let ftyp = &ftyp[..len.min(ftyp.len())];
+  if ftyp.get(8..12) != Some(b"jpx ") {
+    return false;
+  }
+  // Compatibility list after the brand and minor version.
+  !ftyp
+    .get(16..)
+    .unwrap_or_default()
+    .chunks_exact(4)
+    .any(|brand| brand == b"jp2 ")
 }
 
 /// Detect Jpeg 2000 format from magic bytes.
+///
+/// JPX files that aren't JP2 compatible are detected as `JPX`.
+///
+/// ```
+/// # use jpeg2k::*;
+/// # use jpeg2k::format::{j2k_detect_format, J2KFormat};
+/// # fn main() -> anyhow::Result<()> {
+/// let mut buf = std::fs::read("samples/file6.jp2")?;
+/// assert_eq!(j2k_detect_format(&buf)?, J2KFormat::JP2);
+///
+/// // Change the File Type box brand and compatibility list from `jp2 ` to `jpx `.
+/// buf[20..24].copy_from_slice(b"jpx ");
+/// buf[32..36].copy_from_slice(b"jpx ");
+/// assert_eq!(j2k_detect_format(&buf)?, J2KFormat::JPX);
+/// let err = Image::from_bytes(&buf).unwrap_err();
+/// assert!(err.to_string().contains("JPX"), "{err}");
+/// # Ok(())
+/// # }
+/// ```
 pub fn j2k_detect_format(buf: &[u8]) -> Result<J2KFormat> {
   if buf.starts_with(JP2_RFC3745_MAGIC) {
-    Ok(J2KFormat::JP2)
+    if is_jpx_only(buf) {
+      Ok(J2KFormat::JPX)
+    } else {
+      Ok(J2KFormat::JP2)
+    }
   } else if buf.starts_with(J2K_CODESTREAM_MAGIC) {
     Ok(J2KFormat::J2K)
   } else {
@@ -28,15 +162,515 @@ pub fn j2k_detect_format(buf: &[u8]) -> Result<J2KFormat> {
 }
 
 /// Detect Jpeg 2000 format from file extension.
-pub fn j2k_detect_format_from_extension(ext: Option<&std::ffi::OsStr>) -> Result<J2KFormat> {
-  let lower_ext = ext.and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase());
-  match lower_ext.as_ref().map(|s| s.as_str()) {
-    Some("jp2") => Ok(J2KFormat::JP2),
-    Some("j2k") | Some("j2c") | Some("jpc") => Ok(J2KFormat::J2K),
-    Some(ext) => Err(Error::UnknownFormatError(format!(
-      "Unknown file extension: {}",
-      ext
-    ))),
-    None => Err(Error::UnknownFormatError("No file extension".into())),
+pub fn j2k_detect_format_from_extension(ext: Option<&OsStr>) -> Result<J2KFormat> {
+  let ext = ext.ok_or_else(|| Error::UnknownFormatError("No file extension".into()))?;
+  J2KFormat::from_extension(ext).ok_or_else(|| {
+    Error::UnknownFormatError(format!("Unknown file extension: {}", ext.to_string_lossy()))
+  })
+}
+
+/// Image size and tiling from the `SIZ` marker of the main header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizInfo {
+  /// Image width (`Xsiz - XOsiz`).
+  pub width: u32,
+  /// Image height (`Ysiz - YOsiz`).
+  pub height: u32,
+  /// Number of components.
+  pub num_components: u32,
+  /// Precision (bit depth) of each component.
+  pub precisions: Vec<u32>,
+  /// Nominal tile size `(XTsiz, YTsiz)`.
+  pub tile_dims: (u32, u32),
+  /// Capabilities (profile) of the codestream.
+  pub rsiz: Rsiz,
+}
+
+fn siz_error(msg: &str) -> Error {
+  Error::CodecError(format!("Invalid SIZ marker: {msg}"))
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> Option<u16> {
+  let bytes = buf.get(offset..offset + 2)?;
+  Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Option<u32> {
+  let bytes = buf.get(offset..offset + 4)?;
+  Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Iterator over a sequence of JP2 boxes.  Yields the box type and the range of the
+/// box contents.  Stops at the first malformed box.
+pub(crate) struct BoxIter<'b> {
+  buf: &'b [u8],
+  offset: usize,
+}
+
+impl<'b> BoxIter<'b> {
+  pub(crate) fn new(buf: &'b [u8]) -> Self {
+    Self { buf, offset: 0 }
+  }
+}
+
+impl Iterator for BoxIter<'_> {
+  type Item = ([u8; 4], Range<usize>);
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let (buf, offset) = (self.buf, self.offset);
+    if offset + 8 > buf.len() {
+      return None;
+    }
+    let len = read_u32(buf, offset)? as u64;
+    let box_type = buf[offset + 4..offset + 8].try_into().ok()?;
+    let (header, len) = match len {
+      // The box extends to the end of the file.
+      0 => (8, (buf.len() - offset) as u64),
+      // 64bit box length.
+      1 => {
+        let high = read_u32(buf, offset + 8)? as u64;
+        let low = read_u32(buf, offset + 12)? as u64;
+        (16, (high << 32) | low)
+      }
+      len => (8, len),
+    };
+    if len < header as u64 {
+      self.offset = buf.len();
+      return None;
+    }
+    let end = offset.checked_add(usize::try_from(len).ok()?)?;
+    self.offset = end;
+    Some((
+      box_type,
+      (offset + header).min(buf.len())..end.min(buf.len()),
+    ))
+  }
+}
+
+/// Find a box by type in a sequence of JP2 boxes and return its contents.
+pub(crate) fn find_box<'b>(buf: &'b [u8], find_type: &[u8; 4]) -> Option<&'b [u8]> {
+  BoxIter::new(buf)
+    .find(|(box_type, _)| box_type == find_type)
+    .map(|(_, range)| &buf[range])
+}
+
+/// Find the contiguous codestream (`jp2c` box) in a JP2 file.
+pub(crate) fn find_jp2_codestream(buf: &[u8]) -> Option<&[u8]> {
+  find_box(buf, b"jp2c")
+}
+
+/// Read the top-level boxes with a type in `box_types` from a file.
+///
+/// The contents of the other boxes are skipped, not read.  Reading stops at the first
+/// malformed box, the boxes before it are returned.
+#[cfg(feature = "file-io")]
+pub(crate) fn read_file_boxes(
+  path: &std::path::Path,
+  box_types: &[&[u8; 4]],
+) -> Vec<([u8; 4], Vec<u8>)> {
+  let mut boxes = Vec::new();
+  read_file_boxes_into(path, box_types, &mut boxes);
+  boxes
+}
+
+#[cfg(feature = "file-io")]
+fn read_file_boxes_into(
+  path: &std::path::Path,
+  box_types: &[&[u8; 4]],
+  boxes: &mut Vec<([u8; 4], Vec<u8>)>,
+) -> Option<()> {
+  use std::io::{Read, Seek, SeekFrom};
+
+  let mut file = std::fs::File::open(path).ok()?;
+  while let Some((box_type, content_len)) = read_file_box_header(&mut file) {
+    if box_types.contains(&&box_type) {
+      let mut content = Vec::new();
+      (&mut file)
+        .take(content_len.unwrap_or(u64::MAX))
+        .read_to_end(&mut content)
+        .ok()?;
+      boxes.push((box_type, content));
+    } else if let Some(content_len) = content_len {
+      file
+        .seek(SeekFrom::Current(i64::try_from(content_len).ok()?))
+        .ok()?;
+    }
+    // The box extends to the end of the file.
+    if content_len.is_none() {
+      break;
+    }
+  }
+  Some(())
+}
+
+/// Read the header of the next box of a file.  Returns the box type and the length of
+/// the box contents, `None` if the box extends to the end of the file.
+#[cfg(feature = "file-io")]
+fn read_file_box_header(file: &mut impl std::io::Read) -> Option<([u8; 4], Option<u64>)> {
+  let mut header = [0u8; 8];
+  file.read_exact(&mut header).ok()?;
+  let box_type: [u8; 4] = header[4..8].try_into().ok()?;
+  let content_len = match read_u32(&header, 0)? {
+    0 => None,
+    1 => {
+      let mut large = [0u8; 8];
+      file.read_exact(&mut large).ok()?;
+      Some(u64::from_be_bytes(large).checked_sub(16)?)
+    }
+    len => Some((len as u64).checked_sub(8)?),
+  };
+  Some((box_type, content_len))
+}
+
+/// Read the main header of a J2K or JP2 file: the marker segments from the SOC marker to
+/// the first SOT marker.
+///
+/// For JP2 files the boxes before the codestream (`jp2c`) box are skipped, not read, and
+/// the main header is returned without the box header.
+#[cfg(feature = "file-io")]
+pub(crate) fn read_file_main_header(
+  path: &std::path::Path,
+  container: bool,
+) -> std::io::Result<Vec<u8>> {
+  use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom};
+
+  let mut file = BufReader::new(std::fs::File::open(path)?);
+  if container {
+    loop {
+      match read_file_box_header(&mut file) {
+        Some((box_type, _)) if &box_type == b"jp2c" => break,
+        Some((_, Some(content_len))) => {
+          let offset = i64::try_from(content_len).map_err(|_| ErrorKind::InvalidData)?;
+          file.seek(SeekFrom::Current(offset))?;
+        }
+        _ => {
+          let msg = "no codestream (jp2c) box";
+          return Err(std::io::Error::new(ErrorKind::InvalidData, msg));
+        }
+      }
+    }
+  }
+  let mut header = vec![0u8; 2];
+  file.read_exact(&mut header)?;
+  loop {
+    let mut marker = [0u8; 4];
+    if file.read_exact(&mut marker).is_err() || marker[..2] == [0xff, 0x90] {
+      return Ok(header);
+    }
+    header.extend_from_slice(&marker);
+    let len = u16::from_be_bytes([marker[2], marker[3]]).saturating_sub(2);
+    (&mut file).take(len as u64).read_to_end(&mut header)?;
+  }
+}
+
+/// Collect the contents of an `xml ` box, or of the `xml ` boxes nested in an association
+/// (`asoc`) box.
+pub(crate) fn collect_xml_box(box_type: &[u8; 4], content: &[u8], xml: &mut Vec<Vec<u8>>) {
+  match box_type {
+    b"xml " => xml.push(content.to_vec()),
+    b"asoc" => {
+      for (box_type, range) in BoxIter::new(content) {
+        collect_xml_box(&box_type, &content[range], xml);
+      }
+    }
+    _ => (),
+  }
+}
+
+/// Read the contents of the XML (`xml `) boxes from a JP2 file without decoding the image.
+///
+/// Top-level boxes and boxes nested in association (`asoc`) boxes (as used by GMLJP2) are
+/// returned in file order.  Returns an empty vec for raw J2K codestreams.
+///
+/// ```
+/// # fn main() -> anyhow::Result<()> {
+/// let buf = std::fs::read("samples/file6.jp2")?;
+/// assert!(jpeg2k::format::read_xml_boxes(&buf).is_empty());
+/// # Ok(())
+/// # }
+/// ```
+pub fn read_xml_boxes(buf: &[u8]) -> Vec<Vec<u8>> {
+  let mut xml = Vec::new();
+  if matches!(j2k_detect_format(buf), Ok(format) if format.is_container()) {
+    for (box_type, range) in BoxIter::new(buf) {
+      collect_xml_box(&box_type, &buf[range], &mut xml);
+    }
+  }
+  xml
+}
+
+/// A UUID, as used by the JP2 `uuid` boxes.
+pub type Uuid = [u8; 16];
+
+/// Split the contents of a `uuid` box into the UUID and the data.
+pub(crate) fn parse_uuid_box(content: &[u8]) -> Option<(Uuid, Vec<u8>)> {
+  let uuid = content.get(..16)?.try_into().ok()?;
+  Some((uuid, content[16..].to_vec()))
+}
+
+/// Read the top-level UUID (`uuid`) boxes from a JP2 file without decoding the image.
+///
+/// Returns the UUID and the data of each box, in file order.  Returns an empty vec for
+/// raw J2K codestreams.
+///
+/// ```
+/// # fn main() -> anyhow::Result<()> {
+/// let buf = std::fs::read("samples/file6.jp2")?;
+/// assert!(jpeg2k::format::read_uuid_boxes(&buf).is_empty());
+/// # Ok(())
+/// # }
+/// ```
+pub fn read_uuid_boxes(buf: &[u8]) -> Vec<(Uuid, Vec<u8>)> {
+  if !matches!(j2k_detect_format(buf), Ok(format) if format.is_container()) {
+    return Vec::new();
+  }
+  BoxIter::new(buf)
+    .filter(|(box_type, _)| box_type == b"uuid")
+    .filter_map(|(_, range)| parse_uuid_box(&buf[range]))
+    .collect()
+}
+
+/// Channel type from the JP2 Channel Definition (`cdef`) box.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelType {
+  /// Color channel.
+  Color,
+  /// Opacity (alpha) channel.
+  Opacity,
+  /// Premultiplied opacity channel.
+  PremultOpacity,
+  /// Unspecified channel type (`65535`) or a reserved value.
+  Unspecified(u16),
+}
+
+impl From<u16> for ChannelType {
+  fn from(typ: u16) -> Self {
+    match typ {
+      0 => Self::Color,
+      1 => Self::Opacity,
+      2 => Self::PremultOpacity,
+      typ => Self::Unspecified(typ),
+    }
+  }
+}
+
+/// A channel definition from the JP2 Channel Definition (`cdef`) box.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelDef {
+  /// Channel index in the codestream (after any palette is applied).
+  pub channel: u16,
+  /// Channel type.
+  pub type_: ChannelType,
+  /// The color this channel is associated with, starting at `1`.  `0` means the whole
+  /// image and `65535` means no association.
+  pub association: u16,
+}
+
+/// The ICC profile of the colour specification (`colr`) box from the contents of a JP2
+/// header (`jp2h`) box.
+///
+/// Like openjpeg, only the first `colr` box is used and only the restricted ICC method
+/// (`METH` 2) has a profile.
+pub(crate) fn parse_icc_profile(jp2h: &[u8]) -> Option<&[u8]> {
+  let colr = find_box(jp2h, b"colr")?;
+  match colr.first()? {
+    2 => colr.get(3..).filter(|icc| !icc.is_empty()),
+    _ => None,
+  }
+}
+
+/// Parse the `cdef` box from the contents of a JP2 header (`jp2h`) box.
+pub(crate) fn parse_channel_definitions(jp2h: &[u8]) -> Result<Option<Vec<ChannelDef>>> {
+  let cdef_error = |msg: &str| Error::CodecError(format!("Invalid cdef box: {msg}"));
+  let cdef = match find_box(jp2h, b"cdef") {
+    Some(cdef) => cdef,
+    None => return Ok(None),
+  };
+  let num = read_u16(cdef, 0).ok_or_else(|| cdef_error("truncated"))? as usize;
+  (0..num)
+    .map(|idx| {
+      let field =
+        |n: usize| read_u16(cdef, 2 + idx * 6 + n * 2).ok_or_else(|| cdef_error("truncated"));
+      Ok(ChannelDef {
+        channel: field(0)?,
+        type_: field(1)?.into(),
+        association: field(2)?,
+      })
+    })
+    .collect::<Result<_>>()
+    .map(Some)
+}
+
+/// A palette from the JP2 Palette (`pclr`) box.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Palette {
+  /// Precision (bit depth) of each palette column.
+  pub precisions: Vec<u32>,
+  /// Signedness of each palette column.
+  pub signed: Vec<bool>,
+  /// The palette entries, one value for each column.
+  pub entries: Vec<Vec<i32>>,
+}
+
+/// Parse the `pclr` box from the contents of a JP2 header (`jp2h`) box.
+pub(crate) fn parse_palette(jp2h: &[u8]) -> Result<Option<Palette>> {
+  let pclr_error = || Error::CodecError("Invalid pclr box: truncated".into());
+  let pclr = match find_box(jp2h, b"pclr") {
+    Some(pclr) => pclr,
+    None => return Ok(None),
+  };
+  let num_entries = read_u16(pclr, 0).ok_or_else(pclr_error)? as usize;
+  let num_columns = *pclr.get(2).ok_or_else(pclr_error)? as usize;
+  let depths = pclr.get(3..3 + num_columns).ok_or_else(pclr_error)?;
+  let precisions: Vec<u32> = depths.iter().map(|b| (b & 0x7f) as u32 + 1).collect();
+  let signed: Vec<bool> = depths.iter().map(|b| b & 0x80 != 0).collect();
+  let mut offset = 3 + num_columns;
+  let entries = (0..num_entries)
+    .map(|_| {
+      precisions
+        .iter()
+        .zip(&signed)
+        .map(|(&precision, &signed)| {
+          let len = precision.div_ceil(8) as usize;
+          let bytes = pclr.get(offset..offset + len).ok_or_else(pclr_error)?;
+          offset += len;
+          let value = bytes.iter().fold(0u64, |v, &b| (v << 8) | b as u64);
+          // Sign extend the `precision` bits value.
+          let shift = 64 - precision.min(64);
+          Ok(if signed {
+            ((value << shift) as i64 >> shift) as i32
+          } else {
+            value as i32
+          })
+        })
+        .collect()
+    })
+    .collect::<Result<_>>()?;
+  Ok(Some(Palette {
+    precisions,
+    signed,
+    entries,
+  }))
+}
+
+/// Read the channel definitions (`cdef` box) from a JP2 file without decoding the image.
+///
+/// Returns an empty vec for raw J2K codestreams and JP2 files without a `cdef` box.
+///
+/// ```
+/// # fn main() -> anyhow::Result<()> {
+/// let buf = std::fs::read("samples/file6.jp2")?;
+/// assert!(jpeg2k::format::read_channel_definitions(&buf)?.is_empty());
+/// # Ok(())
+/// # }
+/// ```
+pub fn read_channel_definitions(buf: &[u8]) -> Result<Vec<ChannelDef>> {
+  if !j2k_detect_format(buf)?.is_container() {
+    return Ok(Vec::new());
+  }
+  match find_box(buf, b"jp2h") {
+    Some(jp2h) => Ok(parse_channel_definitions(jp2h)?.unwrap_or_default()),
+    None => Err(Error::CodecError(
+      "JP2 file has no header (jp2h) box".into(),
+    )),
+  }
+}
+
+/// Precinct size exponents (`PPx`, `PPy`) of each resolution from the `COD` marker of the
+/// main header.  Without user defined precincts all resolutions use `(15, 15)`.
+pub(crate) fn read_cod_precincts(buf: &[u8]) -> Option<Vec<(u32, u32)>> {
+  let codestream = if j2k_detect_format(buf).ok()?.is_container() {
+    find_jp2_codestream(buf)?
+  } else {
+    buf
+  };
+  if !codestream.starts_with(J2K_CODESTREAM_MAGIC) {
+    return None;
+  }
+  // Marker segments after the SOC marker, until the first SOT marker.
+  let mut offset = 2;
+  loop {
+    let marker = read_u16(codestream, offset)?;
+    if marker == 0xff90 {
+      return None;
+    }
+    let len = read_u16(codestream, offset + 2)? as usize;
+    if marker == 0xff52 {
+      let cod = codestream.get(offset + 4..offset + 2 + len)?;
+      let num_res = *cod.get(5)? as usize + 1;
+      if cod[0] & 0x01 == 0 {
+        return Some(vec![(15, 15); num_res]);
+      }
+      let precincts = cod.get(10..10 + num_res)?;
+      return Some(
+        precincts
+          .iter()
+          .map(|pp| ((pp & 0x0f) as u32, (pp >> 4) as u32))
+          .collect(),
+      );
+    }
+    offset += 2 + len;
+  }
+}
+
+/// Read the `SIZ` marker from a J2K codestream or JP2 file without decoding the image.
+///
+/// This only parses the start of the main header, it doesn't use openjpeg.
+///
+/// ```
+/// # fn main() -> anyhow::Result<()> {
+/// let buf = std::fs::read("samples/file6.jp2")?;
+/// let siz = jpeg2k::format::read_siz(&buf)?;
+/// assert_eq!((siz.width, siz.height, siz.num_components), (768, 512, 1));
+/// assert_eq!(siz.precisions, [12]);
+///
+/// let buf = std::fs::read("samples/p0_03.j2k")?;
+/// let siz = jpeg2k::format::read_siz(&buf)?;
+/// assert_eq!((siz.width, siz.height), (256, 256));
+/// assert_eq!(siz.tile_dims, (128, 128));
+/// assert_eq!(siz.rsiz, jpeg2k::Rsiz::Profile0);
+/// # Ok(())
+/// # }
+/// ```
+pub fn read_siz(buf: &[u8]) -> Result<SizInfo> {
+  let codestream = if j2k_detect_format(buf)?.is_container() {
+    find_jp2_codestream(buf)
+      .ok_or_else(|| Error::CodecError("JP2 file has no codestream (jp2c) box".into()))?
+  } else {
+    buf
+  };
+  if !codestream.starts_with(J2K_CODESTREAM_MAGIC) {
+    return Err(siz_error("codestream doesn't start with SOC and SIZ"));
+  }
+  // Marker segment after the SOC and SIZ markers.
+  let siz = &codestream[4..];
+  let len = read_u16(siz, 0).ok_or_else(|| siz_error("truncated"))? as usize;
+  let siz = siz.get(..len).ok_or_else(|| siz_error("truncated"))?;
+  let field = |offset: usize| read_u32(siz, offset).ok_or_else(|| siz_error("truncated"));
+  let (x1, y1, x0, y0) = (field(4)?, field(8)?, field(12)?, field(16)?);
+  let tile_dims = (field(20)?, field(24)?);
+  let rsiz = Rsiz::from_raw(read_u16(siz, 2).ok_or_else(|| siz_error("truncated"))?);
+  let num_components = read_u16(siz, 36).ok_or_else(|| siz_error("truncated"))? as u32;
+  if x1 <= x0 || y1 <= y0 {
+    return Err(siz_error("invalid image extent"));
+  }
+  if num_components == 0 {
+    return Err(siz_error("image has no components"));
   }
+  let precisions = (0..num_components as usize)
+    .map(|c| {
+      siz
+        .get(38 + c * 3)
+        .map(|ssiz| (*ssiz as u32 & 0x7f) + 1)
+        .ok_or_else(|| siz_error("truncated"))
+    })
+    .collect::<Result<Vec<_>>>()?;
+  Ok(SizInfo {
+    width: x1 - x0,
+    height: y1 - y0,
+    num_components,
+    precisions,
+    tile_dims,
+    rsiz,
+  })
 }
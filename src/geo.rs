@@ -0,0 +1,196 @@
+use super::*;
+
+/// UUID of the GeoJP2 `uuid` box.
+pub const GEOJP2_UUID: Uuid = [
+  0xb1, 0x4b, 0xf8, 0xbd, 0x08, 0x3d, 0x4b, 0x43, 0xa5, 0xae, 0x8c, 0xd7, 0xd5, 0xa6, 0xce, 0x03,
+];
+
+const MODEL_PIXEL_SCALE_TAG: u16 = 33550;
+const MODEL_TIEPOINT_TAG: u16 = 33922;
+const MODEL_TRANSFORMATION_TAG: u16 = 34264;
+const GEO_KEY_DIRECTORY_TAG: u16 = 34735;
+
+const GEOGRAPHIC_TYPE_GEO_KEY: u16 = 2048;
+const PROJECTED_CS_TYPE_GEO_KEY: u16 = 3072;
+/// GeoKey value of a user defined CRS.
+const USER_DEFINED: u16 = 32767;
+
+/// Georeferencing of an image, from a GeoJP2 box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeoTransform {
+  /// Affine transform from pixel to world coordinates, in the GDAL order:
+  /// `[origin_x, pixel_width, row_rotation, origin_y, column_rotation, pixel_height]`.
+  ///
+  /// `pixel_height` is negative for north-up images.
+  pub transform: [f64; 6],
+  /// EPSG code of the projected or geographic CRS, `None` if it isn't given or is user
+  /// defined.
+  pub epsg: Option<u16>,
+}
+
+impl GeoTransform {
+  /// Map the pixel coordinates `(x, y)` (column, row) to world coordinates.
+  ///
+  /// `(0.0, 0.0)` is the top-left corner of the top-left pixel.
+  pub fn pixel_to_world(&self, x: f64, y: f64) -> (f64, f64) {
+    let t = &self.transform;
+    (t[0] + x * t[1] + y * t[2], t[3] + x * t[4] + y * t[5])
+  }
+}
+
+/// The tags of the first IFD of a TIFF file.
+struct Tiff<'a> {
+  buf: &'a [u8],
+  little_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+  fn new(buf: &'a [u8]) -> Option<Self> {
+    let little_endian = match buf.get(..4)? {
+      b"II*\0" => true,
+      b"MM\0*" => false,
+      _ => return None,
+    };
+    Some(Self { buf, little_endian })
+  }
+
+  fn bytes<const N: usize>(&self, offset: usize) -> Option<[u8; N]> {
+    let mut bytes: [u8; N] = self
+      .buf
+      .get(offset..offset.checked_add(N)?)?
+      .try_into()
+      .ok()?;
+    if !self.little_endian {
+      bytes.reverse();
+    }
+    Some(bytes)
+  }
+
+  fn u16(&self, offset: usize) -> Option<u16> {
+    self.bytes(offset).map(u16::from_le_bytes)
+  }
+
+  fn u32(&self, offset: usize) -> Option<u32> {
+    self.bytes(offset).map(u32::from_le_bytes)
+  }
+
+  fn f64(&self, offset: usize) -> Option<f64> {
+    self.bytes(offset).map(f64::from_le_bytes)
+  }
+
+  /// The values of `tag` in the first IFD.  Only `SHORT`, `LONG` and `DOUBLE` values are
+  /// supported.
+  fn values(&self, tag: u16) -> Option<Vec<f64>> {
+    let ifd = self.u32(4)? as usize;
+    let num_entries = self.u16(ifd)? as usize;
+    let entry = (0..num_entries)
+      .map(|idx| ifd + 2 + idx * 12)
+      .find(|&entry| self.u16(entry) == Some(tag))?;
+    let count = self.u32(entry + 4)? as usize;
+    let size = match self.u16(entry + 2)? {
+      3 => 2,
+      4 => 4,
+      12 => 8,
+      _ => return None,
+    };
+    // Values that fit in 4 bytes are stored in the entry.
+    let offset = if count.checked_mul(size)? <= 4 {
+      entry + 8
+    } else {
+      self.u32(entry + 8)? as usize
+    };
+    (0..count)
+      .map(|idx| {
+        let offset = offset + idx * size;
+        match size {
+          2 => self.u16(offset).map(f64::from),
+          4 => self.u32(offset).map(f64::from),
+          _ => self.f64(offset),
+        }
+      })
+      .collect()
+  }
+}
+
+/// Parse the degenerate GeoTIFF of a GeoJP2 box.
+fn parse_geotiff(buf: &[u8]) -> Option<GeoTransform> {
+  let tiff = Tiff::new(buf)?;
+  let transform = match tiff.values(MODEL_TRANSFORMATION_TAG) {
+    Some(m) if m.len() >= 16 => [m[3], m[0], m[1], m[7], m[4], m[5]],
+    _ => {
+      let tiepoint = tiff.values(MODEL_TIEPOINT_TAG)?;
+      let scale = tiff.values(MODEL_PIXEL_SCALE_TAG)?;
+      let (&[i, j, _, x, y, ..], &[sx, sy, ..]) = (tiepoint.as_slice(), scale.as_slice()) else {
+        return None;
+      };
+      [x - i * sx, sx, 0.0, y + j * sy, 0.0, -sy]
+    }
+  };
+  // The GeoKey directory: a header of 4 shorts (with the number of keys last), then
+  // `(key, location, count, value)` for each key.  Location `0` means the value is
+  // stored in the key.
+  let keys = tiff.values(GEO_KEY_DIRECTORY_TAG).unwrap_or_default();
+  let key_value = |id: u16| {
+    keys
+      .get(4..)?
+      .chunks_exact(4)
+      .find(|key| key[0] == id as f64 && key[1] == 0.0)
+      .map(|key| key[3] as u16)
+      .filter(|&value| value != 0 && value != USER_DEFINED)
+  };
+  let epsg = key_value(PROJECTED_CS_TYPE_GEO_KEY).or_else(|| key_value(GEOGRAPHIC_TYPE_GEO_KEY));
+  Some(GeoTransform { transform, epsg })
+}
+
+impl Image {
+  /// The georeferencing of the image from the GeoJP2 `uuid` box.
+  ///
+  /// GeoJP2 stores a degenerate (1x1 pixel) GeoTIFF in a `uuid` box with UUID
+  /// [`GEOJP2_UUID`].  Only the tags needed for the affine transform and the CRS are read
+  /// from it, this isn't a full TIFF reader:
+  /// * `ModelTransformationTag`, or `ModelTiepointTag` with `ModelPixelScaleTag` (the
+  ///   first tiepoint is used).
+  /// * The `ProjectedCSTypeGeoKey` or `GeographicTypeGeoKey` of the `GeoKeyDirectoryTag`
+  ///   for the EPSG code.
+  ///
+  /// The raster type (`PixelIsPoint`) isn't applied.  Returns `None` if there is no GeoJP2
+  /// box or it can't be parsed.  GMLJP2 georeferencing is in the XML boxes, see
+  /// [`xml_boxes`](Self::xml_boxes).
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # // A little endian GeoTIFF with a tiepoint, the pixel scale and a projected CRS.
+  /// # fn geotiff(tiepoint: [f64; 6], scale: [f64; 3], epsg: u16) -> Vec<u8> {
+  /// #   let entry = |tag: u16, typ: u16, count: u32, offset: u32| {
+  /// #     [&tag.to_le_bytes()[..], &typ.to_le_bytes(), &count.to_le_bytes(), &offset.to_le_bytes()].concat()
+  /// #   };
+  /// #   let mut buf = [&b"II*\0"[..], &8u32.to_le_bytes(), &3u16.to_le_bytes()].concat();
+  /// #   buf.extend(entry(33550, 12, 3, 50));
+  /// #   buf.extend(entry(33922, 12, 6, 74));
+  /// #   buf.extend(entry(34735, 3, 8, 122));
+  /// #   buf.extend(0u32.to_le_bytes());
+  /// #   buf.extend(scale.iter().chain(&tiepoint).flat_map(|v| v.to_le_bytes()));
+  /// #   buf.extend([1u16, 1, 0, 1, 3072, 0, 1, epsg].iter().flat_map(|v| v.to_le_bytes()));
+  /// #   buf
+  /// # }
+  /// # fn main() -> anyhow::Result<()> {
+  /// let mut buf = std::fs::read("samples/file6.jp2")?;
+  /// assert_eq!(Image::from_bytes(&buf)?.geo_transform(), None);
+  ///
+  /// // UTM zone 33N, 10m pixels.
+  /// let tiff = geotiff([0.0, 0.0, 0.0, 500000.0, 4650000.0, 0.0], [10.0, 10.0, 0.0], 32633);
+  /// buf.extend((tiff.len() as u32 + 24).to_be_bytes());
+  /// buf.extend(b"uuid");
+  /// buf.extend(GEOJP2_UUID);
+  /// buf.extend(tiff);
+  /// let geo = Image::from_bytes(&buf)?.geo_transform().unwrap();
+  /// assert_eq!(geo.epsg, Some(32633));
+  /// assert_eq!(geo.transform, [500000.0, 10.0, 0.0, 4650000.0, 0.0, -10.0]);
+  /// assert_eq!(geo.pixel_to_world(768.0, 512.0), (507680.0, 4644880.0));
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn geo_transform(&self) -> Option<GeoTransform> {
+    parse_geotiff(self.uuid_box(&GEOJP2_UUID)?)
+  }
+}
@@ -0,0 +1,93 @@
+use std::ffi::CStr;
+use std::sync::OnceLock;
+
+use super::*;
+
+/// The openjpeg backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+  /// The C openjpeg library (`openjpeg-sys` feature).
+  OpenJpegSys,
+  /// The Rust port of openjpeg (`openjp2` feature).
+  OpenJp2,
+}
+
+/// What is compiled in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildInfo {
+  /// The openjpeg backend.
+  pub backend: Backend,
+  /// Multi-threaded decoding (`threads` feature).
+  pub threads: bool,
+  /// `image` crate support (`image` feature).
+  pub image: bool,
+  /// Version of the openjpeg library, from `opj_version()`.
+  pub openjpeg_version: String,
+}
+
+/// Get the backend, features and openjpeg version compiled into this crate.
+///
+/// Useful for bug reports.
+///
+/// It is safe to call from multiple threads.
+///
+/// ```
+/// let info = jpeg2k::build_info();
+/// std::thread::scope(|s| {
+///   let handles = (0..16)
+///     .map(|_| s.spawn(|| (0..100).map(|_| jpeg2k::build_info()).collect::<Vec<_>>()))
+///     .collect::<Vec<_>>();
+///   for handle in handles {
+///     assert!(handle.join().unwrap().iter().all(|i| *i == info));
+///   }
+/// });
+/// ```
+pub fn build_info() -> BuildInfo {
+  #[cfg(feature = "openjpeg-sys")]
+  let backend = Backend::OpenJpegSys;
+  #[cfg(feature = "openjp2")]
+  let backend = Backend::OpenJp2;
+  BuildInfo {
+    backend,
+    threads: cfg!(feature = "threads"),
+    image: cfg!(feature = "image"),
+    openjpeg_version: openjpeg_version().to_string(),
+  }
+}
+
+/// Version of the linked openjpeg library (from `opj_version()`), for example `"2.5.2"`.
+///
+/// With the `openjpeg-sys` feature this is the version of the C library (vendored or
+/// system), with the `openjp2` feature it is the openjpeg version the Rust port is based on.
+#[allow(unused_unsafe)]
+pub fn openjpeg_version() -> &'static str {
+  // The version is read once.  openjpeg doesn't need any global initialization.
+  static VERSION: OnceLock<String> = OnceLock::new();
+  VERSION.get_or_init(|| {
+    // `opj_version` is only unsafe with `openjpeg-sys`.
+    let version = unsafe { CStr::from_ptr(sys::opj_version()) };
+    version.to_str().unwrap_or("unknown").to_string()
+  })
+}
+
+/// Check that the linked openjpeg library is at least version `major.minor`.
+///
+/// Returns an error if the version is older or can't be parsed.
+///
+/// ```
+/// jpeg2k::assert_min_version(2, 3).expect("openjpeg is too old");
+/// ```
+pub fn assert_min_version(major: u32, minor: u32) -> Result<()> {
+  let version = openjpeg_version();
+  let mut parts = version.split('.').map(|v| v.parse::<u32>());
+  match (parts.next(), parts.next()) {
+    (Some(Ok(found_major)), Some(Ok(found_minor)))
+      if (found_major, found_minor) >= (major, minor) =>
+    {
+      Ok(())
+    }
+    _ => Err(Error::UnsupportedVersionError(format!(
+      "{version}, need at least {major}.{minor}"
+    ))),
+  }
+}
@@ -1,4 +1,8 @@
+use std::cell::Cell;
+use std::io::{Seek, Write};
 use std::ptr;
+use std::rc::Rc;
+use std::time::Instant;
 
 #[cfg(feature = "file-io")]
 use std::path::Path;
@@ -39,6 +43,50 @@ impl ImageComponent {
     self.0.h
   }
 
+  /// Component origin `(x0, y0)` in component samples at full resolution.
+  ///
+  /// This is the image offset (on the reference grid) divided by the subsampling:
+  /// `ceil(image_x0 / dx)`.  Like openjpeg, the origin isn't reduced for a reduced
+  /// resolution decode, at the decoded resolution it is `ceil(x0 / 2^reduce)`.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # use jpeg2k::format::J2KFormat;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let luma = (0..16 * 16).collect::<Vec<i32>>();
+  /// let chroma = (0..8 * 8).map(|v| v * 3).collect::<Vec<i32>>();
+  /// let img = Image::from_components(16, 16, ColorSpace::Unspecified, vec![
+  ///   ComponentData::new(luma, 8),
+  ///   ComponentData::new(chroma.clone(), 8).subsampling(2, 2),
+  ///   ComponentData::new(chroma, 8).subsampling(2, 2),
+  /// ])?;
+  /// let buf = img.to_bytes(J2KFormat::J2K, EncodeParameters::default())?;
+  /// let area = || DecodeParameters::new().decode_area(Some(DecodeArea::new(4, 6, 16, 16)));
+  /// let img = Image::from_bytes_with(&buf, area())?;
+  /// let comps = img.components();
+  /// assert_eq!((comps[0].origin(), comps[1].origin()), ((4, 6), (2, 3)));
+  /// let reduced = Image::from_bytes_with(&buf, area().reduce(1))?;
+  /// assert_eq!(reduced.components()[1].origin(), (2, 3));
+  ///
+  /// // The components are aligned on the reference grid when building pixels.
+  /// let full = Image::from_bytes(&buf)?.get_pixels(None)?.data;
+  /// let (ImagePixelData::Rgb8(full), ImagePixelData::Rgb8(pixels)) = (full, img.get_pixels(None)?.data) else {
+  ///   unreachable!()
+  /// };
+  /// let expected = (6..16).flat_map(|y| &full[(y * 16 + 4) * 3..(y + 1) * 16 * 3]);
+  /// assert!(expected.eq(pixels.iter()));
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn origin(&self) -> (u32, u32) {
+    (self.0.x0, self.0.y0)
+  }
+
+  /// Horizontal (`dx`) and vertical (`dy`) subsampling of the component.
+  pub fn subsampling(&self) -> (u32, u32) {
+    (self.0.dx, self.0.dy)
+  }
+
   /// Component precision.
   pub fn precision(&self) -> u32 {
     self.0.prec
@@ -59,17 +107,176 @@ impl ImageComponent {
     self.0.sgnd == 1
   }
 
+  /// Number of samples in the component data.
+  ///
+  /// Returns `None` if `w * h` overflows or the data would be too large for a slice.
+  fn data_len(&self) -> Option<usize> {
+    let len = (self.0.w as usize).checked_mul(self.0.h as usize)?;
+    let size = len.checked_mul(std::mem::size_of::<i32>())?;
+    if size > isize::MAX as usize {
+      return None;
+    }
+    Some(len)
+  }
+
   /// Component data.
+  ///
+  /// Returns an empty slice if the component has no data (only the header was decoded)
+  /// or if the component dimensions are too large.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> anyhow::Result<()> {
+  /// let mut buf = std::fs::read("samples/p0_03.j2k")?;
+  /// // Patch the SIZ marker to a 65536x65536 image (and tile), `w * h` overflows a `u32`.
+  /// buf[8..16].copy_from_slice(&[0, 1, 0, 0, 0, 1, 0, 0]);
+  /// buf[24..32].copy_from_slice(&[0, 1, 0, 0, 0, 1, 0, 0]);
+  /// let dump = DumpImage::from_bytes(&buf)?;
+  /// let comp = &dump.img.components()[0];
+  /// assert_eq!((comp.width(), comp.height()), (65536, 65536));
+  /// assert!(comp.data().is_empty());
+  ///
+  /// // The image offset is past the image size (`x1 < x0`).
+  /// let mut buf = std::fs::read("samples/p0_03.j2k")?;
+  /// buf[16..20].copy_from_slice(&[0, 0, 1, 1]);
+  /// assert!(DumpImage::from_bytes(&buf).is_err());
+  /// # Ok(())
+  /// # }
+  /// ```
   pub fn data(&self) -> &[i32] {
-    let len = (self.0.w * self.0.h) as usize;
-    unsafe { std::slice::from_raw_parts(self.0.data, len) }
+    match self.data_len() {
+      Some(len) if !self.0.data.is_null() => unsafe {
+        std::slice::from_raw_parts(self.0.data, len)
+      },
+      _ => &[],
+    }
+  }
+
+  /// Copy the component data into an owned `Vec`.
+  ///
+  /// This copies all samples (`width * height * 4` bytes).
+  pub fn to_vec(&self) -> Vec<i32> {
+    self.data().to_vec()
+  }
+
+  /// Component data as unsigned samples (zero-copy).
+  ///
+  /// Returns `None` if the component data is signed.
+  #[cfg(feature = "bytemuck")]
+  pub fn data_unsigned(&self) -> Option<&[u32]> {
+    if self.is_signed() {
+      None
+    } else {
+      Some(bytemuck::cast_slice(self.data()))
+    }
+  }
+
+  /// Raw bytes of the component's `i32` samples (zero-copy), in native endian.
+  ///
+  /// For uploading a plane as is, for example as a `R32Sint` texture.  The length is
+  /// `width * height * 4`, or `0` when [`ImageComponent::data`] is empty.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let img = Image::from_components(2, 1, ColorSpace::Gray, vec![
+  ///   ComponentData::new(vec![1, -2], 8).signed(true),
+  /// ])?;
+  /// let bytes = img.components()[0].as_bytes();
+  /// assert_eq!(bytes.len(), 8);
+  /// assert_eq!(bytes[4..], (-2i32).to_ne_bytes());
+  /// # Ok(())
+  /// # }
+  /// ```
+  #[cfg(feature = "bytemuck")]
+  pub fn as_bytes(&self) -> &[u8] {
+    bytemuck::cast_slice(self.data())
+  }
+
+  /// Component data as `f32`, without any scaling.
+  ///
+  /// Each sample is converted verbatim, for example 12bit signed data stays in
+  /// `-2048.0..=2047.0`.  Use [`ImageComponent::data_u8`]/[`ImageComponent::data_u16`]
+  /// for data scaled to a fixed range.
+  pub fn data_as_f32_raw(&self) -> impl Iterator<Item = f32> + '_ {
+    self.data().iter().map(|&p| p as f32)
+  }
+
+  /// Component data as `u16`, without scaling to the full 16bit range.
+  ///
+  /// Unsigned samples are kept as is, 12bit data stays in `0..=4095`.  Signed samples are
+  /// offset by half the range (`2^(precision - 1)`) to make them unsigned, 12bit signed
+  /// data in `-2048..=2047` becomes `0..=4095` and `0` maps to `2048`.  Samples that don't
+  /// fit in a `u16` (precision above 16) are clamped.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let img = Image::from_components(3, 1, ColorSpace::Gray, vec![
+  ///   ComponentData::new(vec![0, 1000, 4095], 12),
+  ///   ComponentData::new(vec![-2048, 0, 2047], 12).signed(true),
+  /// ])?;
+  /// let comps = img.components();
+  /// assert_eq!(comps[0].data_raw_u16().collect::<Vec<_>>(), [0, 1000, 4095]);
+  /// assert_eq!(comps[1].data_raw_u16().collect::<Vec<_>>(), [0, 2048, 4095]);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn data_raw_u16(&self) -> impl Iterator<Item = u16> + '_ {
+    let offset = if self.is_signed() {
+      1i64 << (self.precision().clamp(1, 32) - 1)
+    } else {
+      0
+    };
+    self
+      .data()
+      .iter()
+      .map(move |&p| (p as i64 + offset).clamp(0, u16::MAX as i64) as u16)
+  }
+
+  /// Mutable component data.
+  ///
+  /// The samples must stay in the component's precision (and signedness) range, or
+  /// they are clamped or wrapped when encoding.
+  pub fn data_mut(&mut self) -> &mut [i32] {
+    match self.data_len() {
+      Some(len) if !self.0.data.is_null() => unsafe {
+        std::slice::from_raw_parts_mut(self.0.data, len)
+      },
+      _ => &mut [],
+    }
+  }
+
+  /// Component data scaled to unsigned `prec` bits (`1..=31`).
+  ///
+  /// Signed data is offset, so zero maps to the middle of the unsigned range.
+  pub(crate) fn data_scaled(&self, prec: u32) -> Box<dyn Iterator<Item = u32> + '_> {
+    let data = self.data();
+    let old_prec = self.precision().max(1);
+    if self.is_signed() {
+      let old_half = 1i64 << (old_prec - 1);
+      let new_half = 1i64 << (prec - 1);
+      let new_max = (1i64 << prec) - 1;
+      Box::new(
+        data
+          .iter()
+          .map(move |p| ((*p as i64 * new_half) / old_half + new_half).clamp(0, new_max) as u32),
+      )
+    } else {
+      let old_max = (1u64 << old_prec) - 1;
+      let new_max = (1u64 << prec) - 1;
+      Box::new(
+        data
+          .iter()
+          .map(move |p| (((*p as u32 as u64) * new_max) / old_max).min(new_max) as u32),
+      )
+    }
   }
 
   /// Component data scaled to unsigned 8bit.
-  pub fn data_u8(&self) -> Box<dyn Iterator<Item = u8>> {
-    let len = (self.0.w * self.0.h) as usize;
+  pub fn data_u8(&self) -> Box<dyn Iterator<Item = u8> + '_> {
+    let data = self.data();
     if self.is_signed() {
-      let data = unsafe { std::slice::from_raw_parts(self.0.data, len) };
       let old_max = (1 << (self.precision() - 1)) as i64;
       const NEW_MAX: i64 = 1 << (8 - 1);
       const ADJUST: u8 = (NEW_MAX - 1) as u8;
@@ -79,22 +286,20 @@ impl ImageComponent {
           .map(move |p| (((*p as i64) * NEW_MAX) / old_max) as u8 + ADJUST),
       )
     } else {
-      let data = unsafe { std::slice::from_raw_parts(self.0.data as *const u32, len) };
       let old_max = ((1 << self.precision()) - 1) as u64;
       const NEW_MAX: u64 = (1 << 8) - 1;
       Box::new(
         data
           .iter()
-          .map(move |p| (((*p as u64) * NEW_MAX) / old_max) as u8),
+          .map(move |p| (((*p as u32 as u64) * NEW_MAX) / old_max) as u8),
       )
     }
   }
 
   /// Component data scaled to unsigned 16bit.
-  pub fn data_u16(&self) -> Box<dyn Iterator<Item = u16>> {
-    let len = (self.0.w * self.0.h) as usize;
+  pub fn data_u16(&self) -> Box<dyn Iterator<Item = u16> + '_> {
+    let data = self.data();
     if self.is_signed() {
-      let data = unsafe { std::slice::from_raw_parts(self.0.data, len) };
       let old_max = (1 << (self.precision() - 1)) as i64;
       const NEW_MAX: i64 = 1 << (16 - 1);
       const ADJUST: u16 = (NEW_MAX - 1) as u16;
@@ -104,20 +309,158 @@ impl ImageComponent {
           .map(move |p| (((*p as i64) * NEW_MAX) / old_max) as u16 + ADJUST),
       )
     } else {
-      let data = unsafe { std::slice::from_raw_parts(self.0.data as *const u32, len) };
       let old_max = ((1 << self.precision()) - 1) as u64;
       const NEW_MAX: u64 = (1 << 16) - 1;
       Box::new(
         data
           .iter()
-          .map(move |p| (((*p as u64) * NEW_MAX) / old_max) as u16),
+          .map(move |p| (((*p as u32 as u64) * NEW_MAX) / old_max) as u16),
       )
     }
   }
 }
 
+/// Samples for a new image component.  Used by [`Image::from_components`].
+///
+/// The component is unsigned, not subsampled and not an alpha channel by default.
+#[derive(Debug, Clone)]
+pub struct ComponentData {
+  data: Vec<i32>,
+  precision: u32,
+  signed: bool,
+  dx: u32,
+  dy: u32,
+  alpha: bool,
+}
+
+impl ComponentData {
+  /// New component from `data` (row-major samples) with a precision of `precision` bits.
+  pub fn new(data: Vec<i32>, precision: u32) -> Self {
+    Self {
+      data,
+      precision,
+      signed: false,
+      dx: 1,
+      dy: 1,
+      alpha: false,
+    }
+  }
+
+  /// The samples are signed.
+  pub fn signed(mut self, signed: bool) -> Self {
+    self.signed = signed;
+    self
+  }
+
+  /// Horizontal (`dx`) and vertical (`dy`) subsampling of the component.
+  ///
+  /// A subsampled component has `ceil(width / dx) x ceil(height / dy)` samples.
+  pub fn subsampling(mut self, dx: u32, dy: u32) -> Self {
+    self.dx = dx;
+    self.dy = dy;
+    self
+  }
+
+  /// The component is an alpha channel.
+  pub fn alpha(mut self, alpha: bool) -> Self {
+    self.alpha = alpha;
+    self
+  }
+}
+
+/// Filter used to upsample subsampled components.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum UpsampleFilter {
+  /// Repeat the nearest sample.
+  Nearest,
+  /// Bilinear interpolation between the 4 nearest samples.  This is the default.
+  #[default]
+  Bilinear,
+}
+
+impl UpsampleFilter {
+  /// Resample `src` (`src_w x src_h`) into `dst` (`dst_w x dst_h`).
+  fn resample(&self, src: &[i32], src_w: usize, src_h: usize, dst: &mut [i32], dst_w: usize) {
+    if src_w == 0 || src_h == 0 || dst_w == 0 || src.len() < src_w * src_h {
+      return;
+    }
+    let dst_h = dst.len() / dst_w;
+    let scale_x = src_w as f32 / dst_w as f32;
+    let scale_y = src_h as f32 / dst_h as f32;
+    // Map destination pixel centers onto the source grid.
+    let pos = |idx: usize, scale: f32, len: usize| {
+      let p = ((idx as f32 + 0.5) * scale - 0.5).clamp(0.0, (len - 1) as f32);
+      let i = p.floor() as usize;
+      (i, (i + 1).min(len - 1), p - i as f32)
+    };
+    for (y, row) in dst.chunks_exact_mut(dst_w).enumerate() {
+      match self {
+        Self::Nearest => {
+          let sy = ((y as f32 * scale_y) as usize).min(src_h - 1);
+          let src_row = &src[sy * src_w..(sy + 1) * src_w];
+          for (x, p) in row.iter_mut().enumerate() {
+            *p = src_row[((x as f32 * scale_x) as usize).min(src_w - 1)];
+          }
+        }
+        Self::Bilinear => {
+          let (y0, y1, fy) = pos(y, scale_y, src_h);
+          let (row0, row1) = (&src[y0 * src_w..], &src[y1 * src_w..]);
+          for (x, p) in row.iter_mut().enumerate() {
+            let (x0, x1, fx) = pos(x, scale_x, src_w);
+            let top = row0[x0] as f32 * (1.0 - fx) + row0[x1] as f32 * fx;
+            let bottom = row1[x0] as f32 * (1.0 - fx) + row1[x1] as f32 * fx;
+            *p = (top * (1.0 - fy) + bottom * fy).round() as i32;
+          }
+        }
+      }
+    }
+  }
+}
+
+/// Kind of color channels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorKind {
+  Gray,
+  Rgb,
+  Cmyk,
+  Ycc,
+}
+
+impl ColorKind {
+  /// Number of color channels.
+  pub fn num_channels(&self) -> u32 {
+    match self {
+      ColorKind::Gray => 1,
+      ColorKind::Rgb | ColorKind::Ycc => 3,
+      ColorKind::Cmyk => 4,
+    }
+  }
+}
+
+/// Sample format of an image: signedness and bit depth.
+///
+/// JPEG 2000 samples are always integers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SampleFormat {
+  /// Samples are signed.
+  pub signed: bool,
+  /// Max precision (in bits) of all components.
+  pub bits: u32,
+}
+
+/// Summary of the image channels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelInfo {
+  /// Kind of color channels.
+  pub color: ColorKind,
+  /// Has an alpha channel.
+  pub alpha: bool,
+  /// Max precision (in bits) of all components.
+  pub max_precision: u32,
+}
+
 /// Image Data.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ImageFormat {
   L8,
@@ -131,7 +474,7 @@ pub enum ImageFormat {
 }
 
 /// Image Pixel Data.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ImagePixelData {
   L8(Vec<u8>),
@@ -145,18 +488,127 @@ pub enum ImagePixelData {
 }
 
 /// Image Data.
-#[derive(Debug, Clone)]
+///
+/// With the `serde` feature it can be serialized, for example to cache decoded images.
+///
+/// ```
+/// # use jpeg2k::*;
+/// # fn main() -> anyhow::Result<()> {
+/// let pixels = Image::from_file("samples/file6.jp2")?.get_pixels(None)?;
+/// assert_eq!(pixels.color_space, ColorSpace::Gray);
+/// # #[cfg(feature = "serde")]
+/// # {
+/// let json = serde_json::to_string(&pixels)?;
+/// let cached: ImageData = serde_json::from_str(&json)?;
+/// assert_eq!(cached, pixels);
+/// # }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ImageData {
   pub width: u32,
   pub height: u32,
   pub format: ImageFormat,
+  pub color_space: ColorSpace,
   pub data: ImagePixelData,
 }
 
+impl ImageData {
+  fn from_u8(
+    color_space: ColorSpace,
+    width: u32,
+    height: u32,
+    num_channels: usize,
+    pixels: Vec<u8>,
+  ) -> Self {
+    let (format, data) = match num_channels {
+      1 => (ImageFormat::L8, ImagePixelData::L8(pixels)),
+      2 => (ImageFormat::La8, ImagePixelData::La8(pixels)),
+      3 => (ImageFormat::Rgb8, ImagePixelData::Rgb8(pixels)),
+      _ => (ImageFormat::Rgba8, ImagePixelData::Rgba8(pixels)),
+    };
+    Self {
+      width,
+      height,
+      format,
+      color_space,
+      data,
+    }
+  }
+
+  fn from_u16(
+    color_space: ColorSpace,
+    width: u32,
+    height: u32,
+    num_channels: usize,
+    pixels: Vec<u16>,
+  ) -> Self {
+    let (format, data) = match num_channels {
+      1 => (ImageFormat::L16, ImagePixelData::L16(pixels)),
+      2 => (ImageFormat::La16, ImagePixelData::La16(pixels)),
+      3 => (ImageFormat::Rgb16, ImagePixelData::Rgb16(pixels)),
+      _ => (ImageFormat::Rgba16, ImagePixelData::Rgba16(pixels)),
+    };
+    Self {
+      width,
+      height,
+      format,
+      color_space,
+      data,
+    }
+  }
+}
+
+/// Interleave the channel samples into pixels, with an optional constant last channel.
+///
+/// Each channel must have exactly `len` samples.
+fn interleave<'a, T: Copy>(
+  len: usize,
+  mut channels: Vec<Box<dyn Iterator<Item = T> + 'a>>,
+  extra: Option<T>,
+) -> Result<Vec<T>> {
+  let num_channels = channels.len() + extra.is_some() as usize;
+  let mismatch = |idx: usize| {
+    Error::InvalidComponentError(format!(
+      "channel {idx} doesn't have the expected {len} samples"
+    ))
+  };
+  let mut pixels = Vec::with_capacity(len * num_channels);
+  for _ in 0..len {
+    for (idx, channel) in channels.iter_mut().enumerate() {
+      pixels.push(channel.next().ok_or_else(|| mismatch(idx))?);
+    }
+    if let Some(extra) = extra {
+      pixels.push(extra);
+    }
+  }
+  if let Some(idx) = channels.iter_mut().position(|c| c.next().is_some()) {
+    return Err(mismatch(idx));
+  }
+  Ok(pixels)
+}
+
 /// A Jpeg2000 Image.
 pub struct Image {
   img: ptr::NonNull<sys::opj_image_t>,
+  /// Channel definitions from the JP2 `cdef` box.
+  channel_defs: Option<Vec<ChannelDef>>,
+  /// Palette from the JP2 `pclr` box.
+  palette: Option<Palette>,
+  /// openjpeg applied the palette when decoding.
+  palette_applied: bool,
+  /// Contents of the JP2 `xml ` boxes.
+  xml_boxes: Rc<[Vec<u8>]>,
+  /// The JP2 `uuid` boxes.
+  uuid_boxes: Rc<[(Uuid, Vec<u8>)]>,
+  /// Number of resolution levels in the codestream.
+  num_resolutions: u32,
+  /// ICC profile shared with the image this one was cropped from, or read from the `colr`
+  /// box by the tile decoder.  openjpeg's image has no ICC profile then (it can only be
+  /// allocated by openjpeg).
+  shared_icc_profile: Option<Rc<[u8]>>,
 }
 
 impl Drop for Image {
@@ -187,7 +639,414 @@ impl Image {
   pub(crate) fn new(ptr: *mut sys::opj_image_t) -> Result<Self> {
     let img =
       ptr::NonNull::new(ptr).ok_or_else(|| Error::NullPointerError("Image: NULL `opj_image_t`"))?;
-    Ok(Self { img })
+    Ok(Self {
+      img,
+      channel_defs: None,
+      palette: None,
+      palette_applied: false,
+      xml_boxes: Rc::new([]),
+      uuid_boxes: Rc::new([]),
+      num_resolutions: 0,
+      shared_icc_profile: None,
+    })
+  }
+
+  pub(crate) fn set_channel_definitions(&mut self, channel_defs: Option<Vec<ChannelDef>>) {
+    self.channel_defs = channel_defs;
+  }
+
+  /// Apply the channel definitions to the decoded components.
+  ///
+  /// openjpeg moves the color channels to the position of their association on the first
+  /// decode (`reordered`), but clears the alpha flag of an alpha component that
+  /// was swapped away from its position.  Later decodes aren't reordered at all.  This
+  /// replays the reordering of openjpeg, doing the swaps if openjpeg didn't, and sets the
+  /// alpha flags of the final components.
+  pub(crate) fn apply_channel_definitions(&self, reordered: bool) {
+    let defs = match &self.channel_defs {
+      Some(defs) => defs,
+      None => return,
+    };
+    let img = self.image();
+    let numcomps = img.numcomps as usize;
+    if img.comps.is_null() {
+      return;
+    }
+    // SAFETY: The components are owned by the image, like the decoder writes the samples
+    // through the image pointer.
+    let comps = unsafe { std::slice::from_raw_parts_mut(img.comps, numcomps) };
+    // `channels[pos]` is the codestream channel at component `pos`.
+    let mut channels = (0..numcomps).collect::<Vec<_>>();
+    let mut cns = defs
+      .iter()
+      .map(|def| def.channel as usize)
+      .collect::<Vec<_>>();
+    for (idx, def) in defs.iter().enumerate() {
+      let (cn, asoc) = (cns[idx], def.association as usize);
+      if def.type_ != ChannelType::Color || asoc == 0 || asoc == 65535 {
+        continue;
+      }
+      let acn = asoc - 1;
+      if cn >= numcomps || acn >= numcomps || cn == acn {
+        continue;
+      }
+      channels.swap(cn, acn);
+      if !reordered {
+        comps.swap(cn, acn);
+      }
+      for other in &mut cns[idx + 1..] {
+        if *other == cn {
+          *other = acn;
+        } else if *other == acn {
+          *other = cn;
+        }
+      }
+    }
+    for def in defs {
+      let alpha = match def.type_ {
+        ChannelType::Color => 0,
+        ChannelType::Opacity => 1,
+        ChannelType::PremultOpacity => 2,
+        ChannelType::Unspecified(_) => continue,
+      };
+      if let Some(pos) = channels.iter().position(|&ch| ch == def.channel as usize) {
+        comps[pos].alpha = alpha;
+      }
+    }
+  }
+
+  pub(crate) fn set_palette(&mut self, palette: Option<Palette>, applied: bool) {
+    self.palette = palette;
+    self.palette_applied = applied;
+  }
+
+  pub(crate) fn set_palette_applied(&mut self, applied: bool) {
+    self.palette_applied = applied;
+  }
+
+  pub(crate) fn set_shared_icc_profile(&mut self, icc: Rc<[u8]>) {
+    self.shared_icc_profile = Some(icc);
+  }
+
+  pub(crate) fn set_xml_boxes(&mut self, xml_boxes: Vec<Vec<u8>>) {
+    self.xml_boxes = xml_boxes.into();
+  }
+
+  pub(crate) fn set_uuid_boxes(&mut self, uuid_boxes: Vec<(Uuid, Vec<u8>)>) {
+    self.uuid_boxes = uuid_boxes.into();
+  }
+
+  pub(crate) fn set_num_resolutions(&mut self, num_resolutions: u32) {
+    self.num_resolutions = num_resolutions;
+  }
+
+  /// Create an image from component samples.
+  ///
+  /// `width` and `height` are the full resolution image size.  Each component must have
+  /// `ceil(width / dx) * ceil(height / dy)` samples.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let gray = (0..64 * 64).map(|i| i % 256).collect();
+  /// let img = Image::from_components(64, 64, ColorSpace::Gray, vec![ComponentData::new(gray, 8)])?;
+  /// assert_eq!(img.width(), 64);
+  ///
+  /// // 4:2:0 chroma planes of an odd sized image are rounded up: 33x25 samples.
+  /// let ycc = |chroma_len| {
+  ///   Image::from_components(65, 49, ColorSpace::SYCC, vec![
+  ///     ComponentData::new(vec![0; 65 * 49], 8),
+  ///     ComponentData::new(vec![0; chroma_len], 8).subsampling(2, 2),
+  ///     ComponentData::new(vec![0; 33 * 25], 8).subsampling(2, 2),
+  ///   ])
+  /// };
+  /// let img = ycc(33 * 25)?;
+  /// assert_eq!(img.components()[1].width(), 33);
+  /// let err = ycc(32 * 24).unwrap_err();
+  /// assert_eq!(err.to_string(), "Invalid component: component 1: expected 33x25 for dx=2,dy=2, got buffer of 768");
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn from_components(
+    width: u32,
+    height: u32,
+    color_space: ColorSpace,
+    comps: Vec<ComponentData>,
+  ) -> Result<Self> {
+    if comps.is_empty() {
+      return Err(Error::UnsupportedComponentsError(0));
+    }
+    let mut params = comps
+      .iter()
+      .enumerate()
+      .map(|(idx, comp)| {
+        if comp.dx == 0 || comp.dy == 0 {
+          return Err(Error::InvalidComponentError(format!(
+            "component {idx}: subsampling must be at least 1"
+          )));
+        }
+        if !(1..=31).contains(&comp.precision) {
+          return Err(Error::UnsupportedPrecisionError(comp.precision));
+        }
+        let w = width.div_ceil(comp.dx);
+        let h = height.div_ceil(comp.dy);
+        if comp.data.len() != w as usize * h as usize {
+          return Err(Error::InvalidComponentError(format!(
+            "component {idx}: expected {w}x{h} for dx={},dy={}, got buffer of {}",
+            comp.dx,
+            comp.dy,
+            comp.data.len()
+          )));
+        }
+        Ok(sys::opj_image_cmptparm_t {
+          dx: comp.dx,
+          dy: comp.dy,
+          w,
+          h,
+          x0: 0,
+          y0: 0,
+          prec: comp.precision,
+          bpp: comp.precision,
+          sgnd: comp.signed as u32,
+        })
+      })
+      .collect::<Result<Vec<_>>>()?;
+    let mut img = Self::create(&mut params, color_space, (0, 0, width, height))?;
+    for (dst, src) in img.components_mut().iter_mut().zip(comps) {
+      dst.0.alpha = src.alpha as u16;
+      dst.data_mut().copy_from_slice(&src.data);
+    }
+    Ok(img)
+  }
+
+  /// Create an image from interleaved pixels, the inverse of [`get_pixels`](Self::get_pixels).
+  ///
+  /// The precision is 8 or 16 bits (unsigned) from the pixel format.  With `La`/`Rgba`
+  /// formats the last channel is alpha.  `data` must have `width * height` pixels.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let (w, h) = (16, 8);
+  /// let bytes = |ch: usize| (0..w * h * ch).map(|v| v as u8).collect::<Vec<_>>();
+  /// let words = |ch: usize| (0..w * h * ch).map(|v| v as u16 * 97).collect::<Vec<_>>();
+  /// for data in [
+  ///   ImagePixelData::L8(bytes(1)),
+  ///   ImagePixelData::La8(bytes(2)),
+  ///   ImagePixelData::Rgb8(bytes(3)),
+  ///   ImagePixelData::Rgba8(bytes(4)),
+  ///   ImagePixelData::L16(words(1)),
+  ///   ImagePixelData::La16(words(2)),
+  ///   ImagePixelData::Rgb16(words(3)),
+  ///   ImagePixelData::Rgba16(words(4)),
+  /// ] {
+  ///   let color_space = match data {
+  ///     ImagePixelData::L8(_) | ImagePixelData::La8(_) | ImagePixelData::L16(_) | ImagePixelData::La16(_) => ColorSpace::Gray,
+  ///     _ => ColorSpace::SRGB,
+  ///   };
+  ///   let img = Image::from_pixels(w as u32, h as u32, &data, color_space)?;
+  ///   assert_eq!(img.get_pixels(None)?.data, data);
+  /// }
+  ///
+  /// // One pixel is missing.
+  /// let data = ImagePixelData::Rgb8(vec![0; w * h * 3 - 3]);
+  /// assert!(Image::from_pixels(w as u32, h as u32, &data, ColorSpace::SRGB).is_err());
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn from_pixels(
+    width: u32,
+    height: u32,
+    data: &ImagePixelData,
+    color_space: ColorSpace,
+  ) -> Result<Self> {
+    let (len, num_channels) = match data {
+      ImagePixelData::L8(d) => (d.len(), 1),
+      ImagePixelData::La8(d) => (d.len(), 2),
+      ImagePixelData::Rgb8(d) => (d.len(), 3),
+      ImagePixelData::Rgba8(d) => (d.len(), 4),
+      ImagePixelData::L16(d) => (d.len(), 1),
+      ImagePixelData::La16(d) => (d.len(), 2),
+      ImagePixelData::Rgb16(d) => (d.len(), 3),
+      ImagePixelData::Rgba16(d) => (d.len(), 4),
+    };
+    let expected = width as usize * height as usize * num_channels;
+    if len != expected {
+      return Err(Error::InvalidParametersError(format!(
+        "pixel data of {len} samples, expected {width}x{height}x{num_channels}"
+      )));
+    }
+    let comps = match data {
+      ImagePixelData::L8(d)
+      | ImagePixelData::La8(d)
+      | ImagePixelData::Rgb8(d)
+      | ImagePixelData::Rgba8(d) => deinterleave(d, num_channels, 8),
+      ImagePixelData::L16(d)
+      | ImagePixelData::La16(d)
+      | ImagePixelData::Rgb16(d)
+      | ImagePixelData::Rgba16(d) => deinterleave(d, num_channels, 16),
+    };
+    Self::from_components(width, height, color_space, comps)
+  }
+
+  /// Create an 8 bit grayscale image from `width * height` samples.
+  ///
+  /// Returns `Error::UnsupportedComponentsError` if `data` doesn't have one sample per
+  /// pixel.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # use jpeg2k::format::J2KFormat;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let img = Image::new_grayscale(4, 2, &[0, 32, 64, 96, 128, 160, 192, 224])?;
+  /// assert_eq!(img.color_space(), ColorSpace::Gray);
+  /// let jp2 = img.into_bytes(J2KFormat::JP2, EncodeParameters::default())?;
+  /// let pixels = Image::from_bytes(&jp2)?.get_pixels(None)?;
+  /// assert_eq!(pixels.data, ImagePixelData::L8(vec![0, 32, 64, 96, 128, 160, 192, 224]));
+  ///
+  /// assert!(matches!(
+  ///   Image::new_grayscale(4, 2, &[0; 7]),
+  ///   Err(jpeg2k::error::Error::UnsupportedComponentsError(_))
+  /// ));
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn new_grayscale(width: u32, height: u32, data: &[u8]) -> Result<Self> {
+    Self::from_u8_pixels(width, height, data, 1, ColorSpace::Gray)
+  }
+
+  /// Create an 8 bit sRGB image from interleaved RGB pixels.
+  ///
+  /// Returns `Error::UnsupportedComponentsError` if `data` doesn't have 3 samples per
+  /// pixel.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let img = Image::new_rgb8(2, 1, &[255, 0, 0, 0, 0, 255])?;
+  /// assert_eq!((img.color_space(), img.num_components()), (ColorSpace::SRGB, 3));
+  /// assert!(Image::new_rgb8(2, 1, &[255, 0, 0, 0]).is_err());
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn new_rgb8(width: u32, height: u32, data: &[u8]) -> Result<Self> {
+    Self::from_u8_pixels(width, height, data, 3, ColorSpace::SRGB)
+  }
+
+  /// Create an 8 bit sRGB image with alpha from interleaved RGBA pixels.
+  ///
+  /// Returns `Error::UnsupportedComponentsError` if `data` doesn't have 4 samples per
+  /// pixel.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let img = Image::new_rgba8(1, 2, &[255, 0, 0, 128, 0, 0, 255, 255])?;
+  /// assert!(img.components()[3].is_alpha());
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn new_rgba8(width: u32, height: u32, data: &[u8]) -> Result<Self> {
+    Self::from_u8_pixels(width, height, data, 4, ColorSpace::SRGB)
+  }
+
+  fn from_u8_pixels(
+    width: u32,
+    height: u32,
+    data: &[u8],
+    num_channels: usize,
+    color_space: ColorSpace,
+  ) -> Result<Self> {
+    let pixels = width as usize * height as usize;
+    if data.len() != pixels * num_channels {
+      // The number of channels in the buffer, if it is a whole number.
+      let channels = match data.len().checked_rem(pixels) {
+        Some(0) => data.len() / pixels,
+        _ => 0,
+      };
+      return Err(Error::UnsupportedComponentsError(channels as u32));
+    }
+    Self::from_components(
+      width,
+      height,
+      color_space,
+      deinterleave(data, num_channels, 8),
+    )
+  }
+
+  /// Create an image with zeroed component data.
+  ///
+  /// `area` is the image area `(x0, y0, x1, y1)` on the reference grid.
+  fn create(
+    params: &mut [sys::opj_image_cmptparm_t],
+    color_space: ColorSpace,
+    area: (u32, u32, u32, u32),
+  ) -> Result<Self> {
+    let img = Self::new(unsafe {
+      sys::opj_image_create(params.len() as u32, params.as_mut_ptr(), color_space.into())
+    })?;
+    let raw = unsafe { &mut *img.as_ptr() };
+    (raw.x0, raw.y0, raw.x1, raw.y1) = area;
+    Ok(img)
+  }
+
+  /// Image header for the encoder, the components have no data.
+  ///
+  /// openjpeg takes ownership of the component data when encoding, so the
+  /// encoder can't be given the image's own buffers.
+  fn encode_header(&self) -> Result<Self> {
+    let mut params = self
+      .components()
+      .iter()
+      .map(|comp| sys::opj_image_cmptparm_t {
+        dx: comp.0.dx,
+        dy: comp.0.dy,
+        w: comp.0.w,
+        h: comp.0.h,
+        x0: comp.0.x0,
+        y0: comp.0.y0,
+        prec: comp.0.prec,
+        bpp: comp.0.bpp,
+        sgnd: comp.0.sgnd,
+      })
+      .collect::<Vec<_>>();
+    let src = self.image();
+    let mut img = Self::new(unsafe {
+      sys::opj_image_tile_create(params.len() as u32, params.as_mut_ptr(), src.color_space)
+    })?;
+    let raw = unsafe { &mut *img.as_ptr() };
+    (raw.x0, raw.y0, raw.x1, raw.y1) = (src.x0, src.y0, src.x1, src.y1);
+    for (dst, comp) in img.components_mut().iter_mut().zip(self.components()) {
+      dst.0.alpha = comp.0.alpha;
+    }
+    self.warn_icc_profile();
+    Ok(img)
+  }
+
+  /// openjpeg can't encode ICC profiles (it fails an assert writing the JP2 `colr` box),
+  /// so the encoder isn't given the ICC profile.
+  fn warn_icc_profile(&self) {
+    if self.has_icc_profile() {
+      log::warn!("The ICC profile isn't encoded, openjpeg can't write ICC profiles");
+    }
+  }
+
+  /// Copy the component data of `src` into the components of an image from `encode_header`.
+  fn copy_component_data(&mut self, src: &Image) -> Result<()> {
+    self.alloc_component_data()?;
+    for (dst, comp) in self.components_mut().iter_mut().zip(src.components()) {
+      let dst = dst.data_mut();
+      let data = comp.data();
+      if data.len() != dst.len() {
+        return Err(Error::InvalidComponentError(format!(
+          "component has {} samples, expected {}",
+          data.len(),
+          dst.len()
+        )));
+      }
+      dst.copy_from_slice(data);
+    }
+    Ok(())
   }
 
   /// Load a Jpeg 2000 image from bytes.  It will detect the J2K format.
@@ -196,7 +1055,123 @@ impl Image {
     Self::from_stream(stream, Default::default())
   }
 
+  /// Load a Jpeg 2000 image from bytes, failing if the decode isn't done by `deadline`.
+  ///
+  /// The image is decoded tile by tile and the deadline is checked before each tile, so
+  /// a decode can run past the deadline by the time to decode one tile.  When the deadline
+  /// has passed the decode fails with `Error::CodecError("decode deadline exceeded")`.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # use jpeg2k::format::J2KFormat;
+  /// # use std::time::{Duration, Instant};
+  /// # fn new_box(box_type: &[u8], content: &[u8]) -> Vec<u8> {
+  /// #   let len = (content.len() as u32 + 8).to_be_bytes();
+  /// #   [&len[..], box_type, content].concat()
+  /// # }
+  /// # // Append boxes to the `jp2h` box.
+  /// # fn add_header_boxes(jp2: &[u8], new: &[Vec<u8>]) -> Vec<u8> {
+  /// #   let (mut out, mut offset) = (Vec::new(), 0);
+  /// #   while offset < jp2.len() {
+  /// #     let len = u32::from_be_bytes(jp2[offset..offset + 4].try_into().unwrap()) as usize;
+  /// #     let (box_type, content) = (&jp2[offset + 4..offset + 8], &jp2[offset + 8..offset + len]);
+  /// #     if box_type == b"jp2h" {
+  /// #       out.extend(new_box(box_type, &[content, &new.concat()].concat()));
+  /// #     } else {
+  /// #       out.extend_from_slice(&jp2[offset..offset + len]);
+  /// #     }
+  /// #     offset += len;
+  /// #   }
+  /// #   out
+  /// # }
+  /// # fn main() -> anyhow::Result<()> {
+  /// let buf = std::fs::read("samples/p0_03.j2k")?;
+  /// let deadline = Instant::now() + Duration::from_secs(60);
+  /// let img = Image::from_bytes_with_deadline(&buf, DecodeParameters::new(), deadline)?;
+  /// assert_eq!(img.components()[0].data(), Image::from_bytes(&buf)?.components()[0].data());
+  ///
+  /// // Only the tiles in the decode area are decoded.
+  /// let params = || DecodeParameters::new().decode_area(Some(DecodeArea::new(10, 20, 200, 150)));
+  /// let img = Image::from_bytes_with_deadline(&buf, params().reduce(1), deadline)?;
+  /// let expected = Image::from_bytes_with(&buf, params().reduce(1))?;
+  /// assert_eq!((img.width(), img.height()), (95, 65));
+  /// assert_eq!(img.components()[0].data(), expected.components()[0].data());
+  ///
+  /// let res = Image::from_bytes_with_deadline(&buf, DecodeParameters::new(), Instant::now());
+  /// assert!(matches!(res, Err(jpeg2k::error::Error::CodecError(msg)) if msg == "decode deadline exceeded"));
+  ///
+  /// // The JP2 header is applied: channel definitions, palette and ICC profile.
+  /// let decode = |jp2: &[u8]| -> anyhow::Result<_> {
+  ///   let img = Image::from_bytes_with_deadline(jp2, DecodeParameters::new(), deadline)?;
+  ///   let expected = Image::from_bytes(jp2)?;
+  ///   assert_eq!(img.get_pixels(None)?, expected.get_pixels(None)?);
+  ///   assert_eq!(img.icc_profile(), expected.icc_profile());
+  ///   Ok(img)
+  /// };
+  /// let comps = [200, 10, 20, 30]
+  ///   .into_iter()
+  ///   .map(|v| ComponentData::new(vec![v; 4], 8))
+  ///   .collect();
+  /// let img = Image::from_components(2, 2, ColorSpace::SRGB, comps)?;
+  /// let jp2 = img.to_bytes(J2KFormat::JP2, EncodeParameters::default())?;
+  /// // Components stored as `[A, R, G, B]`.
+  /// let cdef = [0, 4, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 2, 0, 0, 0, 2, 0, 3, 0, 0, 0, 3];
+  /// let img = decode(&add_header_boxes(&jp2, &[new_box(b"cdef", &cdef)]))?;
+  /// assert_eq!(img.get_pixels(None)?.data, ImagePixelData::Rgba8([10, 20, 30, 200].repeat(4)));
+  ///
+  /// // A 2 entry (red, blue) palette with a component mapping.
+  /// let indices = ComponentData::new(vec![0, 1, 1, 0], 1);
+  /// let img = Image::from_components(4, 1, ColorSpace::SRGB, vec![indices])?;
+  /// let jp2 = img.to_bytes(J2KFormat::JP2, EncodeParameters::default())?;
+  /// let pclr = new_box(b"pclr", &[0, 2, 3, 7, 7, 7, 255, 0, 0, 0, 0, 255]);
+  /// let cmap = new_box(b"cmap", &[0, 0, 1, 0, 0, 0, 1, 1, 0, 0, 1, 2]);
+  /// let img = decode(&add_header_boxes(&jp2, &[pclr, cmap]))?;
+  /// let expected = ImagePixelData::Rgb8(vec![255, 0, 0, 0, 0, 255, 0, 0, 255, 255, 0, 0]);
+  /// assert_eq!(img.get_pixels(None)?.data, expected);
+  ///
+  /// // `file7.jp2` has an ICC profile.
+  /// let img = decode(&std::fs::read("samples/file7.jp2")?)?;
+  /// assert!(img.has_icc_profile());
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn from_bytes_with_deadline(
+    buf: &[u8],
+    params: DecodeParameters,
+    deadline: Instant,
+  ) -> Result<Self> {
+    let stream = Stream::from_bytes(buf)?;
+    Self::from_stream_tiles(stream, params, || {
+      if Instant::now() >= deadline {
+        Err(Error::CodecError("decode deadline exceeded".into()))
+      } else {
+        Ok(())
+      }
+    })
+  }
+
   /// Load a Jpeg 2000 image from file.  It will detect the J2K format.
+  ///
+  /// On Unix the path doesn't need to be valid UTF-8.  On Windows it must be valid UTF-8.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> anyhow::Result<()> {
+  /// # #[cfg(unix)]
+  /// # {
+  /// use std::os::unix::ffi::OsStrExt;
+  ///
+  /// let dir = std::env::temp_dir().join(format!("jpeg2k-{}", std::process::id()));
+  /// std::fs::create_dir_all(&dir)?;
+  /// let path = dir.join(std::ffi::OsStr::from_bytes(b"non-utf8-\xff.jp2"));
+  /// std::fs::copy("samples/file6.jp2", &path)?;
+  /// let img = Image::from_file(&path)?;
+  /// assert_eq!((img.width(), img.height()), (768, 512));
+  /// std::fs::remove_dir_all(&dir)?;
+  /// # }
+  /// # Ok(())
+  /// # }
+  /// ```
   #[cfg(feature = "file-io")]
   pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
     let stream = Stream::from_file(path)?;
@@ -209,30 +1184,359 @@ impl Image {
     Self::from_stream(stream, params)
   }
 
+  /// Load a Jpeg 2000 image from bytes, reporting which layer failed to load.
+  ///
+  /// For JP2 files the container is checked first and the contiguous codestream box
+  /// (`jp2c`) is located.  If the file fails to decode, the codestream is decoded on its
+  /// own to find out if the container or the codestream is broken.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # use jpeg2k::error::LayeredError;
+  /// # fn main() -> anyhow::Result<()> {
+  /// let buf = std::fs::read("samples/file6.jp2")?;
+  /// assert!(Image::from_bytes_diagnostic(&buf, DecodeParameters::new()).is_ok());
+  ///
+  /// // Valid container, broken codestream: replace the codestream `SIZ` marker.
+  /// let jp2c = buf.windows(4).position(|w| w == b"jp2c").unwrap() + 4;
+  /// let mut bad = buf.clone();
+  /// bad[jp2c + 2..jp2c + 4].copy_from_slice(&[0xff, 0x90]);
+  /// let err = Image::from_bytes_diagnostic(&bad, DecodeParameters::new()).unwrap_err();
+  /// assert!(matches!(err, LayeredError::Codestream(_)), "{err}");
+  ///
+  /// // Broken container: no codestream box.
+  /// let mut bad = buf.clone();
+  /// bad[jp2c - 4..jp2c].copy_from_slice(b"xxxx");
+  /// let err = Image::from_bytes_diagnostic(&bad, DecodeParameters::new()).unwrap_err();
+  /// assert!(matches!(err, LayeredError::Container(_)), "{err}");
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn from_bytes_diagnostic(buf: &[u8], params: DecodeParameters) -> Result<Self, LayeredError> {
+    let format = j2k_detect_format(buf).map_err(LayeredError::Container)?;
+    let codestream = if format.is_container() {
+      find_jp2_codestream(buf).ok_or_else(|| {
+        LayeredError::Container(Error::CodecError(
+          "JP2 file has no codestream (jp2c) box".into(),
+        ))
+      })?
+    } else {
+      buf
+    };
+    read_siz(codestream).map_err(LayeredError::Codestream)?;
+    match Self::from_bytes_with(buf, params) {
+      Ok(img) => Ok(img),
+      Err(err) if format.is_container() => {
+        // Decode the codestream without the container.
+        match Self::from_bytes_with(codestream, params) {
+          Ok(_) => Err(LayeredError::Container(err)),
+          Err(err) => Err(LayeredError::Codestream(err)),
+        }
+      }
+      Err(err) => Err(LayeredError::Codestream(err)),
+    }
+  }
+
   /// Load a Jpeg 2000 image from file.  It will detect the J2K format.
+  ///
+  /// The format is detected from the contents of the file, the file extension is only
+  /// used if the contents are inconclusive.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> anyhow::Result<()> {
+  /// // A raw codestream with a `.jp2` extension.
+  /// let path = std::env::temp_dir().join(format!("jpeg2k-{}-j2k32.jp2", std::process::id()));
+  /// std::fs::copy("samples/j2k32.j2k", &path)?;
+  /// let img = Image::from_file_with(&path, DecodeParameters::new());
+  /// std::fs::remove_file(&path)?;
+  /// let img = img?;
+  /// assert_eq!((img.width(), img.height()), (256, 256));
+  /// let expected = Image::from_file("samples/j2k32.j2k")?;
+  /// assert_eq!(img.components()[0].data(), expected.components()[0].data());
+  /// # Ok(())
+  /// # }
+  /// ```
   #[cfg(feature = "file-io")]
   pub fn from_file_with<P: AsRef<Path>>(path: P, params: DecodeParameters) -> Result<Self> {
     let stream = Stream::from_file(path)?;
     Self::from_stream(stream, params)
   }
 
+  /// Load a Jpeg 2000 image from file using the given format.  The file extension is ignored.
+  ///
+  /// See [`DecodeParameters::auto_format_fallback`] to retry with another format if
+  /// decoding fails.
+  #[cfg(feature = "file-io")]
+  pub fn from_file_as<P: AsRef<Path>>(
+    path: P,
+    format: J2KFormat,
+    params: DecodeParameters,
+  ) -> Result<Self> {
+    let path = path.as_ref();
+    let stream = Stream::from_file_as(path, format)?;
+    let err = match Self::from_stream(stream, params) {
+      Ok(img) => return Ok(img),
+      Err(err) => err,
+    };
+    let fallback = match format.fallback() {
+      Some(fallback) if params.is_format_fallback() => fallback,
+      _ => return Err(err),
+    };
+    log::debug!("Failed to decode as {format:?} ({err}), retrying as {fallback:?}");
+    match Stream::from_file_as(path, fallback).and_then(|stream| Self::from_stream(stream, params))
+    {
+      Ok(img) => {
+        log::info!("Decoded as {fallback:?} after failing to decode as {format:?}");
+        Ok(img)
+      }
+      // Report the error for the given format.
+      Err(_) => Err(err),
+    }
+  }
+
+  /// Decode all resolution levels of a Jpeg 2000 image.  It will detect the J2K format.
+  ///
+  /// The images are ordered from finest (full resolution, `reduce = 0`) to coarsest
+  /// (`reduce = num_resolutions - 1`).  Each image is half the size of the previous one.
+  /// The `reduce` value from `params` is ignored.
+  ///
+  /// The number of resolution levels is taken from the full resolution image.  Each level
+  /// needs its own decode (and header read) since openjpeg can't re-use a decoder after
+  /// decoding.  Coarser levels decode less coefficients, so they are much cheaper than the
+  /// first level.
+  ///
+  /// Memory: openjpeg stores each sample as `i32`, all levels together use about 4/3
+  /// of the memory of the first level.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> anyhow::Result<()> {
+  /// let buf = std::fs::read("samples/j2k32.j2k")?;
+  /// let levels = Image::decode_pyramid(&buf, DecodeParameters::new())?;
+  /// assert_eq!(levels.len(), 6);
+  /// assert_eq!((levels[0].width(), levels[0].height()), (256, 256));
+  /// for pair in levels.windows(2) {
+  ///   assert_eq!(pair[1].width(), pair[0].width().div_ceil(2));
+  ///   assert_eq!(pair[1].height(), pair[0].height().div_ceil(2));
+  /// }
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn decode_pyramid(buf: &[u8], params: DecodeParameters) -> Result<Vec<Self>> {
+    let full = Self::from_bytes_with(buf, params.reduce(0))?;
+    let num_resolutions = full.num_resolutions();
+    if num_resolutions == 0 {
+      return Err(Error::CodecError(
+        "Failed to get number of resolutions".into(),
+      ));
+    }
+    std::iter::once(Ok(full))
+      .chain((1..num_resolutions).map(|reduce| Self::from_bytes_with(buf, params.reduce(reduce))))
+      .collect()
+  }
+
   /// Save image to Jpeg 2000 file.  It will detect the J2K format.
   #[cfg(feature = "file-io")]
   pub fn save_as_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
     let stream = Stream::to_file(path)?;
-    self.to_stream(stream, Default::default())
+    self.to_stream(stream, Default::default())?;
+    Ok(())
   }
 
   /// Save image to Jpeg 2000 file.  It will detect the J2K format.
   #[cfg(feature = "file-io")]
   pub fn save_as_file_with<P: AsRef<Path>>(&self, path: P, params: EncodeParameters) -> Result<()> {
     let stream = Stream::to_file(path)?;
-    self.to_stream(stream, params)
+    self.to_stream(stream, params)?;
+    Ok(())
   }
 
-  fn from_stream(stream: Stream<'_>, mut params: DecodeParameters) -> Result<Self> {
-    let decoder = Decoder::new(stream)?;
-    decoder.setup(&mut params)?;
+  /// Encode the image in `format` to `writer`.
+  ///
+  /// Encoding seeks back to patch the length fields of markers and boxes, so the writer
+  /// must be `Seek`.  The seeks are relative to the writer's position when this is
+  /// called, the image can be appended to other data.  The writer is flushed at the end.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # use jpeg2k::format::J2KFormat;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let img = Image::from_components(64, 64, ColorSpace::Gray, vec![
+  ///   ComponentData::new(vec![128; 64 * 64], 8),
+  /// ])?;
+  /// let params = EncodeParameters::default();
+  /// let mut out = std::io::Cursor::new(b"header".to_vec());
+  /// out.set_position(6);
+  /// img.save_to_writer(&mut out, J2KFormat::JP2, params.clone())?;
+  ///
+  /// let buf = out.into_inner();
+  /// assert_eq!(&buf[6..], img.to_bytes(J2KFormat::JP2, params)?);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn save_to_writer<W: Write + Seek>(
+    &self,
+    mut writer: W,
+    format: J2KFormat,
+    params: EncodeParameters,
+  ) -> Result<()> {
+    let error = Cell::new(None);
+    let res = self
+      .to_stream(Stream::to_writer(&mut writer, &error, format)?, params)
+      .map(drop);
+    if let Some(err) = error.take() {
+      return Err(Error::Other(err.into()));
+    }
+    res?;
+    writer.flush().map_err(|err| Error::Other(err.into()))
+  }
+
+  /// Encode the image to Jpeg 2000 bytes in `format`, consuming the image.
+  ///
+  /// Encoding a borrowed image (`save_as_file`, `to_bytes`) with a single tile needs a copy
+  /// of the samples, because openjpeg encodes a single tile in place in the component data
+  /// (tiled images are copied one tile at a time).  This consumes the image instead and
+  /// gives its samples to openjpeg, the image memory is freed while encoding.
+  /// Prefer this at the end of a decode -> transform -> encode chain to keep the peak
+  /// memory low.
+  ///
+  /// ```rust,no_run
+  /// # use jpeg2k::*;
+  /// use jpeg2k::format::J2KFormat;
+  /// # fn main() -> anyhow::Result<()> {
+  /// // Transcode a J2K codestream to JP2 bytes.
+  /// let img = Image::from_file("image.j2k")?;
+  /// let jp2 = img.into_bytes(J2KFormat::JP2, EncodeParameters::default())?;
+  /// std::fs::write("image.jp2", jp2)?;
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn into_bytes(self, format: J2KFormat, params: EncodeParameters) -> Result<Vec<u8>> {
+    let stream = Stream::to_bytes(format)?;
+    let mut img = self;
+    let mut stream = Self::encode_stream(&mut img, stream, params)?;
+    drop(img);
+    stream
+      .take_bytes()
+      .ok_or_else(|| Error::CodecError("No encoded bytes".into()))
+  }
+
+  /// Encode the image to JP2 bytes, lossless if it fits in `max_bytes`, otherwise lossy.
+  ///
+  /// A "just make it fit" mode for storage budgets.  Without a size cap the image is
+  /// always encoded losslessly.  Otherwise the lossless encoding is used if it isn't
+  /// bigger than `max_bytes`, else the image is encoded lossy with compression ratios
+  /// estimated from the size of the previous attempt.  The size of a lossy encoding can't
+  /// be predicted exactly, so hitting a byte budget takes a couple of encode attempts.
+  /// The biggest (best quality) result under the cap is returned, an error is returned if
+  /// no attempt fits.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let samples = (0..256 * 256).map(|v| (v * 7919) % 256).collect::<Vec<i32>>();
+  /// let img = Image::from_components(256, 256, ColorSpace::Gray, vec![
+  ///   ComponentData::new(samples.clone(), 8),
+  /// ])?;
+  /// // No cap: lossless.
+  /// let buf = img.encode_auto(None)?;
+  /// assert_eq!(Image::from_bytes(&buf)?.components()[0].data(), samples);
+  ///
+  /// // Noisy images don't compress well, lossy is needed to fit in 8KB.
+  /// let buf = img.encode_auto(Some(8 * 1024))?;
+  /// assert!(buf.len() <= 8 * 1024);
+  /// assert_eq!(Image::from_bytes(&buf)?.width(), 256);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn encode_auto(&self, max_bytes: Option<u64>) -> Result<Vec<u8>> {
+    // Number of lossy encode attempts.
+    const MAX_ATTEMPTS: usize = 4;
+
+    let lossless = self.to_bytes(J2KFormat::JP2, EncodeParameters::default())?;
+    let max_bytes = match max_bytes {
+      Some(max_bytes) if lossless.len() as u64 > max_bytes => max_bytes,
+      _ => return Ok(lossless),
+    };
+    // Compression ratio relative to the uncompressed size, as used by openjpeg.
+    let raw_bytes = self
+      .components()
+      .iter()
+      .map(|comp| comp.width() as f64 * comp.height() as f64 * comp.precision() as f64 / 8.0)
+      .sum::<f64>();
+    let mut ratio = raw_bytes / max_bytes as f64;
+    let mut best: Option<Vec<u8>> = None;
+    for _ in 0..MAX_ATTEMPTS {
+      let buf = self.to_bytes(J2KFormat::JP2, EncodeParameters::lossy(ratio))?;
+      let size = buf.len() as f64;
+      if buf.len() as u64 <= max_bytes {
+        if best.as_ref().is_none_or(|best| buf.len() > best.len()) {
+          best = Some(buf);
+        }
+        // Close enough to the cap.
+        if size >= max_bytes as f64 * 0.95 {
+          break;
+        }
+      }
+      // Scale the ratio by how far this attempt is from the cap.
+      ratio *= size / max_bytes as f64;
+    }
+    best
+      .ok_or_else(|| Error::CodecError(format!("Failed to encode the image in {max_bytes} bytes")))
+  }
+
+  /// Encode the image to Jpeg 2000 bytes in `format`.
+  ///
+  /// The image is encoded to an in-memory buffer, nothing is written to disk.  The
+  /// returned buffer has exactly the encoded bytes.  Use `into_bytes` to avoid copying
+  /// the samples when the image isn't needed after encoding.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// use jpeg2k::format::J2KFormat;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let data = (0..64 * 64).map(|v| v % 256).collect::<Vec<i32>>();
+  /// let img = Image::from_components(64, 64, ColorSpace::Gray, vec![
+  ///   ComponentData::new(data.clone(), 8),
+  /// ])?;
+  /// let j2k = img.to_bytes(J2KFormat::J2K, EncodeParameters::default())?;
+  /// // SOC marker.
+  /// assert_eq!(&j2k[0..2], &[0xff, 0x4f]);
+  /// // EOC marker.
+  /// assert_eq!(&j2k[j2k.len() - 2..], &[0xff, 0xd9]);
+  /// assert_eq!(Image::from_bytes(&j2k)?.components()[0].data(), data);
+  ///
+  /// // Tiles are written from the image's samples, one tile at a time.
+  /// let tiled = EncodeParameters::default().tiles(32, 32);
+  /// let j2k = img.to_bytes(J2KFormat::J2K, tiled)?;
+  /// assert_eq!(DumpImage::from_bytes(&j2k)?.num_tiles()?, 4);
+  /// assert_eq!(Image::from_bytes(&j2k)?.components()[0].data(), data);
+  ///
+  /// let jp2 = img.to_bytes(J2KFormat::JP2, EncodeParameters::default())?;
+  /// assert_eq!(&jp2[4..8], b"jP  ");
+  /// assert_eq!(jp2, img.into_bytes(J2KFormat::JP2, EncodeParameters::default())?);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn to_bytes(&self, format: J2KFormat, params: EncodeParameters) -> Result<Vec<u8>> {
+    let mut stream = self.to_stream(Stream::to_bytes(format)?, params)?;
+    stream
+      .take_bytes()
+      .ok_or_else(|| Error::CodecError("No encoded bytes".into()))
+  }
+
+  /// Read only the image header, the components have no data.
+  #[cfg(all(feature = "image", feature = "file-io"))]
+  pub(crate) fn header_from_bytes(buf: &[u8]) -> Result<Self> {
+    let decoder = Decoder::new(Stream::from_bytes(buf)?)?;
+    decoder.setup(&mut DecodeParameters::default())?;
+    decoder.read_header()
+  }
+
+  fn from_stream(stream: Stream<'_>, mut params: DecodeParameters) -> Result<Self> {
+    let decoder = Decoder::new(stream)?;
+    decoder.setup(&mut params)?;
 
     let img = decoder.read_header()?;
 
@@ -243,14 +1547,70 @@ impl Image {
     Ok(img)
   }
 
-  #[cfg(feature = "file-io")]
-  fn to_stream(&self, stream: Stream<'_>, params: EncodeParameters) -> Result<()> {
+  /// Decode tile by tile, `check` is called before each tile.
+  fn from_stream_tiles(
+    stream: Stream<'_>,
+    mut params: DecodeParameters,
+    check: impl FnMut() -> Result<()>,
+  ) -> Result<Self> {
+    let decoder = Decoder::new(stream)?;
+    decoder.setup(&mut params)?;
+
+    let mut img = decoder.read_header()?;
+
+    decoder.set_decode_area(&img, &params)?;
+
+    decoder.decode_tiles(&mut img, check)?;
+
+    Ok(img)
+  }
+
+  /// Encode a borrowed image to `stream`.
+  ///
+  /// Images with more than one tile are written tile by tile, openjpeg copies each tile
+  /// from the image's samples.  A single tile is encoded in place in the component data,
+  /// so it is encoded from a copy of the samples, as are images with a precision override
+  /// (the samples are clamped).
+  fn to_stream<'a>(&self, stream: Stream<'a>, params: EncodeParameters) -> Result<Stream<'a>> {
+    let mut img = self.encode_header()?;
+    let prec = params.get_component_precision()?;
+    if let Some(prec) = prec {
+      img.copy_component_data(self)?;
+      img.override_precision(prec);
+    }
+    let encoder = Encoder::new(stream)?;
+    encoder.setup(params, &img)?;
+
+    if prec.is_some() {
+      encoder.encode(&img)?;
+    } else if encoder.num_tiles() > 1 {
+      encoder.encode_tiles(&img, self)?;
+    } else {
+      img.copy_component_data(self)?;
+      encoder.encode(&img)?;
+    }
+
+    Ok(encoder.into_stream())
+  }
+
+  /// Encode `img` to `stream`.  openjpeg takes ownership of the component data.
+  fn encode_stream<'a>(
+    img: &mut Image,
+    stream: Stream<'a>,
+    params: EncodeParameters,
+  ) -> Result<Stream<'a>> {
+    if let Some(prec) = params.get_component_precision()? {
+      img.override_precision(prec);
+    }
+    img.warn_icc_profile();
+    // The buffer is still freed with the image.
+    unsafe { (*img.as_ptr()).icc_profile_len = 0 };
     let encoder = Encoder::new(stream)?;
-    encoder.setup(params, &self)?;
+    encoder.setup(params, img)?;
 
-    encoder.encode(&self)?;
+    encoder.encode(img)?;
 
-    Ok(())
+    Ok(encoder.into_stream())
   }
 
   fn image(&self) -> &sys::opj_image_t {
@@ -274,15 +1634,27 @@ impl Image {
   }
 
   /// Full resolution image width.  Not reduced by the scaling factor.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> anyhow::Result<()> {
+  /// let mut buf = std::fs::read("samples/p0_03.j2k")?;
+  /// assert_eq!(Image::from_bytes(&buf)?.orig_width(), 256);
+  /// // A malformed header with the image offset (XOsiz) past the image end (Xsiz).
+  /// buf[16..20].copy_from_slice(&300u32.to_be_bytes());
+  /// assert!(Image::from_bytes(&buf).is_err());
+  /// # Ok(())
+  /// # }
+  /// ```
   pub fn orig_width(&self) -> u32 {
     let img = self.image();
-    img.x1 - img.x0
+    img.x1.saturating_sub(img.x0)
   }
 
   /// Full resolution image height.  Not reduced by the scaling factor.
   pub fn orig_height(&self) -> u32 {
     let img = self.image();
-    img.y1 - img.y0
+    img.y1.saturating_sub(img.y0)
   }
 
   /// Decoded image width.  Reduced by the scaling factor.
@@ -303,8 +1675,46 @@ impl Image {
 
   /// Color space.
   pub fn color_space(&self) -> ColorSpace {
-    let img = self.image();
-    img.color_space.into()
+    // Read the raw value, the library might return a value unknown to the Rust enum.
+    let raw = unsafe {
+      ptr::addr_of!((*self.as_ptr()).color_space)
+        .cast::<i32>()
+        .read()
+    };
+    ColorSpace::from_raw(raw)
+  }
+
+  /// Set the image color space.
+  ///
+  /// This only changes the color space tag, the samples aren't converted.
+  pub fn set_color_space(&mut self, color_space: ColorSpace) {
+    unsafe {
+      (*self.as_ptr()).color_space = color_space.into();
+    }
+  }
+
+  /// Change the image color space, builder style.
+  ///
+  /// This only changes the color space tag, the samples aren't converted.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let img = Image::from_components(1, 1, ColorSpace::Unspecified, vec![
+  ///   ComponentData::new(vec![255], 8),
+  ///   ComponentData::new(vec![0], 8),
+  ///   ComponentData::new(vec![0], 8),
+  /// ])?
+  /// .with_color_space(ColorSpace::SRGB);
+  /// assert_eq!(img.color_space(), ColorSpace::SRGB);
+  /// assert_eq!(img.components()[0].data(), [255]);
+  /// # Ok(())
+  /// # }
+  /// ```
+  #[must_use]
+  pub fn with_color_space(mut self, color_space: ColorSpace) -> Self {
+    self.set_color_space(color_space);
+    self
   }
 
   /// Number of components.
@@ -313,16 +1723,322 @@ impl Image {
     img.numcomps
   }
 
+  /// Number of resolution levels in the codestream, read from the header.
+  ///
+  /// Valid `reduce` values ([`DecodeParameters::reduce`]) are `0..num_resolutions`.  With
+  /// different values per component this is the smallest one.  Returns `0` for images
+  /// that weren't loaded from a codestream.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> anyhow::Result<()> {
+  /// // `j2k32.j2k` is 256x256 with 6 resolutions.
+  /// let img = Image::from_file("samples/j2k32.j2k")?;
+  /// assert_eq!(img.num_resolutions(), 6);
+  ///
+  /// let params = DecodeParameters::new().reduce(img.num_resolutions() - 1);
+  /// assert_eq!(Image::from_file_with("samples/j2k32.j2k", params)?.width(), 8);
+  ///
+  /// let params = DecodeParameters::new().reduce(6);
+  /// let err = Image::from_file_with("samples/j2k32.j2k", params).unwrap_err();
+  /// assert_eq!(err.to_string(), "Invalid parameters: reduce 6 must be less than the number of resolutions (6)");
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn num_resolutions(&self) -> u32 {
+    self.num_resolutions
+  }
+
   /// Has ICC Profile.
   pub fn has_icc_profile(&self) -> bool {
     let img = self.image();
-    !img.icc_profile_buf.is_null()
+    !img.icc_profile_buf.is_null() || self.shared_icc_profile.is_some()
+  }
+
+  /// The embedded ICC profile (from the `colr` box of a JP2 file), to use with a color
+  /// management library.
+  ///
+  /// Returns `None` if the image has no ICC profile.  openjpeg can't write ICC profiles,
+  /// so the profile isn't kept when encoding the image.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// use jpeg2k::format::J2KFormat;
+  /// # fn main() -> anyhow::Result<()> {
+  /// let img = Image::from_file("samples/file7.jp2")?;
+  /// let icc = img.icc_profile().expect("ICC profile");
+  /// assert_eq!(&icc[36..40], b"acsp");
+  ///
+  /// let jp2 = img.to_bytes(J2KFormat::JP2, EncodeParameters::default())?;
+  /// assert_eq!(Image::from_bytes(&jp2)?.icc_profile(), None);
+  ///
+  /// assert_eq!(Image::from_file("samples/file6.jp2")?.icc_profile(), None);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn icc_profile(&self) -> Option<&[u8]> {
+    if let Some(icc) = &self.shared_icc_profile {
+      return Some(icc);
+    }
+    let img = self.image();
+    if img.icc_profile_buf.is_null() || img.icc_profile_len == 0 {
+      return None;
+    }
+    Some(unsafe { std::slice::from_raw_parts(img.icc_profile_buf, img.icc_profile_len as usize) })
+  }
+
+  /// The ICC profile to share with images cropped from this one.
+  fn share_icc_profile(&self) -> Option<Rc<[u8]>> {
+    self
+      .shared_icc_profile
+      .clone()
+      .or_else(|| self.icc_profile().map(Rc::from))
+  }
+
+  /// Channel definitions from the `cdef` box of the JP2 file the image was loaded from.
+  ///
+  /// openjpeg applies the channel definitions when decoding (reordering the components
+  /// and marking the alpha components), this returns the definitions as stored in the
+  /// file.  Returns `None` for raw J2K codestreams, JP2 files without a `cdef` box and
+  /// images that weren't decoded.
+  ///
+  /// The decoded components are in the order given by the associations: color channels
+  /// first (`association` `1` is the first component), so [`get_pixels`](Self::get_pixels)
+  /// returns RGBA pixels for a file with components stored as `[A, R, G, B]`.  The
+  /// components marked as opacity are the image's alpha components.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # use jpeg2k::format::{ChannelDef, ChannelType, J2KFormat};
+  /// # fn new_box(box_type: &[u8], content: &[u8]) -> Vec<u8> {
+  /// #   let len = (content.len() as u32 + 8).to_be_bytes();
+  /// #   [&len[..], box_type, content].concat()
+  /// # }
+  /// # // Append a box to the `jp2h` box.
+  /// # fn add_header_box(jp2: &[u8], new: Vec<u8>) -> Vec<u8> {
+  /// #   let (mut out, mut offset) = (Vec::new(), 0);
+  /// #   while offset < jp2.len() {
+  /// #     let len = u32::from_be_bytes(jp2[offset..offset + 4].try_into().unwrap()) as usize;
+  /// #     let (box_type, content) = (&jp2[offset + 4..offset + 8], &jp2[offset + 8..offset + len]);
+  /// #     if box_type == b"jp2h" {
+  /// #       out.extend(new_box(box_type, &[content, &new].concat()));
+  /// #     } else {
+  /// #       out.extend_from_slice(&jp2[offset..offset + len]);
+  /// #     }
+  /// #     offset += len;
+  /// #   }
+  /// #   out
+  /// # }
+  /// # fn main() -> anyhow::Result<()> {
+  /// let comps = [200, 10, 20, 30]
+  ///   .into_iter()
+  ///   .map(|v| ComponentData::new(vec![v; 4], 8))
+  ///   .collect();
+  /// let img = Image::from_components(2, 2, ColorSpace::SRGB, comps)?;
+  /// let jp2 = img.to_bytes(J2KFormat::JP2, EncodeParameters::default())?;
+  /// assert_eq!(Image::from_bytes(&jp2)?.channel_definitions(), None);
+  /// // Raw codestreams don't have a `cdef` box.
+  /// assert_eq!(Image::from_file("samples/p0_03.j2k")?.channel_definitions(), None);
+  ///
+  /// // openjpeg writes a `cdef` box for images with one alpha component.
+  /// let comps = (0..4)
+  ///   .map(|c| ComponentData::new(vec![c * 50; 64 * 64], 8).alpha(c == 3))
+  ///   .collect();
+  /// let img = Image::from_components(64, 64, ColorSpace::SRGB, comps)?;
+  /// let buf = img.into_bytes(J2KFormat::JP2, EncodeParameters::default())?;
+  /// let img = Image::from_bytes(&buf)?;
+  /// let defs = img.channel_definitions().unwrap();
+  /// assert_eq!(defs.len(), 4);
+  /// assert_eq!(defs[0], ChannelDef { channel: 0, type_: ChannelType::Color, association: 1 });
+  /// assert_eq!(defs[3], ChannelDef { channel: 3, type_: ChannelType::Opacity, association: 0 });
+  ///
+  /// // Components stored as `[A, R, G, B]`.
+  /// let cdef = [0, 4, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 2, 0, 0, 0, 2, 0, 3, 0, 0, 0, 3];
+  /// let jp2 = add_header_box(&jp2, new_box(b"cdef", &cdef));
+  /// let dump = DumpImage::from_bytes(&jp2)?;
+  /// let defs = dump.img.channel_definitions().unwrap();
+  /// assert_eq!(defs[0], ChannelDef { channel: 0, type_: ChannelType::Opacity, association: 0 });
+  /// // Decoding again gives the same components.
+  /// for _ in 0..2 {
+  ///   let img = dump.decode_region(DecodeArea::new(0, 0, 2, 2))?;
+  ///   assert!(img.components()[3].is_alpha());
+  ///   let pixels = img.get_pixels(None)?;
+  ///   assert_eq!(pixels.data, ImagePixelData::Rgba8([10, 20, 30, 200].repeat(4)));
+  /// }
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn channel_definitions(&self) -> Option<&[ChannelDef]> {
+    self.channel_defs.as_deref()
+  }
+
+  /// Contents of the XML (`xml `) boxes of the JP2 file the image was loaded from.
+  ///
+  /// openjpeg skips these boxes, they are read from the input bytes or file.  XML boxes
+  /// nested in association (`asoc`) boxes are included, GMLJP2 files store the GML there.
+  /// Empty for raw J2K codestreams and images that weren't decoded.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> anyhow::Result<()> {
+  /// let new_box = |box_type: &[u8], content: &[u8]| {
+  ///   let len = (content.len() as u32 + 8).to_be_bytes();
+  ///   [&len[..], box_type, content].concat()
+  /// };
+  /// let mut buf = std::fs::read("samples/file6.jp2")?;
+  /// assert!(Image::from_bytes(&buf)?.xml_boxes().is_empty());
+  ///
+  /// buf.extend(new_box(b"xml ", b"<meta/>"));
+  /// let gml = [new_box(b"lbl ", b"gml.data"), new_box(b"xml ", b"<gml/>")].concat();
+  /// buf.extend(new_box(b"asoc", &gml));
+  /// let img = Image::from_bytes(&buf)?;
+  /// assert_eq!(img.xml_boxes(), [b"<meta/>".to_vec(), b"<gml/>".to_vec()]);
+  ///
+  /// let path = std::env::temp_dir().join(format!("jpeg2k-xml-{}.jp2", std::process::id()));
+  /// std::fs::write(&path, &buf)?;
+  /// let img = Image::from_file(&path);
+  /// std::fs::remove_file(&path)?;
+  /// assert_eq!(img?.xml_boxes().len(), 2);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn xml_boxes(&self) -> &[Vec<u8>] {
+    &self.xml_boxes
+  }
+
+  /// The UUID (`uuid`) boxes of the JP2 file the image was loaded from.
+  ///
+  /// Returns the UUID and the data of each top-level box, in file order.  EXIF metadata,
+  /// GeoJP2 and XMP are stored in `uuid` boxes, identified by their UUID.  Like the XML
+  /// boxes, they are read from the input bytes or file.  Empty for raw J2K codestreams
+  /// and images that weren't decoded.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> anyhow::Result<()> {
+  /// let mut buf = std::fs::read("samples/file6.jp2")?;
+  /// assert!(Image::from_bytes(&buf)?.uuid_boxes().is_empty());
+  ///
+  /// let uuid = *b"JpgTiffExif->JP2";
+  /// let content = [&uuid[..], b"Exif\0\0"].concat();
+  /// buf.extend((content.len() as u32 + 8).to_be_bytes());
+  /// buf.extend(b"uuid");
+  /// buf.extend(&content);
+  /// let img = Image::from_bytes(&buf)?;
+  /// assert_eq!(img.uuid_boxes(), [(uuid, b"Exif\0\0".to_vec())]);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn uuid_boxes(&self) -> &[(Uuid, Vec<u8>)] {
+    &self.uuid_boxes
+  }
+
+  /// The data of the first `uuid` box with UUID `uuid`.
+  #[cfg(feature = "geojp2")]
+  pub(crate) fn uuid_box(&self, uuid: &Uuid) -> Option<&[u8]> {
+    self
+      .uuid_boxes
+      .iter()
+      .find(|(id, _)| id == uuid)
+      .map(|(_, data)| data.as_slice())
+  }
+
+  /// The JP2 file the image was loaded from has a palette (`pclr` box).
+  ///
+  /// Indexed-color images have one index component that is mapped through the palette
+  /// to the color (and alpha) channels.  openjpeg applies the palette when decoding if
+  /// the file also has a component mapping (`cmap`) box, as the standard requires, then
+  /// the decoded image has a component for each palette column.  Otherwise the decoded
+  /// image only has the index component and [`get_pixels`](Self::get_pixels) expands it
+  /// with the palette.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # use jpeg2k::format::J2KFormat;
+  /// # fn new_box(box_type: &[u8], content: &[u8]) -> Vec<u8> {
+  /// #   let len = (content.len() as u32 + 8).to_be_bytes();
+  /// #   [&len[..], box_type, content].concat()
+  /// # }
+  /// # // Append a 2 entry (red, blue) `pclr` box to the `jp2h` box.
+  /// # fn add_palette(jp2: &[u8], cmap: bool) -> Vec<u8> {
+  /// #   let (mut out, mut offset) = (Vec::new(), 0);
+  /// #   while offset < jp2.len() {
+  /// #     let len = u32::from_be_bytes(jp2[offset..offset + 4].try_into().unwrap()) as usize;
+  /// #     let (box_type, content) = (&jp2[offset + 4..offset + 8], &jp2[offset + 8..offset + len]);
+  /// #     if box_type == b"jp2h" {
+  /// #       let mut content = content.to_vec();
+  /// #       content.extend(new_box(b"pclr", &[0, 2, 3, 7, 7, 7, 255, 0, 0, 0, 0, 255]));
+  /// #       if cmap {
+  /// #         content.extend(new_box(b"cmap", &[0, 0, 1, 0, 0, 0, 1, 1, 0, 0, 1, 2]));
+  /// #       }
+  /// #       out.extend(new_box(box_type, &content));
+  /// #     } else {
+  /// #       out.extend_from_slice(&jp2[offset..offset + len]);
+  /// #     }
+  /// #     offset += len;
+  /// #   }
+  /// #   out
+  /// # }
+  /// # fn main() -> anyhow::Result<()> {
+  /// let indices = ComponentData::new(vec![0, 1, 1, 0], 1);
+  /// let img = Image::from_components(4, 1, ColorSpace::SRGB, vec![indices])?;
+  /// let jp2 = img.to_bytes(J2KFormat::JP2, EncodeParameters::default())?;
+  /// assert!(!Image::from_bytes(&jp2)?.has_palette());
+  ///
+  /// let expected = ImagePixelData::Rgb8(vec![255, 0, 0, 0, 0, 255, 0, 0, 255, 255, 0, 0]);
+  /// // With a `cmap` box openjpeg applies the palette.
+  /// let img = Image::from_bytes(&add_palette(&jp2, true))?;
+  /// assert!(img.has_palette());
+  /// assert_eq!(img.num_components(), 3);
+  /// assert_eq!(img.get_pixels(None)?.data, expected);
+  ///
+  /// // Without it the image has the index component.
+  /// let img = Image::from_bytes(&add_palette(&jp2, false))?;
+  /// assert!(img.has_palette());
+  /// assert_eq!(img.num_components(), 1);
+  /// assert_eq!(img.get_pixels(None)?.data, expected);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn has_palette(&self) -> bool {
+    self.palette.is_some()
+  }
+
+  /// The image components with the palette applied, if openjpeg didn't apply it.
+  ///
+  /// The first component holds the palette indices, each palette column becomes a
+  /// component.  Indices outside the palette use the last entry.
+  fn expand_palette(&self) -> Result<Option<Image>> {
+    let palette = match &self.palette {
+      Some(palette) if !self.palette_applied && !palette.entries.is_empty() => palette,
+      _ => return Ok(None),
+    };
+    let index = match self.components().first() {
+      Some(index) => index,
+      None => return Ok(None),
+    };
+    let last = palette.entries.len() - 1;
+    let comps = palette
+      .precisions
+      .iter()
+      .zip(&palette.signed)
+      .enumerate()
+      .map(|(column, (&precision, &signed))| {
+        let data = index
+          .data()
+          .iter()
+          .map(|&idx| palette.entries[(idx.max(0) as usize).min(last)][column])
+          .collect();
+        ComponentData::new(data, precision).signed(signed)
+      })
+      .collect();
+    Image::from_components(index.width(), index.height(), self.color_space(), comps).map(Some)
   }
 
   fn component_dimensions(&self) -> Option<(u32, u32)> {
     self
       .components()
-      .get(0)
+      .first()
       .map(|comp| (comp.width(), comp.height()))
   }
 
@@ -333,183 +2049,1957 @@ impl Image {
     unsafe { std::slice::from_raw_parts(img.comps as *mut ImageComponent, numcomps as usize) }
   }
 
-  /// Convert image components into pixels.
+  /// Consume the image and return the samples of each component.
   ///
-  /// `alpha_default` - The default value for the alpha channel if there is no alpha component.
-  pub fn get_pixels(&self, alpha_default: Option<u32>) -> Result<ImageData> {
-    let comps = self.components();
-    let (width, height) = comps
-      .get(0)
-      .map(|c| (c.width(), c.height()))
-      .ok_or_else(|| Error::UnsupportedComponentsError(0))?;
-    let max_prec = comps
+  /// The samples are copied (`width * height * 4` bytes per component), openjpeg's
+  /// buffers are freed when this returns.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let img = Image::from_components(2, 1, ColorSpace::Unspecified, vec![
+  ///   ComponentData::new(vec![1, 2], 8),
+  ///   ComponentData::new(vec![3, 4], 8),
+  /// ])?;
+  /// assert_eq!(img.components()[1].to_vec(), [3, 4]);
+  /// assert_eq!(img.into_component_vecs(), [vec![1, 2], vec![3, 4]]);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn into_component_vecs(self) -> Vec<Vec<i32>> {
+    self.components().iter().map(|c| c.to_vec()).collect()
+  }
+
+  /// Image component `index`, `None` if out of bounds.
+  pub fn component(&self, index: usize) -> Option<&ImageComponent> {
+    self.components().get(index)
+  }
+
+  /// Mutable image component `index`, `None` if out of bounds.
+  ///
+  /// The image can't be used while the component is borrowed.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// use jpeg2k::format::J2KFormat;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let data = (0..64 * 64).map(|v| v % 256).collect::<Vec<i32>>();
+  /// let mut img = Image::from_components(64, 64, ColorSpace::SRGB, vec![
+  ///   ComponentData::new(data.clone(), 8);
+  ///   3
+  /// ])?;
+  /// // Fix a dead pixel in the green band.
+  /// let green = img.component_mut(1).unwrap();
+  /// green.data_mut()[65] = 42;
+  /// assert!(img.component_mut(3).is_none());
+  ///
+  /// let img = Image::from_bytes(&img.to_bytes(J2KFormat::J2K, EncodeParameters::default())?)?;
+  /// assert_eq!(img.component(0).unwrap().data(), data);
+  /// assert_eq!(img.component(1).unwrap().data()[65], 42);
+  /// assert_eq!(img.component(2).unwrap().data(), data);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn component_mut(&mut self, index: usize) -> Option<&mut ImageComponent> {
+    self.components_mut().get_mut(index)
+  }
+
+  pub(crate) fn components_mut(&mut self) -> &mut [ImageComponent] {
+    let img = self.image();
+    let numcomps = img.numcomps;
+    unsafe { std::slice::from_raw_parts_mut(img.comps as *mut ImageComponent, numcomps as usize) }
+  }
+
+  /// New image with the same area and color space, and `numcomps` components without data.
+  ///
+  /// The ICC profile is moved to the new image, this is used to replace `self` with an image
+  /// with different components.
+  fn take_header(&mut self, numcomps: usize) -> Result<Self> {
+    let mut params = vec![unsafe { std::mem::zeroed::<sys::opj_image_cmptparm_t>() }; numcomps];
+    let mut img = Self::new(unsafe {
+      sys::opj_image_tile_create(
+        numcomps as u32,
+        params.as_mut_ptr(),
+        self.image().color_space,
+      )
+    })?;
+    let src = unsafe { &mut *self.as_ptr() };
+    let dst = unsafe { &mut *img.as_ptr() };
+    (dst.x0, dst.y0, dst.x1, dst.y1) = (src.x0, src.y0, src.x1, src.y1);
+    dst.icc_profile_buf = std::mem::replace(&mut src.icc_profile_buf, ptr::null_mut());
+    dst.icc_profile_len = std::mem::take(&mut src.icc_profile_len);
+    img.shared_icc_profile = self.shared_icc_profile.take();
+    Ok(img)
+  }
+
+  /// New image with the same header (area, color space and components) but without
+  /// component data, to decode another area of the codestream into.
+  ///
+  /// The ICC profile isn't copied.
+  pub(crate) fn header_copy(&self) -> Result<Self> {
+    let mut params = self
+      .components()
       .iter()
-      .fold(std::u32::MIN, |max, c| max.max(c.precision()));
-    let has_alpha = comps.iter().any(|c| c.is_alpha());
-    let format;
+      .map(|comp| sys::opj_image_cmptparm_t {
+        dx: comp.0.dx,
+        dy: comp.0.dy,
+        w: comp.0.w,
+        h: comp.0.h,
+        x0: comp.0.x0,
+        y0: comp.0.y0,
+        prec: comp.0.prec,
+        bpp: comp.0.bpp,
+        sgnd: comp.0.sgnd,
+      })
+      .collect::<Vec<_>>();
+    let src = self.image();
+    let mut img = Self::new(unsafe {
+      sys::opj_image_tile_create(params.len() as u32, params.as_mut_ptr(), src.color_space)
+    })?;
+    let dst = unsafe { &mut *img.as_ptr() };
+    (dst.x0, dst.y0, dst.x1, dst.y1) = (src.x0, src.y0, src.x1, src.y1);
+    for (dst, comp) in img.components_mut().iter_mut().zip(self.components()) {
+      dst.0.alpha = comp.0.alpha;
+      dst.0.factor = comp.0.factor;
+    }
+    img.channel_defs = self.channel_defs.clone();
+    img.palette = self.palette.clone();
+    img.palette_applied = self.palette_applied;
+    img.xml_boxes = self.xml_boxes.clone();
+    img.uuid_boxes = self.uuid_boxes.clone();
+    img.num_resolutions = self.num_resolutions;
+    Ok(img)
+  }
 
-    // Check for support color space.
-    match self.color_space() {
-      ColorSpace::Unknown | ColorSpace::Unspecified => {
-        // Assume either Grey/RGB/RGBA based on number of components.
-      }
-      ColorSpace::SRGB | ColorSpace::Gray => (),
-      cs => {
-        return Err(Error::UnsupportedColorSpaceError(cs));
-      }
+  /// Remove all alpha components.
+  ///
+  /// Does nothing if there are no components flagged as alpha.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # use jpeg2k::format::J2KFormat;
+  /// # fn main() -> anyhow::Result<()> {
+  /// // `j2k32.j2k` has 3 signed 8bit components.
+  /// let orig = Image::from_file("samples/j2k32.j2k")?;
+  /// let samples = |img: &Image| img.components().iter().map(|c| c.data().to_vec()).collect::<Vec<_>>();
+  /// let round_trip = |img: &Image| -> anyhow::Result<Image> {
+  ///   Ok(Image::from_bytes(&img.to_bytes(J2KFormat::JP2, EncodeParameters::default())?)?)
+  /// };
+  ///
+  /// // openjpeg only writes the alpha channel definition for known color spaces.
+  /// let mut img = Image::from_file("samples/j2k32.j2k")?.with_color_space(ColorSpace::SRGB);
+  /// img.add_alpha(100)?;
+  /// let mut decoded = round_trip(&img)?;
+  /// assert_eq!(decoded.num_components(), 4);
+  /// assert!(decoded.components()[3].is_alpha());
+  /// assert!(decoded.components()[3].data().iter().all(|&a| a == 100));
+  /// assert_eq!(samples(&decoded)[..3], samples(&orig));
+  ///
+  /// decoded.strip_alpha()?;
+  /// let decoded = round_trip(&decoded)?;
+  /// assert_eq!(decoded.num_components(), 3);
+  /// assert_eq!(samples(&decoded), samples(&orig));
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn strip_alpha(&mut self) -> Result<()> {
+    let keep = self
+      .components()
+      .iter()
+      .enumerate()
+      .filter(|(_, c)| !c.is_alpha())
+      .map(|(idx, _)| idx)
+      .collect::<Vec<_>>();
+    if keep.len() == self.components().len() {
+      return Ok(());
+    }
+    if keep.is_empty() {
+      return Err(Error::UnsupportedComponentsError(0));
     }
+    let mut img = self.take_header(keep.len())?;
+    let comps = self.components_mut();
+    for (dst, idx) in img.components_mut().iter_mut().zip(keep) {
+      // Move the component data.
+      dst.0 = comps[idx].0;
+      comps[idx].0.data = ptr::null_mut();
+    }
+    *self = img;
+    Ok(())
+  }
 
-    let data = match (comps, has_alpha, max_prec) {
-      ([r], _, 1..=8) => {
-        if let Some(alpha) = alpha_default {
-          format = ImageFormat::La8;
-          ImagePixelData::La8(r.data_u8().flat_map(|r| [r, alpha as u8]).collect())
-        } else {
-          format = ImageFormat::L8;
-          ImagePixelData::L8(r.data_u8().map(|r| r).collect())
-        }
-      }
-      ([r], _, 9..=16) => {
-        if let Some(alpha) = alpha_default {
-          format = ImageFormat::La16;
-          ImagePixelData::La16(r.data_u16().flat_map(|r| [r, alpha as u16]).collect())
-        } else {
-          format = ImageFormat::L16;
-          ImagePixelData::L16(r.data_u16().collect())
-        }
-      }
-      ([r, a], true, 1..=8) => {
-        format = ImageFormat::La8;
-        ImagePixelData::La8(
-          r.data_u8()
-            .zip(a.data_u8())
-            .flat_map(|(r, a)| [r, a])
-            .collect(),
-        )
-      }
-      ([r, a], true, 9..=16) => {
-        format = ImageFormat::La16;
-        ImagePixelData::La16(
-          r.data_u16()
-            .zip(a.data_u16())
-            .flat_map(|(r, a)| [r, a])
-            .collect(),
-        )
-      }
-      ([r, g, b], false, 1..=8) => {
-        if let Some(alpha) = alpha_default {
-          format = ImageFormat::Rgba8;
-          ImagePixelData::Rgba8(
-            r.data_u8()
-              .zip(g.data_u8().zip(b.data_u8()))
-              .flat_map(|(r, (g, b))| [r, g, b, alpha as u8])
-              .collect(),
-          )
-        } else {
-          format = ImageFormat::Rgb8;
-          ImagePixelData::Rgb8(
-            r.data_u8()
-              .zip(g.data_u8().zip(b.data_u8()))
-              .flat_map(|(r, (g, b))| [r, g, b])
-              .collect(),
-          )
-        }
-      }
-      ([r, g, b], false, 9..=16) => {
-        if let Some(alpha) = alpha_default {
-          format = ImageFormat::Rgba16;
-          ImagePixelData::Rgba16(
-            r.data_u16()
-              .zip(g.data_u16().zip(b.data_u16()))
-              .flat_map(|(r, (g, b))| [r, g, b, alpha as u16])
-              .collect(),
-          )
-        } else {
-          format = ImageFormat::Rgb16;
-          ImagePixelData::Rgb16(
-            r.data_u16()
-              .zip(g.data_u16().zip(b.data_u16()))
-              .flat_map(|(r, (g, b))| [r, g, b])
-              .collect(),
-          )
-        }
-      }
-      ([r, g, b, a], _, 1..=8) => {
-        format = ImageFormat::Rgba8;
-        ImagePixelData::Rgba8(
-          r.data_u8()
-            .zip(g.data_u8().zip(b.data_u8().zip(a.data_u8())))
-            .flat_map(|(r, (g, (b, a)))| [r, g, b, a])
-            .collect(),
-        )
-      }
-      ([r, g, b, a], _, 9..=16) => {
-        format = ImageFormat::Rgba16;
-        ImagePixelData::Rgba16(
-          r.data_u16()
-            .zip(g.data_u16().zip(b.data_u16().zip(a.data_u16())))
-            .flat_map(|(r, (g, (b, a)))| [r, g, b, a])
-            .collect(),
-        )
-      }
-      _ => {
-        return Err(Error::UnsupportedComponentsError(self.num_components()));
+  /// Append a constant alpha component.
+  ///
+  /// The alpha component has the same size and precision as the first component.
+  /// `value` must fit in that precision (for example `0..=255` for 8bit components).
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let gray = (0..16 * 16).map(|i| i % 256).collect();
+  /// let mut img = Image::from_components(16, 16, ColorSpace::Gray, vec![ComponentData::new(gray, 8)])?;
+  /// img.add_alpha(255)?;
+  /// assert_eq!(img.num_components(), 2);
+  /// assert!(img.channel_info().alpha);
+  /// img.strip_alpha()?;
+  /// assert_eq!(img.num_components(), 1);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn add_alpha(&mut self, value: u16) -> Result<()> {
+    if self.components().iter().any(|c| c.is_alpha()) {
+      return Err(Error::InvalidComponentError(
+        "image already has an alpha component".into(),
+      ));
+    }
+    let first = self
+      .components()
+      .first()
+      .ok_or(Error::UnsupportedComponentsError(0))?
+      .0;
+    if first.prec < 16 && value as u32 > (1u32 << first.prec) - 1 {
+      return Err(Error::InvalidComponentError(format!(
+        "alpha value {value} doesn't fit in {} bits",
+        first.prec
+      )));
+    }
+    let alpha = ImageComponent(first);
+    let len = alpha
+      .data_len()
+      .ok_or_else(|| Error::InvalidComponentError("alpha component is too large".into()))?;
+    let data = unsafe { sys::opj_image_data_alloc(len * std::mem::size_of::<i32>()) as *mut i32 };
+    if data.is_null() {
+      return Err(Error::NullPointerError(
+        "Image: failed to allocate alpha data",
+      ));
+    }
+    let mut alpha = sys::opj_image_comp_t {
+      data,
+      alpha: 1,
+      sgnd: 0,
+      ..first
+    };
+    unsafe { std::slice::from_raw_parts_mut(data, len) }.fill(value as i32);
+
+    let numcomps = self.components().len();
+    let mut img = match self.take_header(numcomps + 1) {
+      Ok(img) => img,
+      Err(err) => {
+        unsafe { sys::opj_image_data_free(data as *mut _) };
+        return Err(err);
       }
     };
-    Ok(ImageData {
-      width,
-      height,
-      format,
-      data,
-    })
+    let comps = self.components_mut();
+    let (dst, dst_alpha) = img.components_mut().split_at_mut(numcomps);
+    for (dst, src) in dst.iter_mut().zip(comps) {
+      // Move the component data.
+      dst.0 = src.0;
+      src.0.data = ptr::null_mut();
+    }
+    std::mem::swap(&mut dst_alpha[0].0, &mut alpha);
+    *self = img;
+    Ok(())
   }
-}
-
-/// Try to convert a loaded Jpeg 2000 image into a `image::DynamicImage`.
-#[cfg(feature = "image")]
-impl TryFrom<&Image> for ::image::DynamicImage {
-  type Error = Error;
 
-  fn try_from(img: &Image) -> Result<::image::DynamicImage> {
-    use image::*;
-    let ImageData {
-      width,
-      height,
-      data,
-      ..
-    } = img.get_pixels(None)?;
-    match data {
-      crate::ImagePixelData::L8(data) => {
-        let gray = GrayImage::from_vec(width, height, data)
-          .expect("Shouldn't happen.  Report to jpeg2k if you see this.");
+  /// Upsample all subsampled components (`dx`/`dy` > 1) to the image resolution using
+  /// bilinear interpolation.
+  ///
+  /// See [`Image::upsample_to_full_resolution_with`].
+  pub fn upsample_to_full_resolution(&mut self) -> Result<()> {
+    self.upsample_to_full_resolution_with(UpsampleFilter::default())
+  }
 
-        Ok(DynamicImage::ImageLuma8(gray))
+  /// Upsample all subsampled components (`dx`/`dy` > 1) to the image resolution.
+  ///
+  /// The components are rewritten in place with `dx = dy = 1`, so all components have
+  /// the same size.  For a reduced resolution decode the components are upsampled to the
+  /// reduced image size.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// // 4:2:0 YCbCr.
+  /// let luma = vec![128; 64 * 64];
+  /// let chroma = vec![64; 32 * 32];
+  /// let mut img = Image::from_components(64, 64, ColorSpace::SYCC, vec![
+  ///   ComponentData::new(luma, 8),
+  ///   ComponentData::new(chroma.clone(), 8).subsampling(2, 2),
+  ///   ComponentData::new(chroma, 8).subsampling(2, 2),
+  /// ])?;
+  /// img.upsample_to_full_resolution()?;
+  /// for comp in img.components() {
+  ///   assert_eq!((comp.width(), comp.height()), (64, 64));
+  /// }
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn upsample_to_full_resolution_with(&mut self, filter: UpsampleFilter) -> Result<()> {
+    let img = self.image();
+    let (x0, y0, x1, y1) = (img.x0, img.y0, img.x1, img.y1);
+    for comp in self.components_mut() {
+      if comp.0.dx <= 1 && comp.0.dy <= 1 {
+        continue;
       }
-      crate::ImagePixelData::La8(data) => {
-        let gray = GrayAlphaImage::from_vec(width, height, data)
-          .expect("Shouldn't happen.  Report to jpeg2k if you see this.");
-
-        Ok(DynamicImage::ImageLumaA8(gray))
+      // Size of a not subsampled component at the decoded resolution.
+      let ceil_div_pow2 =
+        |v: u32| ((v as u64 + (1u64 << comp.0.factor) - 1) >> comp.0.factor) as u32;
+      let w = ceil_div_pow2(x1) - ceil_div_pow2(x0);
+      let h = ceil_div_pow2(y1) - ceil_div_pow2(y0);
+      let (src_w, src_h) = (comp.width() as usize, comp.height() as usize);
+      if src_w == 0 || src_h == 0 || comp.data().len() < src_w * src_h {
+        return Err(Error::InvalidComponentError(format!(
+          "component has {} samples, expected {src_w}x{src_h}",
+          comp.data().len()
+        )));
       }
-      crate::ImagePixelData::Rgb8(data) => {
-        let rgb = RgbImage::from_vec(width, height, data)
-          .expect("Shouldn't happen.  Report to jpeg2k if you see this.");
-
-        Ok(DynamicImage::ImageRgb8(rgb))
+      let mut full = ImageComponent(sys::opj_image_comp_t {
+        dx: 1,
+        dy: 1,
+        w,
+        h,
+        // The origin isn't reduced, see `ImageComponent::origin`.
+        x0,
+        y0,
+        ..comp.0
+      });
+      let len = full
+        .data_len()
+        .ok_or_else(|| Error::InvalidComponentError("upsampled component is too large".into()))?;
+      full.0.data =
+        unsafe { sys::opj_image_data_alloc(len * std::mem::size_of::<i32>()) as *mut i32 };
+      if full.0.data.is_null() {
+        return Err(Error::NullPointerError(
+          "Image: failed to allocate component data",
+        ));
       }
-      crate::ImagePixelData::Rgba8(data) => {
-        let rgba = RgbaImage::from_vec(width, height, data)
-          .expect("Shouldn't happen.  Report to jpeg2k if you see this.");
+      // `opj_image_data_alloc` doesn't initialize the memory.
+      full.data_mut().fill(0);
+      filter.resample(comp.data(), src_w, src_h, full.data_mut(), w as usize);
+      let old = std::mem::replace(&mut comp.0, full.0);
+      unsafe { sys::opj_image_data_free(old.data as *mut _) };
+    }
+    Ok(())
+  }
 
-        Ok(DynamicImage::ImageRgba8(rgba))
+  /// Grow the image to `width x height`, filling the new samples with `fill`.
+  ///
+  /// The new samples are added on the right and bottom edges and the image extent
+  /// (`x1`/`y1`) is updated.  The new size must not be smaller than the current size.
+  /// Useful to align the image size to the tile size before encoding.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let mut img = Image::from_components(100, 100, ColorSpace::Gray, vec![
+  ///   ComponentData::new(vec![7; 100 * 100], 8),
+  /// ])?;
+  /// img.pad_to(128, 128, 0)?;
+  /// assert_eq!((img.width(), img.height()), (128, 128));
+  /// assert_eq!((img.orig_width(), img.orig_height()), (128, 128));
+  /// let data = img.components()[0].data();
+  /// assert_eq!(data[99 * 128 + 99], 7);
+  /// assert_eq!(data[99 * 128 + 100], 0);
+  /// assert!(data[100 * 128..].iter().all(|&p| p == 0));
+  ///
+  /// assert!(img.pad_to(64, 128, 0).is_err());
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn pad_to(&mut self, width: u32, height: u32, fill: i32) -> Result<()> {
+    let (cur_width, cur_height) = (self.orig_width(), self.orig_height());
+    if width < cur_width || height < cur_height {
+      return Err(Error::InvalidParametersError(format!(
+        "can't pad a {cur_width}x{cur_height} image to {width}x{height}, it is smaller"
+      )));
+    }
+    if self.components().iter().any(|c| c.0.factor != 0) {
+      return Err(Error::InvalidParametersError(
+        "can't pad a reduced resolution image".into(),
+      ));
+    }
+    let img = self.image();
+    let (x0, y0) = (img.x0, img.y0);
+    let (x1, y1) = match (x0.checked_add(width), y0.checked_add(height)) {
+      (Some(x1), Some(y1)) => (x1, y1),
+      _ => {
+        return Err(Error::InvalidParametersError(format!(
+          "padded size {width}x{height} is too large"
+        )))
       }
-      crate::ImagePixelData::L16(data) => {
-        let gray = ImageBuffer::from_vec(width, height, data)
-          .expect("Shouldn't happen.  Report to jpeg2k if you see this.");
-
-        Ok(DynamicImage::ImageLuma16(gray))
+    };
+    for comp in self.components_mut() {
+      let c = comp.0;
+      let mut padded = ImageComponent(sys::opj_image_comp_t {
+        w: x1.div_ceil(c.dx) - c.x0,
+        h: y1.div_ceil(c.dy) - c.y0,
+        data: ptr::null_mut(),
+        ..c
+      });
+      if !c.data.is_null() {
+        let len = padded
+          .data_len()
+          .ok_or_else(|| Error::InvalidComponentError("padded component is too large".into()))?;
+        padded.0.data =
+          unsafe { sys::opj_image_data_alloc(len * std::mem::size_of::<i32>()) as *mut i32 };
+        if padded.0.data.is_null() {
+          return Err(Error::NullPointerError(
+            "Image: failed to allocate component data",
+          ));
+        }
+        let (old_w, new_w) = (c.w as usize, padded.0.w as usize);
+        let dst = padded.data_mut();
+        dst.fill(fill);
+        if old_w > 0 {
+          for (src, dst) in comp.data().chunks(old_w).zip(dst.chunks_mut(new_w)) {
+            dst[..old_w].copy_from_slice(src);
+          }
+        }
+        unsafe { sys::opj_image_data_free(c.data as *mut _) };
       }
-      crate::ImagePixelData::La16(data) => {
+      comp.0 = padded.0;
+    }
+    let img = unsafe { &mut *self.as_ptr() };
+    img.x1 = x1;
+    img.y1 = y1;
+    Ok(())
+  }
+
+  /// Remove uniform borders, for example padding added by a tiled encoder.
+  ///
+  /// Rows and columns are trimmed from each edge while all their samples (in every
+  /// component) are within `tolerance` of the corner sample of that edge: the top-left
+  /// sample for the top and left edges, the bottom-right sample for the bottom and right
+  /// edges.  Only uniform borders are trimmed, at least one row and column are kept.
+  /// Subsampled and reduced resolution images aren't supported.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let data = (0..100 * 60).map(|v| 1 + v % 200).collect::<Vec<i32>>();
+  /// let mut img = Image::from_components(100, 60, ColorSpace::Gray, vec![
+  ///   ComponentData::new(data.clone(), 8),
+  /// ])?;
+  /// img.pad_to(128, 64, 0)?;
+  /// img.trim_uniform_border(0)?;
+  /// assert_eq!((img.width(), img.height()), (100, 60));
+  /// assert_eq!((img.orig_width(), img.orig_height()), (100, 60));
+  /// assert_eq!(img.components()[0].data(), data);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn trim_uniform_border(&mut self, tolerance: i32) -> Result<()> {
+    let comps = self.components();
+    if comps.is_empty() {
+      return Err(Error::UnsupportedComponentsError(0));
+    }
+    if comps
+      .iter()
+      .any(|c| c.subsampling() != (1, 1) || c.0.factor != 0)
+    {
+      return Err(Error::InvalidComponentError(
+        "can't trim subsampled or reduced resolution components".into(),
+      ));
+    }
+    let (w, h) = (comps[0].width() as usize, comps[0].height() as usize);
+    if w == 0 || h == 0 || comps.iter().any(|c| c.data().len() != w * h) {
+      return Err(Error::InvalidComponentError(
+        "components have no decoded data".into(),
+      ));
+    }
+    let tolerance = tolerance.unsigned_abs();
+    // Are all samples in the rows `ys` and columns `xs` near the corner sample at `corner`.
+    let uniform = |xs: std::ops::Range<usize>, ys: std::ops::Range<usize>, corner: usize| {
+      comps.iter().all(|c| {
+        let data = c.data();
+        let value = data[corner];
+        ys.clone().all(|y| {
+          data[y * w + xs.start..y * w + xs.end]
+            .iter()
+            .all(|p| p.abs_diff(value) <= tolerance)
+        })
+      })
+    };
+    let (first, last) = (0, w * h - 1);
+    let mut top = 0;
+    while top + 1 < h && uniform(0..w, top..top + 1, first) {
+      top += 1;
+    }
+    let mut bottom = h;
+    while bottom - 1 > top && uniform(0..w, bottom - 1..bottom, last) {
+      bottom -= 1;
+    }
+    let mut left = 0;
+    while left + 1 < w && uniform(left..left + 1, top..bottom, first) {
+      left += 1;
+    }
+    let mut right = w;
+    while right - 1 > left && uniform(right - 1..right, top..bottom, last) {
+      right -= 1;
+    }
+    if (left, top, right, bottom) == (0, 0, w, h) {
+      return Ok(());
+    }
+    let mut img = self.crop(left as u32, top as u32, right as u32, bottom as u32)?;
+    // Move the ICC profile to the trimmed image.
+    let (src, dst) = unsafe { (&mut *self.as_ptr(), &mut *img.as_ptr()) };
+    dst.icc_profile_buf = std::mem::replace(&mut src.icc_profile_buf, ptr::null_mut());
+    dst.icc_profile_len = std::mem::take(&mut src.icc_profile_len);
+    img.shared_icc_profile = self.shared_icc_profile.take();
+    *self = img;
+    Ok(())
+  }
+
+  /// Split the image into a grid of `tile_w` x `tile_h` sub-images, in row-major order.
+  ///
+  /// The tiles in the last column and row are smaller when the image size isn't a
+  /// multiple of the tile size.  Each tile keeps its position on the reference grid
+  /// (`x_offset()` and `y_offset()`), so the tiles can be recomposited.  Subsampled and
+  /// reduced resolution images aren't supported.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let data = (0..70 * 50).map(|v| v % 256).collect::<Vec<i32>>();
+  /// let img = Image::from_components(70, 50, ColorSpace::Gray, vec![
+  ///   ComponentData::new(data.clone(), 8),
+  /// ])?;
+  /// let tiles = img.split_into_grid(32, 32)?;
+  /// assert_eq!(tiles.len(), 3 * 2);
+  /// assert_eq!((tiles[2].width(), tiles[2].height()), (6, 32));
+  /// assert_eq!((tiles[5].x_offset(), tiles[5].y_offset()), (64, 32));
+  /// assert_eq!((tiles[5].width(), tiles[5].height()), (6, 18));
+  ///
+  /// // Recomposite the tiles.
+  /// let mut out = vec![0; 70 * 50];
+  /// for tile in &tiles {
+  ///   let (x0, y0, w) = (tile.x_offset() as usize, tile.y_offset() as usize, tile.width() as usize);
+  ///   for (y, row) in tile.components()[0].data().chunks(w).enumerate() {
+  ///     out[(y0 + y) * 70 + x0..][..w].copy_from_slice(row);
+  ///   }
+  /// }
+  /// assert_eq!(out, data);
+  ///
+  /// // The tiles keep the ICC profile.
+  /// let img = Image::from_file("samples/file7.jp2")?;
+  /// let tiles = img.split_into_grid(256, 256)?;
+  /// assert!(img.has_icc_profile());
+  /// assert!(tiles.iter().all(|tile| tile.icc_profile() == img.icc_profile()));
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn split_into_grid(&self, tile_w: u32, tile_h: u32) -> Result<Vec<Image>> {
+    if tile_w == 0 || tile_h == 0 {
+      return Err(Error::InvalidParametersError(format!(
+        "tile size must be non-zero, got {tile_w}x{tile_h}"
+      )));
+    }
+    let comps = self.components();
+    if comps.is_empty() {
+      return Err(Error::UnsupportedComponentsError(0));
+    }
+    if comps
+      .iter()
+      .any(|c| c.subsampling() != (1, 1) || c.0.factor != 0)
+    {
+      return Err(Error::InvalidComponentError(
+        "can't split subsampled or reduced resolution components".into(),
+      ));
+    }
+    let (w, h) = (comps[0].width(), comps[0].height());
+    if w == 0
+      || h == 0
+      || comps
+        .iter()
+        .any(|c| c.data().len() != w as usize * h as usize)
+    {
+      return Err(Error::InvalidComponentError(
+        "components have no decoded data".into(),
+      ));
+    }
+    let icc = self.share_icc_profile();
+    let mut tiles = Vec::new();
+    for top in (0..h).step_by(tile_h as usize) {
+      for left in (0..w).step_by(tile_w as usize) {
+        let right = left.saturating_add(tile_w).min(w);
+        let bottom = top.saturating_add(tile_h).min(h);
+        let mut tile = self.crop(left, top, right, bottom)?;
+        tile.shared_icc_profile = icc.clone();
+        tiles.push(tile);
+      }
+    }
+    Ok(tiles)
+  }
+
+  /// Copy the samples in columns `left..right` and rows `top..bottom` to a new image.
+  ///
+  /// The components must be full resolution without subsampling.  The ICC profile isn't
+  /// copied.
+  fn crop(&self, left: u32, top: u32, right: u32, bottom: u32) -> Result<Self> {
+    let comps = self.components();
+    let w = comps[0].width() as usize;
+    let mut params = comps
+      .iter()
+      .map(|comp| sys::opj_image_cmptparm_t {
+        dx: comp.0.dx,
+        dy: comp.0.dy,
+        w: right - left,
+        h: bottom - top,
+        x0: comp.0.x0 + left,
+        y0: comp.0.y0 + top,
+        prec: comp.0.prec,
+        bpp: comp.0.bpp,
+        sgnd: comp.0.sgnd,
+      })
+      .collect::<Vec<_>>();
+    let src = self.image();
+    let (x0, y0) = (src.x0 + left, src.y0 + top);
+    let mut img = Self::create(
+      &mut params,
+      self.color_space(),
+      (x0, y0, x0 + right - left, y0 + bottom - top),
+    )?;
+    let (left, right) = (left as usize, right as usize);
+    for (dst, comp) in img.components_mut().iter_mut().zip(comps) {
+      dst.0.alpha = comp.0.alpha;
+      let data = comp.data();
+      for (y, row) in (top as usize..bottom as usize).zip(dst.data_mut().chunks_mut(right - left)) {
+        row.copy_from_slice(&data[y * w + left..y * w + right]);
+      }
+    }
+    img.channel_defs = self.channel_defs.clone();
+    img.palette = self.palette.clone();
+    img.palette_applied = self.palette_applied;
+    img.xml_boxes = self.xml_boxes.clone();
+    img.uuid_boxes = self.uuid_boxes.clone();
+    img.num_resolutions = self.num_resolutions;
+    Ok(img)
+  }
+
+  /// Rewrite the samples of each component with `map(x, y, w, h) -> new index`.
+  ///
+  /// If `transpose` is true the width and height are swapped (for 90 degree rotations),
+  /// along with the image offsets and the component subsampling.
+  fn transform_samples(
+    &mut self,
+    transpose: bool,
+    map: impl Fn(usize, usize, usize, usize) -> usize,
+  ) {
+    if transpose {
+      let img = unsafe { &mut *self.as_ptr() };
+      std::mem::swap(&mut img.x0, &mut img.y0);
+      std::mem::swap(&mut img.x1, &mut img.y1);
+    }
+    for comp in self.components_mut() {
+      let (w, h) = (comp.0.w as usize, comp.0.h as usize);
+      let data = comp.data_mut();
+      if !data.is_empty() {
+        let src = data.to_vec();
+        for (idx, &p) in src.iter().enumerate() {
+          data[map(idx % w, idx / w, w, h)] = p;
+        }
+      }
+      if transpose {
+        let c = &mut comp.0;
+        std::mem::swap(&mut c.w, &mut c.h);
+        std::mem::swap(&mut c.x0, &mut c.y0);
+        std::mem::swap(&mut c.dx, &mut c.dy);
+      }
+    }
+  }
+
+  /// Rotate the image 90 degrees clockwise.
+  ///
+  /// The samples of each component are rotated (lossless), the width and height are
+  /// swapped.  The image offsets and the component subsampling are swapped too.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let mut img = Image::from_components(3, 2, ColorSpace::Gray, vec![
+  ///   ComponentData::new(vec![1, 2, 3, 4, 5, 6], 8),
+  /// ])?;
+  /// img.rotate90();
+  /// assert_eq!((img.width(), img.height()), (2, 3));
+  /// assert_eq!(img.components()[0].data(), [4, 1, 5, 2, 6, 3]);
+  ///
+  /// img.rotate270();
+  /// assert_eq!((img.width(), img.height()), (3, 2));
+  /// assert_eq!(img.components()[0].data(), [1, 2, 3, 4, 5, 6]);
+  ///
+  /// img.rotate180();
+  /// assert_eq!(img.components()[0].data(), [6, 5, 4, 3, 2, 1]);
+  /// img.flip_horizontal();
+  /// assert_eq!(img.components()[0].data(), [4, 5, 6, 1, 2, 3]);
+  /// img.flip_vertical();
+  /// assert_eq!(img.components()[0].data(), [1, 2, 3, 4, 5, 6]);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn rotate90(&mut self) {
+    self.transform_samples(true, |x, y, _w, h| x * h + (h - 1 - y));
+  }
+
+  /// Rotate the image 180 degrees.
+  pub fn rotate180(&mut self) {
+    self.transform_samples(false, |x, y, w, h| (h - 1 - y) * w + (w - 1 - x));
+  }
+
+  /// Rotate the image 270 degrees clockwise (90 degrees counter-clockwise).
+  ///
+  /// Like [`Image::rotate90`] the width and height are swapped.
+  pub fn rotate270(&mut self) {
+    self.transform_samples(true, |x, y, w, h| (w - 1 - x) * h + y);
+  }
+
+  /// Mirror the image horizontally (left to right).
+  pub fn flip_horizontal(&mut self) {
+    self.transform_samples(false, |x, y, w, _h| y * w + (w - 1 - x));
+  }
+
+  /// Mirror the image vertically (top to bottom).
+  pub fn flip_vertical(&mut self) {
+    self.transform_samples(false, |x, y, w, h| (h - 1 - y) * w + x);
+  }
+
+  /// Rescale the samples of all components to `target_prec` (`1..=31`) bits.
+  ///
+  /// Unsigned samples are scaled to the full range (8bit `255` becomes 16bit `65535`),
+  /// rounding to the nearest value.
+  /// Signed samples keep their sign and are scaled by the change in precision.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let mut img = Image::from_components(2, 1, ColorSpace::Unspecified, vec![
+  ///   ComponentData::new(vec![0, 255], 8),
+  ///   ComponentData::new(vec![2048, 4095], 12),
+  /// ])?;
+  /// img.normalize_precision(16)?;
+  /// let comps = img.components();
+  /// assert!(comps.iter().all(|c| c.precision() == 16));
+  /// assert_eq!(comps[0].data(), [0, 65535]);
+  /// assert_eq!(comps[1].data(), [32776, 65535]);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn normalize_precision(&mut self, target_prec: u32) -> Result<()> {
+    if !(1..=31).contains(&target_prec) {
+      return Err(Error::UnsupportedPrecisionError(target_prec));
+    }
+    for comp in self.components_mut() {
+      let old_prec = comp.precision();
+      if old_prec == target_prec {
+        continue;
+      }
+      if comp.is_signed() {
+        let old_half = 1i64 << (old_prec.max(1) - 1);
+        let new_half = 1i64 << (target_prec - 1);
+        for p in comp.data_mut() {
+          *p = ((*p as i64 * new_half) / old_half).clamp(-new_half, new_half - 1) as i32;
+        }
+      } else {
+        let old_max = (1i64 << old_prec) - 1;
+        let new_max = (1i64 << target_prec) - 1;
+        for p in comp.data_mut() {
+          *p = ((*p as i64 * new_max + old_max / 2) / old_max).clamp(0, new_max) as i32;
+        }
+      }
+      comp.0.prec = target_prec;
+      comp.0.bpp = target_prec;
+    }
+    Ok(())
+  }
+
+  /// Levels adjustment of component `comp`.
+  ///
+  /// Samples are mapped linearly from `in_black..=in_white` to the full range of the
+  /// component (for example `0..=255` for 8bit), with a `gamma` curve
+  /// (`out = in ^ (1 / gamma)`, so `gamma > 1.0` brightens the midtones).  Samples
+  /// outside the input range are clamped.  The precision doesn't change.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let new_image = || Image::from_components(4, 1, ColorSpace::Gray, vec![
+  ///   ComponentData::new(vec![0, 50, 100, 200], 8),
+  /// ]);
+  /// // Linear: 100 is half way between 50 and 150, `127.5` is rounded up.
+  /// let mut img = new_image()?;
+  /// img.apply_levels(0, 50, 150, 1.0)?;
+  /// assert_eq!(img.components()[0].data(), [0, 0, 128, 255]);
+  ///
+  /// // Gamma 2.0: `255 * 0.5 ^ 0.5 = 180.3`.
+  /// let mut img = new_image()?;
+  /// img.apply_levels(0, 50, 150, 2.0)?;
+  /// assert_eq!(img.components()[0].data(), [0, 0, 180, 255]);
+  ///
+  /// // Signed 8bit components map to `-128..=127`.
+  /// let mut img = Image::from_components(3, 1, ColorSpace::Gray, vec![
+  ///   ComponentData::new(vec![-10, 0, 10], 8).signed(true),
+  /// ])?;
+  /// img.apply_levels(0, -10, 10, 1.0)?;
+  /// assert_eq!(img.components()[0].data(), [-128, 0, 127]);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn apply_levels(
+    &mut self,
+    comp: usize,
+    in_black: i32,
+    in_white: i32,
+    gamma: f64,
+  ) -> Result<()> {
+    if in_white <= in_black {
+      return Err(Error::InvalidParametersError(format!(
+        "levels input range {in_black}..={in_white}, white must be above black"
+      )));
+    }
+    if !gamma.is_finite() || gamma <= 0.0 {
+      return Err(Error::InvalidParametersError(format!(
+        "levels gamma {gamma}, it must be positive"
+      )));
+    }
+    let num_comps = self.components().len();
+    let comp = self.components_mut().get_mut(comp).ok_or_else(|| {
+      Error::InvalidComponentError(format!(
+        "component {comp}, the image has {num_comps} components"
+      ))
+    })?;
+    let prec = comp.precision().clamp(1, 31);
+    let (min, max) = if comp.is_signed() {
+      (-(1i64 << (prec - 1)), (1i64 << (prec - 1)) - 1)
+    } else {
+      (0, (1i64 << prec) - 1)
+    };
+    let (black, range) = (in_black as f64, in_white as f64 - in_black as f64);
+    let out_range = (max - min) as f64;
+    for p in comp.data_mut() {
+      let t = ((*p as f64 - black) / range).clamp(0.0, 1.0);
+      let t = if gamma == 1.0 { t } else { t.powf(1.0 / gamma) };
+      *p = (min + (t * out_range).round() as i64).clamp(min, max) as i32;
+    }
+    Ok(())
+  }
+
+  /// Set the precision of all components to `prec`, clamping the samples to the new range.
+  fn override_precision(&mut self, prec: u32) {
+    for (idx, comp) in self.components_mut().iter_mut().enumerate() {
+      if comp.precision() == prec {
+        continue;
+      }
+      let (min, max) = if comp.is_signed() {
+        (-(1i64 << (prec - 1)), (1i64 << (prec - 1)) - 1)
+      } else {
+        (0, (1i64 << prec) - 1)
+      };
+      let mut clamped = 0usize;
+      for p in comp.data_mut() {
+        let v = (*p as i64).clamp(min, max);
+        if v != *p as i64 {
+          clamped += 1;
+          *p = v as i32;
+        }
+      }
+      if clamped > 0 {
+        log::warn!(
+          "Component {idx}: {clamped} samples don't fit in {prec} bits (was {} bits), they are clamped",
+          comp.precision()
+        );
+      }
+      comp.0.prec = prec;
+      comp.0.bpp = prec;
+    }
+  }
+
+  /// Allocate the data of components without data (after reading the header).
+  pub(crate) fn alloc_component_data(&mut self) -> Result<()> {
+    for comp in self.components_mut() {
+      if !comp.0.data.is_null() {
+        continue;
+      }
+      let len = comp
+        .data_len()
+        .ok_or_else(|| Error::InvalidComponentError("component is too large".into()))?;
+      comp.0.data =
+        unsafe { sys::opj_image_data_alloc(len * std::mem::size_of::<i32>()) as *mut i32 };
+      if comp.0.data.is_null() {
+        return Err(Error::NullPointerError(
+          "Image: failed to allocate component data",
+        ));
+      }
+      comp.data_mut().fill(0);
+    }
+    Ok(())
+  }
+
+  /// Copy the data of a decoded tile (from `opj_decode_tile_data`) into the components.
+  ///
+  /// `tile` is the tile area `(x0, y0, x1, y1)` on the reference grid.  The tile data has
+  /// the whole tile of each component (at the decoded resolution), one after the other,
+  /// with samples stored in 1, 2 or 4 bytes depending on the precision.  Only the part
+  /// inside the component area (the decode area) is copied.
+  ///
+  /// openjpeg only uses the decode area to select the tiles when decoding with
+  /// `opj_decode_tile_data` (there is no output image to take the window from), so the
+  /// tile data isn't cropped to the decode area.
+  pub(crate) fn copy_tile_data(&mut self, data: &[u8], tile: (u32, u32, u32, u32)) -> Result<()> {
+    let mut offset = 0;
+    for comp in self.components_mut() {
+      let c = comp.0;
+      let ceil_div_pow2 = |v: u32| ((v as u64 + (1u64 << c.factor) - 1) >> c.factor) as u32;
+      // Tile area of the component at the decoded resolution.
+      let tx0 = ceil_div_pow2(tile.0.div_ceil(c.dx));
+      let ty0 = ceil_div_pow2(tile.1.div_ceil(c.dy));
+      let tx1 = ceil_div_pow2(tile.2.div_ceil(c.dx));
+      let ty1 = ceil_div_pow2(tile.3.div_ceil(c.dy));
+      let size = match c.prec {
+        0..=8 => 1,
+        9..=16 => 2,
+        _ => 4,
+      };
+      let tile_w = tx1.saturating_sub(tx0) as usize;
+      let len = tile_w * ty1.saturating_sub(ty0) as usize * size;
+      let src = data
+        .get(offset..offset + len)
+        .ok_or_else(|| Error::CodecError("Tile data is too small".into()))?;
+      offset += len;
+      // Component area at the decoded resolution.
+      let (cx0, cy0) = (ceil_div_pow2(c.x0), ceil_div_pow2(c.y0));
+      let (cx1, cy1) = (cx0 + c.w, cy0 + c.h);
+      // Part of the tile inside the component area.
+      let (wx0, wy0) = (tx0.max(cx0), ty0.max(cy0));
+      let (wx1, wy1) = (tx1.min(cx1), ty1.min(cy1));
+      if wx1 <= wx0 || wy1 <= wy0 {
+        continue;
+      }
+      let win_w = (wx1 - wx0) as usize;
+      let signed = c.sgnd == 1;
+      let stride = c.w as usize;
+      let dst = comp.data_mut();
+      for y in wy0..wy1 {
+        let src_start = ((y - ty0) as usize * tile_w + (wx0 - tx0) as usize) * size;
+        let src = &src[src_start..src_start + win_w * size];
+        let dst_start = (y - cy0) as usize * stride + (wx0 - cx0) as usize;
+        let dst = &mut dst[dst_start..dst_start + win_w];
+        for (d, s) in dst.iter_mut().zip(src.chunks_exact(size)) {
+          *d = match (size, signed) {
+            (1, false) => s[0] as i32,
+            (1, true) => s[0] as i8 as i32,
+            (2, false) => u16::from_ne_bytes([s[0], s[1]]) as i32,
+            (2, true) => i16::from_ne_bytes([s[0], s[1]]) as i32,
+            _ => i32::from_ne_bytes([s[0], s[1], s[2], s[3]]),
+          };
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// Pack the samples of a tile for `opj_write_tile`, the counterpart of `copy_tile_data`.
+  ///
+  /// `tile` is the tile area `(x0, y0, x1, y1)` on the reference grid, inside the image
+  /// area.  The samples of each component are stored one after the other, in 1, 2 or 4
+  /// bytes depending on the precision.
+  pub(crate) fn write_tile_data(
+    &self,
+    buf: &mut Vec<u8>,
+    tile: (u32, u32, u32, u32),
+  ) -> Result<()> {
+    buf.clear();
+    for comp in self.components() {
+      let c = comp.0;
+      // Tile area of the component.
+      let (tx0, ty0) = (tile.0.div_ceil(c.dx), tile.1.div_ceil(c.dy));
+      let (tx1, ty1) = (tile.2.div_ceil(c.dx), tile.3.div_ceil(c.dy));
+      if tx0 < c.x0
+        || ty0 < c.y0
+        || tx1 as u64 > c.x0 as u64 + c.w as u64
+        || ty1 as u64 > c.y0 as u64 + c.h as u64
+      {
+        return Err(Error::InvalidComponentError(
+          "tile is outside of the component area".into(),
+        ));
+      }
+      let data = comp.data();
+      let stride = c.w as usize;
+      for y in ty0..ty1 {
+        let start = (y - c.y0) as usize * stride + (tx0 - c.x0) as usize;
+        let row = data
+          .get(start..start + (tx1 - tx0) as usize)
+          .ok_or_else(|| Error::InvalidComponentError("component is too small".into()))?;
+        match c.prec {
+          0..=8 => buf.extend(row.iter().map(|&v| v as u8)),
+          9..=16 => buf.extend(row.iter().flat_map(|&v| (v as u16).to_ne_bytes())),
+          _ => buf.extend(row.iter().flat_map(|&v| v.to_ne_bytes())),
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// Update the component dimensions for a new resolution factor (before decoding).
+  ///
+  /// openjpeg only updates the image header if the factor was 0 when the header was read.
+  pub(crate) fn set_resolution_factor(&mut self, factor: u32) {
+    let img = self.image();
+    let (x0, y0, x1, y1) = (img.x0, img.y0, img.x1, img.y1);
+    let ceil_div = |v: u32, d: u32| v.div_ceil(d.max(1)) as u64;
+    let ceil_div_pow2 = |v: u64| ((v + (1u64 << factor) - 1) >> factor) as u32;
+    for comp in self.components_mut() {
+      let c = &mut comp.0;
+      c.factor = factor;
+      c.x0 = ceil_div(x0, c.dx) as u32;
+      c.y0 = ceil_div(y0, c.dy) as u32;
+      c.w = ceil_div_pow2(ceil_div(x1, c.dx)) - ceil_div_pow2(c.x0 as u64);
+      c.h = ceil_div_pow2(ceil_div(y1, c.dy)) - ceil_div_pow2(c.y0 as u64);
+    }
+  }
+
+  /// Sample format of the image components.
+  ///
+  /// `bits` is the max precision of all components.  Returns an error if some components
+  /// are signed and others aren't.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let img = Image::from_components(1, 1, ColorSpace::Unspecified, vec![
+  ///   ComponentData::new(vec![-5], 12).signed(true),
+  ///   ComponentData::new(vec![3], 10).signed(true),
+  /// ])?;
+  /// assert_eq!(img.sample_format()?, SampleFormat { signed: true, bits: 12 });
+  ///
+  /// let img = Image::from_components(1, 1, ColorSpace::Unspecified, vec![
+  ///   ComponentData::new(vec![-5], 12).signed(true),
+  ///   ComponentData::new(vec![3], 12),
+  /// ])?;
+  /// assert!(img.sample_format().is_err());
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn sample_format(&self) -> Result<SampleFormat> {
+    let comps = self.components();
+    let first = comps.first().ok_or(Error::UnsupportedComponentsError(0))?;
+    let signed = first.is_signed();
+    if let Some(idx) = comps.iter().position(|c| c.is_signed() != signed) {
+      return Err(Error::InvalidComponentError(format!(
+        "component {idx} signedness doesn't match component 0"
+      )));
+    }
+    let bits = comps
+      .iter()
+      .map(|c| c.precision())
+      .max()
+      .unwrap_or_default();
+    Ok(SampleFormat { signed, bits })
+  }
+
+  /// Summary of the image channels: color kind, alpha and max precision.
+  ///
+  /// The color kind comes from the color space.  If the color space is unknown/unspecified,
+  /// it is guessed from the number of components (1-2: Gray, 3-4: RGB).
+  /// The image has alpha if a component is marked as alpha, or if there is one more component
+  /// than color channels (Gray + 1 or RGB + 1).
+  pub fn channel_info(&self) -> ChannelInfo {
+    let comps = self.components();
+    let num_comps = comps.len() as u32;
+    let max_precision = comps.iter().fold(u32::MIN, |max, c| max.max(c.precision()));
+    let color = match self.color_space() {
+      ColorSpace::Gray => ColorKind::Gray,
+      ColorSpace::SRGB => ColorKind::Rgb,
+      ColorSpace::CMYK => ColorKind::Cmyk,
+      ColorSpace::SYCC | ColorSpace::EYCC => ColorKind::Ycc,
+      ColorSpace::Unknown | ColorSpace::Unspecified => {
+        if num_comps <= 2 {
+          ColorKind::Gray
+        } else {
+          ColorKind::Rgb
+        }
+      }
+    };
+    let alpha = comps.iter().any(|c| c.is_alpha()) || num_comps > color.num_channels();
+    ChannelInfo {
+      color,
+      alpha,
+      max_precision,
+    }
+  }
+
+  /// Split the components into color components and an optional alpha component.
+  fn pixel_layout(&self) -> Result<(&[ImageComponent], Option<&ImageComponent>)> {
+    // Check for support color space.
+    match self.color_space() {
+      ColorSpace::Unknown | ColorSpace::Unspecified => {
+        // Assume either Grey/RGB/RGBA based on number of components.
+      }
+      ColorSpace::SRGB | ColorSpace::Gray => (),
+      cs => {
+        return Err(Error::UnsupportedColorSpaceError(cs));
+      }
+    }
+
+    let has_alpha = self.channel_info().alpha;
+    match (self.components(), has_alpha) {
+      (comps @ [_], _) => Ok((comps, None)),
+      ([comps @ .., a], true) if comps.len() == 1 => Ok((comps, Some(a))),
+      (comps @ [_, _, _], false) => Ok((comps, None)),
+      ([comps @ .., a], _) if comps.len() == 3 => Ok((comps, Some(a))),
+      _ => Err(Error::UnsupportedComponentsError(self.num_components())),
+    }
+  }
+
+  /// Map the pixel grid (the samples of the first component) onto the samples of `comp`.
+  ///
+  /// Returns the sample column for each pixel column and the sample row for each pixel row,
+  /// or `None` if `comp` is aligned with the pixel grid.
+  fn sample_map(&self, comp: &ImageComponent) -> Option<(Vec<usize>, Vec<usize>)> {
+    let first = self.components().first()?;
+    if (first.origin(), first.subsampling()) == (comp.origin(), comp.subsampling())
+      && (first.width(), first.height()) == (comp.width(), comp.height())
+    {
+      return None;
+    }
+    // The origin is in full resolution component samples, reduce it to the decoded
+    // resolution.
+    let reduced = |v: u32, factor: u32| ((v as u64 + (1u64 << factor) - 1) >> factor) as usize;
+    let map = |len: u32, origin: u32, d: u32, c_len: u32, c_origin: u32, c_d: u32| {
+      let origin = reduced(origin, first.0.factor);
+      let c_origin = reduced(c_origin, comp.0.factor);
+      (0..len as usize)
+        .map(|i| {
+          // Position on the reference grid.
+          let pos = (origin + i) * d.max(1) as usize;
+          (pos / c_d.max(1) as usize)
+            .saturating_sub(c_origin)
+            .min((c_len as usize).saturating_sub(1))
+        })
+        .collect::<Vec<_>>()
+    };
+    let xs = map(
+      first.width(),
+      first.0.x0,
+      first.0.dx,
+      comp.width(),
+      comp.0.x0,
+      comp.0.dx,
+    );
+    let ys = map(
+      first.height(),
+      first.0.y0,
+      first.0.dy,
+      comp.height(),
+      comp.0.y0,
+      comp.0.dy,
+    );
+    Some((xs, ys))
+  }
+
+  /// Build pixels from the color and alpha components.
+  ///
+  /// Components that are subsampled or have a different origin than the first component
+  /// are mapped onto the first component's samples (nearest sample).
+  ///
+  /// `alpha_default` is only used if there is no alpha component.
+  fn build_pixels<'a, T: Copy + 'a>(
+    &'a self,
+    alpha_default: Option<T>,
+    data: impl Fn(&'a ImageComponent) -> Box<dyn Iterator<Item = T> + 'a>,
+  ) -> Result<(u32, u32, usize, Vec<T>)> {
+    let (color, alpha) = self.pixel_layout()?;
+    self.build_pixels_from(color, alpha, alpha_default, data)
+  }
+
+  /// Interleave the `color` and `alpha` components, see [`build_pixels`](Self::build_pixels).
+  fn build_pixels_from<'a, T: Copy + 'a>(
+    &'a self,
+    color: &'a [ImageComponent],
+    alpha: Option<&'a ImageComponent>,
+    alpha_default: Option<T>,
+    data: impl Fn(&'a ImageComponent) -> Box<dyn Iterator<Item = T> + 'a>,
+  ) -> Result<(u32, u32, usize, Vec<T>)> {
+    let (width, height) = (self.width(), self.height());
+    let alpha_default = if alpha.is_some() { None } else { alpha_default };
+    let channels = color
+      .iter()
+      .chain(alpha)
+      .map(|comp| -> Result<Box<dyn Iterator<Item = T> + 'a>> {
+        let Some((xs, ys)) = self.sample_map(comp) else {
+          return Ok(data(comp));
+        };
+        let samples = data(comp).collect::<Vec<_>>();
+        let w = comp.width() as usize;
+        let expected = w * comp.height() as usize;
+        if samples.len() != expected {
+          return Err(Error::InvalidComponentError(format!(
+            "component has {} samples, expected {expected}",
+            samples.len()
+          )));
+        }
+        let mut pixels = Vec::with_capacity(xs.len() * ys.len());
+        for y in ys {
+          let row = &samples[y * w..];
+          pixels.extend(xs.iter().map(|&x| row[x]));
+        }
+        Ok(Box::new(pixels.into_iter()))
+      })
+      .collect::<Result<Vec<_>>>()?;
+    let num_channels = channels.len() + alpha_default.is_some() as usize;
+    let len = width as usize * height as usize;
+    let pixels = interleave(len, channels, alpha_default)?;
+    Ok((width, height, num_channels, pixels))
+  }
+
+  /// Convert image components into pixels.
+  ///
+  /// Subsampled components (and components with a different origin) are mapped onto the
+  /// samples of the first component, using the nearest sample.
+  ///
+  /// `alpha_default` - The default value for the alpha channel if there is no alpha component.
+  ///
+  /// The `alpha_default` contract is the same for all color spaces (and for the other
+  /// pixel conversions):
+  /// * If the image has no alpha component, an alpha channel with the value
+  ///   `alpha_default` is added (`L` -> `La`, `Rgb` -> `Rgba`).  The value is clamped to the
+  ///   max value of the output pixels (`255` for 8bit pixels).
+  /// * If the image has an alpha component, `alpha_default` is ignored.
+  /// * Color spaces that can't be converted (CMYK, e-YCC) are an error, with or without
+  ///   `alpha_default`.  Use [`get_pixels_with_cmyk`](Self::get_pixels_with_cmyk) to
+  ///   convert CMYK images.
+  ///
+  /// SYCC (YCbCr) images are converted to sRGB pixels, subsampled chroma components (for
+  /// example 4:2:0) are upsampled to the luma resolution first.
+  ///
+  /// Indexed-color images are expanded with their palette, see
+  /// [`has_palette`](Self::has_palette).
+  ///
+  /// The number of components must match the color space:
+  /// * Two components are gray + alpha, even if the second component isn't flagged as
+  ///   alpha (no channel definition).
+  /// * A Gray image with three components is an error, it isn't converted as RGB.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let comp = |v: i32| ComponentData::new(vec![v; 2], 8);
+  /// let pixels = |color_space, comps| Image::from_components(2, 1, color_space, comps)?.get_pixels(None);
+  ///
+  /// let gray = pixels(ColorSpace::Gray, vec![comp(10)])?;
+  /// assert_eq!(gray.data, ImagePixelData::L8(vec![10, 10]));
+  /// let gray_alpha = pixels(ColorSpace::Gray, vec![comp(10), comp(20).alpha(true)])?;
+  /// assert_eq!(gray_alpha.data, ImagePixelData::La8(vec![10, 20, 10, 20]));
+  /// // The second component is used as alpha without the alpha flag.
+  /// let gray_alpha = pixels(ColorSpace::Gray, vec![comp(10), comp(20)])?;
+  /// assert_eq!(gray_alpha.data, ImagePixelData::La8(vec![10, 20, 10, 20]));
+  /// let rgb = pixels(ColorSpace::SRGB, vec![comp(1), comp(2), comp(3)])?;
+  /// assert_eq!(rgb.data, ImagePixelData::Rgb8(vec![1, 2, 3, 1, 2, 3]));
+  /// let rgba = pixels(ColorSpace::SRGB, vec![comp(1), comp(2), comp(3), comp(4).alpha(true)])?;
+  /// assert_eq!(rgba.data, ImagePixelData::Rgba8(vec![1, 2, 3, 4, 1, 2, 3, 4]));
+  /// assert!(pixels(ColorSpace::CMYK, vec![comp(1), comp(2), comp(3), comp(4)]).is_err());
+  /// assert!(pixels(ColorSpace::Gray, vec![comp(1), comp(2), comp(3)]).is_err());
+  /// # Ok(())
+  /// # }
+  /// ```
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let comp = || ComponentData::new(vec![10; 64 * 64], 8);
+  /// for (color_space, num_comps, format) in [
+  ///   (ColorSpace::Gray, 1, ImageFormat::La8),
+  ///   (ColorSpace::SRGB, 3, ImageFormat::Rgba8),
+  ///   (ColorSpace::Unspecified, 1, ImageFormat::La8),
+  ///   (ColorSpace::Unspecified, 3, ImageFormat::Rgba8),
+  ///   (ColorSpace::Unknown, 3, ImageFormat::Rgba8),
+  /// ] {
+  ///   let img = Image::from_components(64, 64, color_space, vec![comp(); num_comps])?;
+  ///   let pixels = img.get_pixels(Some(1000))?;
+  ///   assert_eq!(pixels.format, format, "{color_space:?}");
+  ///   let (ImagePixelData::La8(data) | ImagePixelData::Rgba8(data)) = pixels.data else {
+  ///     unreachable!()
+  ///   };
+  ///   // Clamped to 8bit.
+  ///   assert_eq!(data[num_comps], 255);
+  /// }
+  ///
+  /// // The alpha component is kept.
+  /// let img = Image::from_components(64, 64, ColorSpace::Gray, vec![
+  ///   comp(),
+  ///   ComponentData::new(vec![7; 64 * 64], 8).alpha(true),
+  /// ])?;
+  /// assert_eq!(img.get_pixels(Some(255))?.data, ImagePixelData::La8([10, 7].repeat(64 * 64)));
+  ///
+  /// let img = Image::from_components(64, 64, ColorSpace::CMYK, vec![comp(); 4])?;
+  /// assert!(img.get_pixels(None).is_err());
+  /// assert!(img.get_pixels(Some(255)).is_err());
+  ///
+  /// // 4:2:0 YCbCr: the left half is red, the right half is gray.
+  /// let img = Image::from_components(4, 2, ColorSpace::SYCC, vec![
+  ///   ComponentData::new(vec![76, 76, 100, 100, 76, 76, 100, 100], 8),
+  ///   ComponentData::new(vec![85, 128], 8).subsampling(2, 2),
+  ///   ComponentData::new(vec![255, 128], 8).subsampling(2, 2),
+  /// ])?;
+  /// let pixels = img.get_pixels(None)?;
+  /// assert_eq!((pixels.format, pixels.color_space), (ImageFormat::Rgb8, ColorSpace::SRGB));
+  /// let row = [254, 0, 0, 254, 0, 0, 100, 100, 100, 100, 100, 100];
+  /// assert_eq!(pixels.data, ImagePixelData::Rgb8(row.repeat(2)));
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn get_pixels(&self, alpha_default: Option<u32>) -> Result<ImageData> {
+    if let Some(img) = self.expand_palette()? {
+      return img.get_pixels(alpha_default);
+    }
+    if self.color_space() == ColorSpace::SYCC {
+      return self.rgb_pixels(3, alpha_default, sycc_to_rgb);
+    }
+    match self.channel_info().max_precision {
+      1..=8 => {
+        let (width, height, num_channels, pixels) =
+          self.build_pixels(clamp_alpha(alpha_default, 8), |c| c.data_u8())?;
+        Ok(ImageData::from_u8(
+          self.color_space(),
+          width,
+          height,
+          num_channels,
+          pixels,
+        ))
+      }
+      9..=16 => {
+        let (width, height, num_channels, pixels) =
+          self.build_pixels(clamp_alpha(alpha_default, 16), |c| c.data_u16())?;
+        Ok(ImageData::from_u16(
+          self.color_space(),
+          width,
+          height,
+          num_channels,
+          pixels,
+        ))
+      }
+      _ => Err(Error::UnsupportedComponentsError(self.num_components())),
+    }
+  }
+
+  /// Convert image components into 16bit pixels with the raw sample values.
+  ///
+  /// Unlike [`get_pixels`](Self::get_pixels) the samples aren't scaled to the full 8bit or
+  /// 16bit range, 12bit samples stay in `0..=4095`.  This keeps the real values for
+  /// windowing/leveling of medical or elevation data.  The samples are converted with
+  /// [`ImageComponent::data_raw_u16`], signed samples are offset by `2^(precision - 1)`.
+  ///
+  /// The pixels are always `L16`, `La16`, `Rgb16` or `Rgba16`.  There is no color
+  /// conversion: only gray and RGB images are supported.  `alpha_default` is clamped to
+  /// the max value of the highest component precision.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let img = Image::from_components(3, 1, ColorSpace::Gray, vec![
+  ///   ComponentData::new(vec![0, 1000, 4095], 12),
+  /// ])?;
+  /// let pixels = img.get_pixels_raw(None)?;
+  /// assert_eq!(pixels.data, ImagePixelData::L16(vec![0, 1000, 4095]));
+  /// // Scaled to 16bit.
+  /// assert_eq!(img.get_pixels(None)?.data, ImagePixelData::L16(vec![0, 16003, 65535]));
+  ///
+  /// let pixels = img.get_pixels_raw(Some(u32::MAX))?;
+  /// assert_eq!(pixels.data, ImagePixelData::La16(vec![0, 4095, 1000, 4095, 4095, 4095]));
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn get_pixels_raw(&self, alpha_default: Option<u32>) -> Result<ImageData> {
+    let max_precision = self.channel_info().max_precision;
+    if !(1..=16).contains(&max_precision) {
+      return Err(Error::UnsupportedPrecisionError(max_precision));
+    }
+    let (width, height, num_channels, pixels) = self
+      .build_pixels(clamp_alpha(alpha_default, max_precision), |c| {
+        Box::new(c.data_raw_u16())
+      })?;
+    Ok(ImageData::from_u16(
+      self.color_space(),
+      width,
+      height,
+      num_channels,
+      pixels,
+    ))
+  }
+
+  /// Convert image components into pixels, like [`get_pixels`](Self::get_pixels), with
+  /// CMYK images converted to RGB.
+  ///
+  /// `get_pixels` returns an error for CMYK images, the conversion has to be requested.
+  /// The conversion is the naive one without color management: `R = (1 - C) * (1 - K)`,
+  /// `G = (1 - M) * (1 - K)` and `B = (1 - Y) * (1 - K)`, with the samples scaled to 8bit
+  /// or 16bit pixels.  A fifth alpha component is kept.  Images in other color spaces
+  /// are converted as by `get_pixels`.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// // Red, black and a 50% gray (K only).
+  /// let comp = |samples: [i32; 3]| ComponentData::new(samples.to_vec(), 8);
+  /// let img = Image::from_components(3, 1, ColorSpace::CMYK, vec![
+  ///   comp([0, 0, 0]),
+  ///   comp([255, 0, 0]),
+  ///   comp([255, 0, 0]),
+  ///   comp([0, 255, 128]),
+  /// ])?;
+  /// assert!(img.get_pixels(None).is_err());
+  /// let pixels = img.get_pixels_with_cmyk(None)?;
+  /// assert_eq!((pixels.format, pixels.color_space), (ImageFormat::Rgb8, ColorSpace::SRGB));
+  /// assert_eq!(pixels.data, ImagePixelData::Rgb8(vec![255, 0, 0, 0, 0, 0, 127, 127, 127]));
+  ///
+  /// let pixels = img.get_pixels_with_cmyk(Some(255))?;
+  /// assert_eq!(pixels.format, ImageFormat::Rgba8);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn get_pixels_with_cmyk(&self, alpha_default: Option<u32>) -> Result<ImageData> {
+    if self.color_space() != ColorSpace::CMYK {
+      return self.get_pixels(alpha_default);
+    }
+    self.rgb_pixels(4, alpha_default, cmyk_to_rgb)
+  }
+
+  /// Convert an image with `num_color` color components (and an optional alpha component)
+  /// to sRGB pixels.
+  ///
+  /// The components are mapped onto the pixel grid (subsampled components are upsampled)
+  /// and scaled to 8bit or 16bit before `to_rgb` converts the color samples of each
+  /// pixel, given the max sample value.
+  fn rgb_pixels(
+    &self,
+    num_color: usize,
+    alpha_default: Option<u32>,
+    to_rgb: fn(&[u32], u32) -> [u32; 3],
+  ) -> Result<ImageData> {
+    let comps = self.components();
+    if comps.len() < num_color {
+      return Err(Error::UnsupportedComponentsError(self.num_components()));
+    }
+    let (color, alpha) = match comps.split_at(num_color) {
+      (color, []) => (color, None),
+      (color, [alpha]) if self.channel_info().alpha => (color, Some(alpha)),
+      _ => return Err(Error::UnsupportedComponentsError(self.num_components())),
+    };
+    let num_rgb = 3 + (alpha.is_some() || alpha_default.is_some()) as usize;
+    match self.channel_info().max_precision {
+      1..=8 => {
+        let (width, height, num_channels, pixels) =
+          self.build_pixels_from(color, alpha, None, |c| c.data_u8())?;
+        let pixels = convert_to_rgb(
+          &pixels,
+          num_channels,
+          num_color,
+          u8::MAX as u32,
+          |v| v as u8,
+          clamp_alpha(alpha_default, 8),
+          to_rgb,
+        );
+        Ok(ImageData::from_u8(
+          ColorSpace::SRGB,
+          width,
+          height,
+          num_rgb,
+          pixels,
+        ))
+      }
+      9..=16 => {
+        let (width, height, num_channels, pixels) =
+          self.build_pixels_from(color, alpha, None, |c| c.data_u16())?;
+        let pixels = convert_to_rgb(
+          &pixels,
+          num_channels,
+          num_color,
+          u16::MAX as u32,
+          |v| v as u16,
+          clamp_alpha(alpha_default, 16),
+          to_rgb,
+        );
+        Ok(ImageData::from_u16(
+          ColorSpace::SRGB,
+          width,
+          height,
+          num_rgb,
+          pixels,
+        ))
+      }
+      _ => Err(Error::UnsupportedComponentsError(self.num_components())),
+    }
+  }
+
+  /// Convert image components into 16bit interleaved pixels.
+  ///
+  /// Returns `(width, height, num_channels, pixels)`.  Components with a lower precision
+  /// are scaled up to 16bit (for example 8bit `255` becomes `65535`).
+  ///
+  /// `alpha_default` - The default value for the alpha channel if there is no alpha component.
+  pub fn to_u16_interleaved(&self, alpha_default: Option<u16>) -> Result<(u32, u32, u8, Vec<u16>)> {
+    let (width, height, num_channels, pixels) = self.build_pixels(alpha_default, |c| {
+      Box::new(c.data_scaled(16).map(|p| p as u16))
+    })?;
+    Ok((width, height, num_channels as u8, pixels))
+  }
+
+  /// Pack a single component 1, 2 or 4 bit image into bytes.
+  ///
+  /// Returns `(width, height, precision, packed)`.  Samples are packed MSB-first (the first
+  /// sample is in the high bits of the byte) and each row starts on a byte boundary, so rows
+  /// are `(width * precision + 7) / 8` bytes long with the unused low bits of the last
+  /// byte set to zero.  This matches the PBM bit-packing, but the sample values are
+  /// not inverted (PBM uses `1` for black).
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let samples = vec![1, 0, 1, 1, 0, 0, 0, 0, 1, 1, 0, 1, 0, 1, 0, 1, 1, 0];
+  /// let img = Image::from_components(9, 2, ColorSpace::Gray, vec![ComponentData::new(samples.clone(), 1)])?;
+  /// let (width, height, prec, packed) = img.to_packed_bits()?;
+  /// assert_eq!((width, height, prec), (9, 2, 1));
+  /// assert_eq!(packed, [0b1011_0000, 0b1000_0000, 0b1010_1011, 0b0000_0000]);
+  ///
+  /// // Unpack the rows.
+  /// let stride = (width * prec).div_ceil(8) as usize;
+  /// let unpacked: Vec<i32> = packed
+  ///   .chunks(stride)
+  ///   .flat_map(|row| (0..width as usize).map(move |x| ((row[x / 8] >> (7 - x % 8)) & 1) as i32))
+  ///   .collect();
+  /// assert_eq!(unpacked, samples);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn to_packed_bits(&self) -> Result<(u32, u32, u32, Vec<u8>)> {
+    let comps = self.components();
+    let comp = match comps {
+      [comp] => comp,
+      _ => return Err(Error::UnsupportedComponentsError(self.num_components())),
+    };
+    let prec = comp.precision();
+    if !matches!(prec, 1 | 2 | 4) {
+      return Err(Error::UnsupportedPrecisionError(prec));
+    }
+    let width = comp.width() as usize;
+    let height = comp.height() as usize;
+    let per_byte = 8 / prec as usize;
+    let stride = width.div_ceil(per_byte);
+    let mut packed = vec![0u8; stride * height];
+    if width > 0 {
+      let samples = comp.data_scaled(prec).collect::<Vec<_>>();
+      for (row, out) in samples.chunks(width).zip(packed.chunks_mut(stride)) {
+        for (x, sample) in row.iter().enumerate() {
+          let shift = 8 - prec as usize * (x % per_byte + 1);
+          out[x / per_byte] |= (*sample as u8) << shift;
+        }
+      }
+    }
+    Ok((comp.width(), comp.height(), prec, packed))
+  }
+
+  /// Convert image components into 8bit pixels using a window (center/width).
+  ///
+  /// This is the window/level transform used to display medical and scientific images:
+  /// samples (raw values, not scaled) below the window map to `0`, samples above the window
+  /// map to `255` and samples inside the window are mapped linearly (DICOM linear function).
+  /// The alpha component isn't windowed, it is scaled to 8bit.
+  ///
+  /// `width` must be at least `1.0`.
+  ///
+  /// `alpha_default` - The default value for the alpha channel if there is no alpha component.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> jpeg2k::error::Result<()> {
+  /// let samples = vec![0, 1000, 2000, 3000];
+  /// let img = Image::from_components(4, 1, ColorSpace::Gray, vec![ComponentData::new(samples, 12)])?;
+  /// let ImageData { data, .. } = img.window_to_u8(2000.0, 2000.0, None)?;
+  /// assert!(matches!(data, ImagePixelData::L8(p) if p == [0, 0, 128, 255]));
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn window_to_u8(
+    &self,
+    center: f64,
+    width: f64,
+    alpha_default: Option<u8>,
+  ) -> Result<ImageData> {
+    if width.is_nan() || width < 1.0 || center.is_nan() {
+      return Err(Error::InvalidParametersError(format!(
+        "window center {center} and width {width}, the width must be at least 1"
+      )));
+    }
+    let (_, alpha) = self.pixel_layout()?;
+    let window = width;
+    let (width, height, num_channels, pixels) = self.build_pixels(alpha_default, |c| {
+      if alpha.is_some_and(|a| ptr::eq(a, c)) {
+        return c.data_u8();
+      }
+      Box::new(c.data().iter().map(move |&p| {
+        let v = ((p as f64 - (center - 0.5)) / (window - 1.0) + 0.5) * 255.0;
+        v.round().clamp(0.0, 255.0) as u8
+      }))
+    })?;
+    Ok(ImageData::from_u8(
+      self.color_space(),
+      width,
+      height,
+      num_channels,
+      pixels,
+    ))
+  }
+
+  /// Convert image components into pixels scaled to `target_prec` bits.
+  ///
+  /// All components are scaled to `target_prec` (`1..=16`) bits, no matter their
+  /// precision.  The samples are stored as `u8` for `target_prec <= 8` and `u16` for
+  /// `9..=16`.  For example a `target_prec` of 12 gives 16bit pixels with values in `0..=4095`.
+  ///
+  /// `alpha_default` - The default value for the alpha channel if there is no alpha component.
+  ///
+  /// Components without the expected number of samples are an error (for example when
+  /// only the header was read).
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> anyhow::Result<()> {
+  /// let img = Image::from_file("samples/file6.jp2")?;
+  /// let pixels = img.get_pixels_at_precision(12, None)?;
+  /// assert_eq!(pixels.format, ImageFormat::L16);
+  /// let ImagePixelData::L16(data) = pixels.data else { unreachable!() };
+  /// assert_eq!(data.len(), 768 * 512);
+  /// assert!(data.iter().all(|&p| p <= 4095));
+  ///
+  /// // Only the header: the components have no samples.
+  /// let dump = DumpImage::from_file("samples/file6.jp2")?;
+  /// assert!(dump.img.get_pixels_at_precision(12, None).is_err());
+  /// assert!(dump.img.get_pixels(None).is_err());
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn get_pixels_at_precision(
+    &self,
+    target_prec: u32,
+    alpha_default: Option<u32>,
+  ) -> Result<ImageData> {
+    match target_prec {
+      1..=8 => {
+        let (width, height, num_channels, pixels) = self
+          .build_pixels(clamp_alpha(alpha_default, target_prec), |c| {
+            Box::new(c.data_scaled(target_prec).map(|p| p as u8))
+          })?;
+        Ok(ImageData::from_u8(
+          self.color_space(),
+          width,
+          height,
+          num_channels,
+          pixels,
+        ))
+      }
+      9..=16 => {
+        let (width, height, num_channels, pixels) = self
+          .build_pixels(clamp_alpha(alpha_default, target_prec), |c| {
+            Box::new(c.data_scaled(target_prec).map(|p| p as u16))
+          })?;
+        Ok(ImageData::from_u16(
+          self.color_space(),
+          width,
+          height,
+          num_channels,
+          pixels,
+        ))
+      }
+      _ => Err(Error::UnsupportedPrecisionError(target_prec)),
+    }
+  }
+}
+
+/// Convert interleaved pixels with `num_color` color channels (and alpha) to RGB (and
+/// alpha).
+///
+/// `max` is the max sample value, `from` converts the RGB values back to samples.
+/// `alpha_default` is added if the pixels have no alpha channel.
+fn convert_to_rgb<T: Copy + Into<u32>>(
+  pixels: &[T],
+  num_channels: usize,
+  num_color: usize,
+  max: u32,
+  from: fn(u32) -> T,
+  alpha_default: Option<T>,
+  to_rgb: fn(&[u32], u32) -> [u32; 3],
+) -> Vec<T> {
+  let out_channels = if num_channels > num_color || alpha_default.is_some() {
+    4
+  } else {
+    3
+  };
+  let mut rgb = Vec::with_capacity(pixels.len() / num_channels * out_channels);
+  let mut color = [0u32; 4];
+  for px in pixels.chunks_exact(num_channels) {
+    for (c, &v) in color.iter_mut().zip(&px[..num_color]) {
+      *c = v.into();
+    }
+    rgb.extend(to_rgb(&color[..num_color], max).map(from));
+    if let Some(alpha) = px.get(num_color).copied().or(alpha_default) {
+      rgb.push(alpha);
+    }
+  }
+  rgb
+}
+
+/// Naive CMYK to RGB: `R = (1 - C) * (1 - K)`.
+fn cmyk_to_rgb(cmyk: &[u32], max: u32) -> [u32; 3] {
+  let k = (max - cmyk[3]) as u64;
+  [0, 1, 2].map(|idx| ((max - cmyk[idx]) as u64 * k / max as u64) as u32)
+}
+
+/// YCbCr (BT.601 full range, as JPEG) to RGB.
+fn sycc_to_rgb(ycc: &[u32], max: u32) -> [u32; 3] {
+  let offset = (max / 2 + 1) as f32;
+  let y = ycc[0] as f32;
+  let cb = ycc[1] as f32 - offset;
+  let cr = ycc[2] as f32 - offset;
+  let clamp = |v: f32| v.round().clamp(0.0, max as f32) as u32;
+  [
+    clamp(y + 1.402 * cr),
+    clamp(y - 0.344136 * cb - 0.714136 * cr),
+    clamp(y + 1.772 * cb),
+  ]
+}
+
+/// Clamp `alpha_default` to the max value of `prec` bits pixels.
+fn clamp_alpha<T: TryFrom<u32>>(alpha_default: Option<u32>, prec: u32) -> Option<T> {
+  let max = (1u32 << prec) - 1;
+  alpha_default.and_then(|a| T::try_from(a.min(max)).ok())
+}
+
+/// Load a Jpeg 2000 image from bytes.  It will detect the J2K format.
+impl TryFrom<&[u8]> for Image {
+  type Error = Error;
+
+  fn try_from(buf: &[u8]) -> Result<Image> {
+    Image::from_bytes(buf)
+  }
+}
+
+/// Load a Jpeg 2000 image from owned bytes.  It will detect the J2K format.
+///
+/// The decoded image doesn't borrow from the bytes, they are dropped after decoding.
+impl TryFrom<Vec<u8>> for Image {
+  type Error = Error;
+
+  fn try_from(buf: Vec<u8>) -> Result<Image> {
+    Image::from_bytes(&buf)
+  }
+}
+
+#[cfg(feature = "lcms2")]
+impl Image {
+  /// Convert image components into sRGB pixels, using the embedded ICC profile.
+  ///
+  /// Without an ICC profile this is the same as `get_pixels(None)`.  With an ICC profile,
+  /// an lcms2 transform from the profile to sRGB (perceptual intent) is applied to the
+  /// pixels from `get_pixels`.  Gray and RGB profiles are supported, gray images are
+  /// converted to RGB.  The alpha channel is copied unchanged.
+  ///
+  /// Performance: building the transform parses the profile, which is cheap compared to
+  /// decoding.  The transform itself is a second pass over all pixels with a full copy
+  /// of the pixels (16bit pixels need two extra copies), it can take about as long as
+  /// `get_pixels` for large images.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> anyhow::Result<()> {
+  /// // `file7.jp2` is 16bit RGB with a scanner ICC profile.
+  /// let img = Image::from_file("samples/file7.jp2")?;
+  /// let srgb = img.to_srgb()?;
+  /// assert_eq!((srgb.format, srgb.color_space), (ImageFormat::Rgb16, ColorSpace::SRGB));
+  /// assert_ne!(srgb.data, img.get_pixels(None)?.data);
+  ///
+  /// // No ICC profile.
+  /// let img = Image::from_file("samples/file6.jp2")?;
+  /// assert_eq!(img.to_srgb()?, img.get_pixels(None)?);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn to_srgb(&self) -> Result<ImageData> {
+    self.get_pixels_color_managed(None)
+  }
+
+  /// Like [`get_pixels`](Self::get_pixels), with the pixels converted to sRGB using the
+  /// embedded ICC profile (see [`to_srgb`](Self::to_srgb)).
+  ///
+  /// `alpha_default` is used as in `get_pixels`, the alpha channel isn't color managed.
+  /// Without an ICC profile the pixels are the same as from `get_pixels`.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> anyhow::Result<()> {
+  /// // `file7.jp2` is 16bit RGB with a scanner ICC profile.
+  /// let img = Image::from_file("samples/file7.jp2")?;
+  /// let pixels = img.get_pixels_color_managed(Some(65535))?;
+  /// assert_eq!(pixels.format, ImageFormat::Rgba16);
+  /// let ImagePixelData::Rgba16(data) = &pixels.data else { unreachable!() };
+  /// assert!(data.chunks(4).all(|px| px[3] == 65535));
+  ///
+  /// let img = Image::from_file("samples/file6.jp2")?;
+  /// assert_eq!(img.get_pixels_color_managed(None)?, img.get_pixels(None)?);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn get_pixels_color_managed(&self, alpha_default: Option<u32>) -> Result<ImageData> {
+    use lcms2::{ColorSpaceSignature, Flags, Intent, PixelFormat, Profile, Transform};
+
+    let pixels = self.get_pixels(alpha_default)?;
+    let Some(icc) = self.icc_profile() else {
+      return Ok(pixels);
+    };
+    let profile = Profile::new_icc(icc)
+      .map_err(|err| Error::CodecError(format!("Invalid ICC profile: {err}")))?;
+    let is_gray = matches!(
+      pixels.format,
+      ImageFormat::L8 | ImageFormat::La8 | ImageFormat::L16 | ImageFormat::La16
+    );
+    let expected = if is_gray {
+      ColorSpaceSignature::GrayData
+    } else {
+      ColorSpaceSignature::RgbData
+    };
+    if profile.color_space() != expected {
+      return Err(Error::CodecError(format!(
+        "ICC profile color space {:?} doesn't match the {:?} pixels",
+        profile.color_space(),
+        pixels.format
+      )));
+    }
+    let (in_format, out_format, format) = match pixels.format {
+      ImageFormat::L8 => (PixelFormat::GRAY_8, PixelFormat::RGB_8, ImageFormat::Rgb8),
+      ImageFormat::La8 => (
+        PixelFormat::GRAYA_8,
+        PixelFormat::RGBA_8,
+        ImageFormat::Rgba8,
+      ),
+      ImageFormat::Rgb8 => (PixelFormat::RGB_8, PixelFormat::RGB_8, ImageFormat::Rgb8),
+      ImageFormat::Rgba8 => (PixelFormat::RGBA_8, PixelFormat::RGBA_8, ImageFormat::Rgba8),
+      ImageFormat::L16 => (
+        PixelFormat::GRAY_16,
+        PixelFormat::RGB_16,
+        ImageFormat::Rgb16,
+      ),
+      ImageFormat::La16 => (
+        PixelFormat::GRAYA_16,
+        PixelFormat::RGBA_16,
+        ImageFormat::Rgba16,
+      ),
+      ImageFormat::Rgb16 => (PixelFormat::RGB_16, PixelFormat::RGB_16, ImageFormat::Rgb16),
+      ImageFormat::Rgba16 => (
+        PixelFormat::RGBA_16,
+        PixelFormat::RGBA_16,
+        ImageFormat::Rgba16,
+      ),
+    };
+    let transform = Transform::<u8, u8>::new_flags(
+      &profile,
+      in_format,
+      &Profile::new_srgb(),
+      out_format,
+      Intent::Perceptual,
+      Flags::COPY_ALPHA,
+    )
+    .map_err(|err| Error::CodecError(format!("Failed to create ICC transform: {err}")))?;
+    let num_pixels = pixels.width as usize * pixels.height as usize;
+    let out_channels = match format {
+      ImageFormat::Rgb8 | ImageFormat::Rgb16 => 3,
+      _ => 4,
+    };
+    // lcms2 takes the pixels as bytes, in native byte order for 16bit pixels.
+    let data = match pixels.data {
+      ImagePixelData::L8(src)
+      | ImagePixelData::La8(src)
+      | ImagePixelData::Rgb8(src)
+      | ImagePixelData::Rgba8(src) => {
+        let mut dst = vec![0u8; num_pixels * out_channels];
+        transform.transform_pixels(&src, &mut dst);
+        match format {
+          ImageFormat::Rgb8 => ImagePixelData::Rgb8(dst),
+          _ => ImagePixelData::Rgba8(dst),
+        }
+      }
+      ImagePixelData::L16(src)
+      | ImagePixelData::La16(src)
+      | ImagePixelData::Rgb16(src)
+      | ImagePixelData::Rgba16(src) => {
+        let src = src
+          .iter()
+          .flat_map(|p| p.to_ne_bytes())
+          .collect::<Vec<u8>>();
+        let mut dst = vec![0u8; num_pixels * out_channels * 2];
+        transform.transform_pixels(&src, &mut dst);
+        let dst = dst
+          .chunks_exact(2)
+          .map(|b| u16::from_ne_bytes([b[0], b[1]]))
+          .collect();
+        match format {
+          ImageFormat::Rgb16 => ImagePixelData::Rgb16(dst),
+          _ => ImagePixelData::Rgba16(dst),
+        }
+      }
+    };
+    Ok(ImageData {
+      width: pixels.width,
+      height: pixels.height,
+      format,
+      color_space: ColorSpace::SRGB,
+      data,
+    })
+  }
+}
+
+#[cfg(feature = "image")]
+impl Image {
+  /// Convert to a `image::DynamicImage`.
+  ///
+  /// `alpha_default` - The default value for the alpha channel if there is no alpha component.
+  pub fn to_dynamic_image(&self, alpha_default: Option<u32>) -> Result<::image::DynamicImage> {
+    use image::*;
+    if let Some(img) = self.to_rgb8_direct(alpha_default) {
+      return Ok(img);
+    }
+    let ImageData {
+      width,
+      height,
+      data,
+      ..
+    } = self.get_pixels(alpha_default)?;
+    match data {
+      crate::ImagePixelData::L8(data) => {
+        let gray = GrayImage::from_vec(width, height, data)
+          .expect("Shouldn't happen.  Report to jpeg2k if you see this.");
+
+        Ok(DynamicImage::ImageLuma8(gray))
+      }
+      crate::ImagePixelData::La8(data) => {
+        let gray = GrayAlphaImage::from_vec(width, height, data)
+          .expect("Shouldn't happen.  Report to jpeg2k if you see this.");
+
+        Ok(DynamicImage::ImageLumaA8(gray))
+      }
+      crate::ImagePixelData::Rgb8(data) => {
+        let rgb = RgbImage::from_vec(width, height, data)
+          .expect("Shouldn't happen.  Report to jpeg2k if you see this.");
+
+        Ok(DynamicImage::ImageRgb8(rgb))
+      }
+      crate::ImagePixelData::Rgba8(data) => {
+        let rgba = RgbaImage::from_vec(width, height, data)
+          .expect("Shouldn't happen.  Report to jpeg2k if you see this.");
+
+        Ok(DynamicImage::ImageRgba8(rgba))
+      }
+      crate::ImagePixelData::L16(data) => {
+        let gray = ImageBuffer::from_vec(width, height, data)
+          .expect("Shouldn't happen.  Report to jpeg2k if you see this.");
+
+        Ok(DynamicImage::ImageLuma16(gray))
+      }
+      crate::ImagePixelData::La16(data) => {
         let gray = ImageBuffer::from_vec(width, height, data)
           .expect("Shouldn't happen.  Report to jpeg2k if you see this.");
 
@@ -529,4 +4019,259 @@ impl TryFrom<&Image> for ::image::DynamicImage {
       }
     }
   }
+
+  /// Convert the image to `format` (PNG, JPEG, ...) with the `image` crate and write it
+  /// to `w`.
+  ///
+  /// The image is converted with [`Image::to_dynamic_image`], 9 to 16bit images are
+  /// written as 16bit if the format supports it (PNG).  JPEG only supports 8bit images
+  /// without alpha, other images are converted to 8bit gray or RGB (the alpha channel is
+  /// dropped).  Only the formats enabled in the `image` crate can be written, by default
+  /// PNG and JPEG.
+  ///
+  /// ```
+  /// # use jpeg2k::*;
+  /// # fn main() -> anyhow::Result<()> {
+  /// // `file6.jp2` is 12bit gray.
+  /// let img = Image::from_file("samples/file6.jp2")?;
+  /// let mut png = std::io::Cursor::new(Vec::new());
+  /// img.write_to(&mut png, image::ImageFormat::Png)?;
+  /// let png = image::load_from_memory(png.get_ref())?;
+  /// assert_eq!(png.color(), image::ColorType::L16);
+  /// assert_eq!(png, img.to_dynamic_image(None)?);
+  ///
+  /// let mut jpeg = std::io::Cursor::new(Vec::new());
+  /// img.write_to(&mut jpeg, image::ImageFormat::Jpeg)?;
+  /// let jpeg = image::load_from_memory(jpeg.get_ref())?;
+  /// assert_eq!((jpeg.color(), jpeg.width()), (image::ColorType::L8, 768));
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn write_to<W: std::io::Write + std::io::Seek>(
+    &self,
+    w: &mut W,
+    format: ::image::ImageFormat,
+  ) -> Result<()> {
+    use image::*;
+    let mut img = self.to_dynamic_image(None)?;
+    if format == ImageFormat::Jpeg {
+      img = match img.color() {
+        ColorType::L8 | ColorType::Rgb8 => img,
+        ColorType::L16 | ColorType::La8 | ColorType::La16 => {
+          DynamicImage::ImageLuma8(img.to_luma8())
+        }
+        _ => DynamicImage::ImageRgb8(img.to_rgb8()),
+      };
+    }
+    img
+      .write_to(w, format)
+      .map_err(|err| Error::Other(err.into()))
+  }
+
+  /// Fast path for 8bit RGB/RGBA images: interleave the samples directly into the final
+  /// buffer.  Returns `None` for other images, they use the generic `get_pixels` path.
+  fn to_rgb8_direct(&self, alpha_default: Option<u32>) -> Option<::image::DynamicImage> {
+    use image::*;
+    let (color, alpha) = self.pixel_layout().ok()?;
+    let (width, height) = (self.width(), self.height());
+    let len = width as usize * height as usize;
+    let [r, g, b] = color else {
+      return None;
+    };
+    let direct = |c: &ImageComponent| {
+      c.precision() == 8 && !c.is_signed() && c.data().len() == len && self.sample_map(c).is_none()
+    };
+    if !color.iter().chain(alpha).all(direct) {
+      return None;
+    }
+    let rgb = r.data().iter().zip(g.data()).zip(b.data());
+    match (alpha, alpha_default) {
+      (None, None) => {
+        let mut buf = vec![0u8; len * 3];
+        for (px, ((r, g), b)) in buf.chunks_exact_mut(3).zip(rgb) {
+          px.copy_from_slice(&[*r as u8, *g as u8, *b as u8]);
+        }
+        RgbImage::from_vec(width, height, buf).map(DynamicImage::ImageRgb8)
+      }
+      (alpha, alpha_default) => {
+        let mut buf = vec![0u8; len * 4];
+        let pixels = buf.chunks_exact_mut(4).zip(rgb);
+        match alpha {
+          Some(a) => {
+            for ((px, ((r, g), b)), a) in pixels.zip(a.data()) {
+              px.copy_from_slice(&[*r as u8, *g as u8, *b as u8, *a as u8]);
+            }
+          }
+          None => {
+            let a = clamp_alpha::<u8>(alpha_default, 8).unwrap_or_default();
+            for (px, ((r, g), b)) in pixels {
+              px.copy_from_slice(&[*r as u8, *g as u8, *b as u8, a]);
+            }
+          }
+        }
+        RgbaImage::from_vec(width, height, buf).map(DynamicImage::ImageRgba8)
+      }
+    }
+  }
+}
+
+/// UUID of the `uuid` box holding the PNG preview written by
+/// [`Image::save_as_file_with_preview`].
+#[cfg(feature = "image")]
+pub const PREVIEW_UUID: Uuid = [
+  0xb7, 0xa3, 0xf0, 0xc2, 0x5e, 0x1d, 0x4c, 0x8a, 0x9f, 0x2e, 0x3d, 0x6b, 0x8a, 0x1c, 0x4e, 0x70,
+];
+
+#[cfg(all(feature = "image", feature = "file-io"))]
+impl Image {
+  /// Save image to a JP2 file with an embedded preview image.
+  ///
+  /// The preview is a PNG image that fits in `preview_max_dim x preview_max_dim`.  It is
+  /// made from a reduced resolution decode of the encoded image, so it is cheap to create.
+  ///
+  /// The PNG is stored in a `uuid` box (UUID [`PREVIEW_UUID`]) after the codestream box.
+  /// Jpeg 2000 readers ignore unknown `uuid` boxes.  Only the JP2 format has boxes,
+  /// saving a J2K codestream with a preview is an error.
+  pub fn save_as_file_with_preview<P: AsRef<Path>>(
+    &self,
+    path: P,
+    params: EncodeParameters,
+    preview_max_dim: u32,
+  ) -> Result<()> {
+    let path = path.as_ref();
+    if j2k_detect_format_from_extension(path.extension())? != J2KFormat::JP2 {
+      return Err(Error::UnknownFormatError(format!(
+        "A preview can only be saved in a JP2 file: {:?}",
+        path
+      )));
+    }
+    let stream = Stream::to_bytes(J2KFormat::JP2)?;
+    let mut buf = self
+      .to_stream(stream, params)?
+      .take_bytes()
+      .ok_or_else(|| Error::CodecError("No encoded bytes".into()))?;
+
+    // Decode the smallest resolution level that is still larger than the preview.
+    let preview_max_dim = preview_max_dim.max(1);
+    let num_resolutions = Self::header_from_bytes(&buf)?.num_resolutions().max(1);
+    let max_dim = self.orig_width().max(self.orig_height());
+    let reduce = (0..num_resolutions)
+      .take_while(|r| (max_dim >> r) >= preview_max_dim)
+      .last()
+      .unwrap_or(0);
+    let preview = Image::from_bytes_with(&buf, DecodeParameters::new().reduce(reduce))?
+      .to_dynamic_image(None)?
+      .thumbnail(preview_max_dim, preview_max_dim);
+    let mut png = std::io::Cursor::new(Vec::new());
+    preview
+      .write_to(&mut png, ::image::ImageFormat::Png)
+      .map_err(|err| Error::Other(err.into()))?;
+    let png = png.into_inner();
+
+    // Append the `uuid` box.
+    let box_len = 8 + PREVIEW_UUID.len() + png.len();
+    let box_len = u32::try_from(box_len)
+      .map_err(|_| Error::CodecError("Preview is too large for a box".into()))?;
+    buf.extend_from_slice(&box_len.to_be_bytes());
+    buf.extend_from_slice(b"uuid");
+    buf.extend_from_slice(&PREVIEW_UUID);
+    buf.extend_from_slice(&png);
+    std::fs::write(path, buf).map_err(|err| Error::Other(err.into()))?;
+    Ok(())
+  }
+}
+
+/// Try to convert a loaded Jpeg 2000 image into a `image::DynamicImage`.
+#[cfg(feature = "image")]
+impl TryFrom<&Image> for ::image::DynamicImage {
+  type Error = Error;
+
+  fn try_from(img: &Image) -> Result<::image::DynamicImage> {
+    img.to_dynamic_image(None)
+  }
+}
+
+/// Split interleaved samples into one component per channel.  With 2 or 4 channels
+/// the last one is alpha.
+fn deinterleave<T: Copy + Into<i32>>(
+  samples: &[T],
+  num_channels: usize,
+  precision: u32,
+) -> Vec<ComponentData> {
+  let has_alpha = num_channels == 2 || num_channels == 4;
+  (0..num_channels)
+    .map(|channel| {
+      let data = samples
+        .iter()
+        .skip(channel)
+        .step_by(num_channels)
+        .map(|&s| s.into())
+        .collect();
+      ComponentData::new(data, precision).alpha(has_alpha && channel == num_channels - 1)
+    })
+    .collect()
+}
+
+/// Create a Jpeg 2000 image from a `image::DynamicImage`, for encoding.
+///
+/// 8bit and 16bit luma, luma + alpha, RGB and RGBA images are supported.  The last channel
+/// of luma + alpha and RGBA images is marked as an alpha channel.
+///
+/// ```
+/// # use jpeg2k::*;
+/// use jpeg2k::format::J2KFormat;
+/// use image::{DynamicImage, Rgba, RgbaImage};
+/// # fn main() -> anyhow::Result<()> {
+/// let rgba = RgbaImage::from_fn(64, 48, |x, y| Rgba([x as u8, y as u8, (x * y) as u8, 200]));
+/// let src = DynamicImage::ImageRgba8(rgba);
+/// let img = Image::try_from(&src)?;
+/// assert_eq!(img.color_space(), ColorSpace::SRGB);
+/// assert!(img.components()[3].is_alpha());
+///
+/// // Lossless round-trip through JP2.
+/// let jp2 = img.into_bytes(J2KFormat::JP2, EncodeParameters::default())?;
+/// let decoded: DynamicImage = (&Image::from_bytes(&jp2)?).try_into()?;
+/// assert_eq!(decoded, src);
+///
+/// let gray = DynamicImage::ImageLuma16(image::ImageBuffer::from_fn(64, 48, |x, y| {
+///   image::Luma([(x * 1000 + y) as u16])
+/// }));
+/// let img = Image::try_from(&gray)?;
+/// assert_eq!(img.components()[0].precision(), 16);
+/// assert_eq!(img.to_dynamic_image(None)?, gray);
+///
+/// assert!(Image::try_from(&DynamicImage::new_rgb32f(8, 8)).is_err());
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "image")]
+impl TryFrom<&::image::DynamicImage> for Image {
+  type Error = Error;
+
+  fn try_from(img: &::image::DynamicImage) -> Result<Image> {
+    use ::image::DynamicImage;
+
+    let comps = match img {
+      DynamicImage::ImageLuma8(img) => deinterleave(img.as_raw(), 1, 8),
+      DynamicImage::ImageLumaA8(img) => deinterleave(img.as_raw(), 2, 8),
+      DynamicImage::ImageRgb8(img) => deinterleave(img.as_raw(), 3, 8),
+      DynamicImage::ImageRgba8(img) => deinterleave(img.as_raw(), 4, 8),
+      DynamicImage::ImageLuma16(img) => deinterleave(img.as_raw(), 1, 16),
+      DynamicImage::ImageLumaA16(img) => deinterleave(img.as_raw(), 2, 16),
+      DynamicImage::ImageRgb16(img) => deinterleave(img.as_raw(), 3, 16),
+      DynamicImage::ImageRgba16(img) => deinterleave(img.as_raw(), 4, 16),
+      img => {
+        return Err(Error::InvalidParametersError(format!(
+          "unsupported pixel format {:?}",
+          img.color()
+        )));
+      }
+    };
+    let color_space = if comps.len() >= 3 {
+      ColorSpace::SRGB
+    } else {
+      ColorSpace::Gray
+    };
+    Image::from_components(img.width(), img.height(), color_space, comps)
+  }
 }
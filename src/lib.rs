@@ -4,21 +4,22 @@
 //!
 //! ## Example: Convert a Jpeg 2000 image to a png image.
 //!
-//! ```rust
+//! ```rust,no_run
 //! use image::DynamicImage;
 //!
 //! use jpeg2k::*;
 //!
-//! fn main() {
+//! fn main() -> Result<(), Box<dyn std::error::Error>> {
 //!   // Load jpeg 2000 file from file.
 //!   let jp2_image = Image::from_file("./assets/example.j2k")
-//! 		.expect("Failed to load j2k file.");
+//!     .expect("Failed to load j2k file.");
 //!
 //!   // Convert to a `image::DynamicImage`
-//!   let img: DynamicImage = jp2_image.try_into()?;
+//!   let img: DynamicImage = (&jp2_image).try_into()?;
 //!
 //!   // Save as png file.
 //!   img.save("out.png")?;
+//!   Ok(())
 //! }
 //! ```
 
@@ -29,11 +30,16 @@ pub(crate) use format::*;
 pub mod error;
 pub(crate) use error::*;
 
+/// Test image generators.
+#[cfg(feature = "test-util")]
+pub mod test_patterns;
+
 #[cfg(feature = "openjpeg-sys")]
 pub(crate) use openjpeg_sys as sys;
 
 #[cfg(feature = "openjp2")]
 pub(crate) mod sys {
+  pub use openjp2::image::opj_image_cmptparm_t;
   pub use openjp2::openjpeg::*;
 }
 
@@ -43,23 +49,41 @@ impl From<J2KFormat> for sys::CODEC_FORMAT {
     match format {
       JP2 => sys::CODEC_FORMAT::OPJ_CODEC_JP2,
       J2K => sys::CODEC_FORMAT::OPJ_CODEC_J2K,
+      JPX => sys::CODEC_FORMAT::OPJ_CODEC_JPX,
+      JPP => sys::CODEC_FORMAT::OPJ_CODEC_JPP,
+      JPT => sys::CODEC_FORMAT::OPJ_CODEC_JPT,
     }
   }
 }
 
 pub(crate) mod codec;
+pub(crate) mod document;
 pub(crate) mod dump;
+#[cfg(feature = "geojp2")]
+pub(crate) mod geo;
+pub(crate) mod info;
 pub(crate) mod j2k_image;
+#[cfg(feature = "metrics")]
+pub(crate) mod metrics;
+pub(crate) mod profile;
 pub(crate) mod stream;
 
 pub use codec::*;
+pub use document::*;
 pub use dump::*;
+#[cfg(feature = "geojp2")]
+pub use geo::*;
+pub use info::*;
+#[cfg(feature = "metrics")]
+pub use metrics::*;
+pub use profile::*;
 pub(crate) use stream::*;
 
 pub use self::j2k_image::*;
 
 /// Image color space.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ColorSpace {
   Unknown,
   Unspecified,
@@ -70,6 +94,36 @@ pub enum ColorSpace {
   CMYK,
 }
 
+impl ColorSpace {
+  /// From the raw OpenJpeg `COLOR_SPACE` value.  Unknown values map to `ColorSpace::Unknown`.
+  ///
+  /// ```
+  /// # use jpeg2k::ColorSpace;
+  /// use ColorSpace::*;
+  /// for color in [Unknown, Unspecified, SRGB, Gray, SYCC, EYCC, CMYK] {
+  ///   assert_eq!(ColorSpace::from_raw(color.to_raw()), color);
+  /// }
+  /// assert_eq!(ColorSpace::from_raw(42), Unknown);
+  /// ```
+  pub fn from_raw(raw: i32) -> Self {
+    use ColorSpace::*;
+    match raw {
+      0 => Unspecified,
+      1 => SRGB,
+      2 => Gray,
+      3 => SYCC,
+      4 => EYCC,
+      5 => CMYK,
+      _ => Unknown,
+    }
+  }
+
+  /// To the raw OpenJpeg `COLOR_SPACE` value.
+  pub fn to_raw(self) -> i32 {
+    sys::COLOR_SPACE::from(self) as i32
+  }
+}
+
 /// From `ColorSpace` to OpenJpeg `COLOR_SPACE`.
 impl From<ColorSpace> for sys::COLOR_SPACE {
   fn from(color: ColorSpace) -> Self {
@@ -90,16 +144,87 @@ impl From<ColorSpace> for sys::COLOR_SPACE {
 /// From OpenJpeg `COLOR_SPACE` to `ColorSpace`.
 impl From<sys::COLOR_SPACE> for ColorSpace {
   fn from(color: sys::COLOR_SPACE) -> Self {
-    use sys::COLOR_SPACE::*;
-    use ColorSpace::*;
-    match color {
-      OPJ_CLRSPC_UNKNOWN => Unknown,
-      OPJ_CLRSPC_UNSPECIFIED => Unspecified,
-      OPJ_CLRSPC_SRGB => SRGB,
-      OPJ_CLRSPC_GRAY => Gray,
-      OPJ_CLRSPC_SYCC => SYCC,
-      OPJ_CLRSPC_EYCC => EYCC,
-      OPJ_CLRSPC_CMYK => CMYK,
+    Self::from_raw(color as i32)
+  }
+}
+
+/// Progression order of the packets in the codestream.
+///
+/// The letters give the nesting of the packet loops, outermost first: Layer, Resolution,
+/// Component and Position (precinct).  `RPCL` and `PCRL` are used for resolution and
+/// position progressive streaming.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ProgressionOrder {
+  LRCP,
+  RLCP,
+  RPCL,
+  PCRL,
+  CPRL,
+}
+
+impl ProgressionOrder {
+  /// From the raw OpenJpeg `PROG_ORDER` value.  Unknown values map to `None`.
+  ///
+  /// ```
+  /// # use jpeg2k::ProgressionOrder;
+  /// use ProgressionOrder::*;
+  /// for order in [LRCP, RLCP, RPCL, PCRL, CPRL] {
+  ///   assert_eq!(ProgressionOrder::from_raw(order.to_raw()), Some(order));
+  /// }
+  /// assert_eq!(ProgressionOrder::from_raw(-1), None);
+  /// assert_eq!(RPCL.to_string(), "RPCL");
+  /// ```
+  pub fn from_raw(raw: i32) -> Option<Self> {
+    use ProgressionOrder::*;
+    match raw {
+      0 => Some(LRCP),
+      1 => Some(RLCP),
+      2 => Some(RPCL),
+      3 => Some(PCRL),
+      4 => Some(CPRL),
+      _ => None,
+    }
+  }
+
+  /// To the raw OpenJpeg `PROG_ORDER` value.
+  pub fn to_raw(self) -> i32 {
+    use ProgressionOrder::*;
+    match self {
+      LRCP => 0,
+      RLCP => 1,
+      RPCL => 2,
+      PCRL => 3,
+      CPRL => 4,
+    }
+  }
+}
+
+impl std::fmt::Display for ProgressionOrder {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    std::fmt::Debug::fmt(self, f)
+  }
+}
+
+/// From `ProgressionOrder` to the raw OpenJpeg `PROG_ORDER` value.
+impl From<ProgressionOrder> for i32 {
+  fn from(order: ProgressionOrder) -> Self {
+    order.to_raw()
+  }
+}
+
+/// From `ProgressionOrder` to OpenJpeg `PROG_ORDER`.
+#[cfg(feature = "openjpeg-sys")]
+impl From<ProgressionOrder> for sys::PROG_ORDER {
+  fn from(order: ProgressionOrder) -> Self {
+    use sys::PROG_ORDER::*;
+    use ProgressionOrder::*;
+    match order {
+      LRCP => OPJ_LRCP,
+      RLCP => OPJ_RLCP,
+      RPCL => OPJ_RPCL,
+      PCRL => OPJ_PCRL,
+      CPRL => OPJ_CPRL,
     }
   }
 }
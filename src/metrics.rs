@@ -0,0 +1,85 @@
+use super::*;
+
+/// Check that the images have the same number of components with matching sizes and precision.
+fn check_compatible(a: &Image, b: &Image) -> Result<()> {
+  if a.num_components() != b.num_components() {
+    return Err(Error::InvalidComponentError(format!(
+      "images have a different number of components: {} vs {}",
+      a.num_components(),
+      b.num_components()
+    )));
+  }
+  if a.num_components() == 0 {
+    return Err(Error::UnsupportedComponentsError(0));
+  }
+  for (idx, (ca, cb)) in a.components().iter().zip(b.components()).enumerate() {
+    if (ca.width(), ca.height()) != (cb.width(), cb.height()) {
+      return Err(Error::InvalidComponentError(format!(
+        "component {idx} size is different: {}x{} vs {}x{}",
+        ca.width(),
+        ca.height(),
+        cb.width(),
+        cb.height()
+      )));
+    }
+    if ca.precision() != cb.precision() || ca.is_signed() != cb.is_signed() {
+      return Err(Error::InvalidComponentError(format!(
+        "component {idx} precision is different: {} vs {}",
+        ca.precision(),
+        cb.precision()
+      )));
+    }
+    if ca.data().len() != cb.data().len() || ca.data().is_empty() {
+      return Err(Error::InvalidComponentError(format!(
+        "component {idx} has no decoded data"
+      )));
+    }
+  }
+  Ok(())
+}
+
+/// Peak signal-to-noise ratio (in dB) between two images.
+///
+/// The PSNR is computed for each component from the mean squared error of the raw samples,
+/// using the component precision for the peak value.  Returns the mean PSNR of all
+/// components.  Identical images have an infinite PSNR.
+///
+/// The images must have the same number of components, with the same size and precision.
+///
+/// ```
+/// # use jpeg2k::*;
+/// # use jpeg2k::format::J2KFormat;
+/// # fn main() -> anyhow::Result<()> {
+/// let img = Image::from_file("samples/j2k32.j2k")?;
+/// assert_eq!(psnr(&img, &img)?, f64::INFINITY);
+///
+/// let lossy = Image::from_file("samples/j2k32.j2k")?
+///   .into_bytes(J2KFormat::J2K, EncodeParameters::preset_web())?;
+/// let db = psnr(&img, &Image::from_bytes(&lossy)?)?;
+/// assert!(db > 20.0 && db < 60.0, "{db}");
+/// # Ok(())
+/// # }
+/// ```
+pub fn psnr(a: &Image, b: &Image) -> Result<f64> {
+  check_compatible(a, b)?;
+  let total: f64 = a
+    .components()
+    .iter()
+    .zip(b.components())
+    .map(|(ca, cb)| {
+      let sum: f64 = ca
+        .data()
+        .iter()
+        .zip(cb.data())
+        .map(|(&pa, &pb)| {
+          let diff = pa as f64 - pb as f64;
+          diff * diff
+        })
+        .sum();
+      let mse = sum / ca.data().len() as f64;
+      let peak = ((1u64 << ca.precision()) - 1) as f64;
+      10.0 * (peak * peak / mse).log10()
+    })
+    .sum();
+  Ok(total / a.num_components() as f64)
+}
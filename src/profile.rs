@@ -0,0 +1,284 @@
+use super::*;
+
+/// Codestream capabilities (profile) from the `Rsiz` field of the `SIZ` marker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Rsiz {
+  /// No restrictions (Part 1).
+  None,
+  /// Part 1 profile 0.
+  Profile0,
+  /// Part 1 profile 1.
+  Profile1,
+  /// Digital cinema 2K.
+  Cinema2K,
+  /// Digital cinema 4K.
+  Cinema4K,
+  /// Scalable digital cinema 2K.
+  CinemaS2K,
+  /// Scalable digital cinema 4K.
+  CinemaS4K,
+  /// Long term storage digital cinema.
+  CinemaLts,
+  /// Broadcast profile, the raw value includes the main level.
+  Broadcast(u16),
+  /// IMF profile, the raw value includes the main and sub levels.
+  Imf(u16),
+  /// Part 2 extensions, the raw value includes the extension bits.
+  Part2(u16),
+  /// Unknown or reserved value.
+  Other(u16),
+}
+
+impl Rsiz {
+  /// From the raw `Rsiz` value.
+  ///
+  /// ```
+  /// # use jpeg2k::Rsiz;
+  /// assert_eq!(Rsiz::from_raw(0x0003), Rsiz::Cinema2K);
+  /// assert_eq!(Rsiz::from_raw(0x0103), Rsiz::Broadcast(0x0103));
+  /// assert_eq!(Rsiz::from_raw(0x8001), Rsiz::Part2(0x8001));
+  /// ```
+  pub fn from_raw(raw: u16) -> Self {
+    match raw {
+      0x0000 => Self::None,
+      0x0001 => Self::Profile0,
+      0x0002 => Self::Profile1,
+      0x0003 => Self::Cinema2K,
+      0x0004 => Self::Cinema4K,
+      0x0005 => Self::CinemaS2K,
+      0x0006 => Self::CinemaS4K,
+      0x0007 => Self::CinemaLts,
+      raw if raw & 0x8000 != 0 => Self::Part2(raw),
+      raw if raw & 0xff00 <= 0x0300 && raw & 0xff00 != 0 => Self::Broadcast(raw),
+      raw if (0x0400..=0x0900).contains(&(raw & 0xff00)) => Self::Imf(raw),
+      raw => Self::Other(raw),
+    }
+  }
+
+  /// To the raw `Rsiz` value.
+  pub fn to_raw(self) -> u16 {
+    match self {
+      Self::None => 0x0000,
+      Self::Profile0 => 0x0001,
+      Self::Profile1 => 0x0002,
+      Self::Cinema2K => 0x0003,
+      Self::Cinema4K => 0x0004,
+      Self::CinemaS2K => 0x0005,
+      Self::CinemaS4K => 0x0006,
+      Self::CinemaLts => 0x0007,
+      Self::Broadcast(raw) | Self::Imf(raw) | Self::Part2(raw) | Self::Other(raw) => raw,
+    }
+  }
+}
+
+/// A codestream parameter that doesn't conform to a profile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComplianceIssue {
+  /// The parameter, for example `"progression order"`.
+  pub parameter: &'static str,
+  /// What the profile requires.
+  pub expected: String,
+  /// The value in the codestream.
+  pub found: String,
+}
+
+impl std::fmt::Display for ComplianceIssue {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "{} must be {}, found {}",
+      self.parameter, self.expected, self.found
+    )
+  }
+}
+
+/// Collects the compliance issues.
+struct Checker(Vec<ComplianceIssue>);
+
+impl Checker {
+  fn check<T: std::fmt::Display>(
+    &mut self,
+    ok: bool,
+    parameter: &'static str,
+    expected: impl Into<String>,
+    found: T,
+  ) {
+    if !ok {
+      self.0.push(ComplianceIssue {
+        parameter,
+        expected: expected.into(),
+        found: found.to_string(),
+      });
+    }
+  }
+}
+
+/// Check the codestream parameters against the constraints of `profile`.
+///
+/// `header` is the start of the file, the `Rsiz` value and the precinct sizes are parsed
+/// from the main header.  openjpeg's codestream info doesn't have them.
+pub(crate) fn check_profile(
+  profile: Rsiz,
+  img: &Image,
+  info: &CodestreamInfo,
+  header: &[u8],
+) -> Result<Vec<ComplianceIssue>> {
+  let rsiz = read_siz(header)
+    .map_err(|err| Error::CodecError(format!("Can't inspect the main header: {err}")))?
+    .rsiz;
+  let precincts = read_cod_precincts(header).ok_or_else(|| {
+    Error::CodecError("Can't inspect the main header: no COD marker before the first tile".into())
+  })?;
+  let mut c = Checker(Vec::new());
+  let single_tile = info.num_tiles() == 1;
+  let tccps = info.tccp_info();
+  let (tdx, tdy) = info.tile_size();
+  let max_cblk = |c: &mut Checker, max_exp: u32| {
+    for tccp in tccps {
+      c.check(
+        tccp.cblkw <= max_exp && tccp.cblkh <= max_exp,
+        "code-block size",
+        format!("at most {0}x{0}", 1u32 << max_exp),
+        format_args!("{}x{}", 1u32 << tccp.cblkw, 1u32 << tccp.cblkh),
+      );
+    }
+  };
+  match profile {
+    Rsiz::None => return Ok(Vec::new()),
+    Rsiz::Profile0 => {
+      c.check(
+        single_tile || (tdx == tdy && tdx <= 128),
+        "tile size",
+        "a single tile or square tiles of at most 128x128",
+        format_args!("{tdx}x{tdy}"),
+      );
+      max_cblk(&mut c, 6);
+    }
+    Rsiz::Profile1 => {
+      c.check(
+        single_tile || (tdx == tdy && tdx <= 1024),
+        "tile size",
+        "a single tile or square tiles of at most 1024x1024",
+        format_args!("{tdx}x{tdy}"),
+      );
+      max_cblk(&mut c, 6);
+    }
+    Rsiz::Cinema2K | Rsiz::Cinema4K => {
+      let is_4k = profile == Rsiz::Cinema4K;
+      let (max_w, max_h) = if is_4k { (4096, 2160) } else { (2048, 1080) };
+      let (width, height) = (img.orig_width(), img.orig_height());
+      c.check(
+        width <= max_w && height <= max_h,
+        "image size",
+        format!("at most {max_w}x{max_h}"),
+        format_args!("{width}x{height}"),
+      );
+      let comps = img.components();
+      c.check(comps.len() == 3, "number of components", "3", comps.len());
+      for comp in comps {
+        c.check(
+          comp.precision() == 12 && !comp.is_signed(),
+          "component precision",
+          "unsigned 12 bits",
+          format_args!(
+            "{} {} bits",
+            if comp.is_signed() {
+              "signed"
+            } else {
+              "unsigned"
+            },
+            comp.precision()
+          ),
+        );
+        c.check(
+          comp.subsampling() == (1, 1),
+          "component subsampling",
+          "1x1",
+          format_args!("{}x{}", comp.subsampling().0, comp.subsampling().1),
+        );
+      }
+      c.check(single_tile, "number of tiles", "1", info.num_tiles());
+      let tile = info.tile_info();
+      // `prg` is an enum with openjpeg-sys and an `i32` with openjp2.
+      #[allow(clippy::unnecessary_cast)]
+      let prg = tile.0.prg as i32;
+      let prg = ProgressionOrder::from_raw(prg);
+      c.check(
+        prg == Some(ProgressionOrder::CPRL),
+        "progression order",
+        "CPRL",
+        prg.map_or_else(|| "unknown".to_string(), |prg| prg.to_string()),
+      );
+      c.check(
+        tile.0.numlayers == 1,
+        "number of layers",
+        "1",
+        tile.0.numlayers,
+      );
+      c.check(
+        tile.0.mct == 1,
+        "multi-component transform",
+        "1",
+        tile.0.mct,
+      );
+      let (min_res, max_res) = if is_4k { (2, 7) } else { (1, 6) };
+      for tccp in tccps {
+        c.check(
+          (min_res..=max_res).contains(&tccp.numresolutions),
+          "number of resolutions",
+          format!("{min_res} to {max_res}"),
+          tccp.numresolutions,
+        );
+        c.check(
+          (tccp.cblkw, tccp.cblkh) == (5, 5),
+          "code-block size",
+          "32x32",
+          format_args!("{}x{}", 1u32 << tccp.cblkw, 1u32 << tccp.cblkh),
+        );
+        c.check(tccp.cblksty == 0, "code-block style", "0", tccp.cblksty);
+        c.check(
+          tccp.qmfbid == 0,
+          "wavelet transform",
+          "irreversible 9/7",
+          if tccp.qmfbid == 1 {
+            "reversible 5/3"
+          } else {
+            "unknown"
+          },
+        );
+      }
+      // 128x128 precincts for the lowest resolution, 256x256 for the others.
+      if precincts.iter().all(|&pp| pp == (15, 15)) {
+        c.check(
+          false,
+          "precinct size",
+          "128x128 at resolution 0 and 256x256 at the others",
+          "the default (no precincts)",
+        );
+      } else {
+        for (res, &(pw, ph)) in precincts.iter().enumerate() {
+          let exp = if res == 0 { 7 } else { 8 };
+          c.check(
+            (pw, ph) == (exp, exp),
+            "precinct size",
+            format!("{0}x{0} at resolution {res}", 1u32 << exp),
+            format_args!("{}x{}", 1u32 << pw, 1u32 << ph),
+          );
+        }
+      }
+    }
+    profile => {
+      return Err(Error::InvalidParametersError(format!(
+        "no compliance rules for profile {profile:?}"
+      )));
+    }
+  }
+  c.check(
+    rsiz == profile,
+    "Rsiz",
+    format!("{profile:?}"),
+    format_args!("{rsiz:?}"),
+  );
+  Ok(c.0)
+}
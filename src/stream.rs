@@ -1,6 +1,10 @@
+use std::borrow::Cow;
+use std::cell::Cell;
 #[cfg(feature = "file-io")]
 use std::ffi::CString;
 use std::os::raw::c_void;
+use std::path::PathBuf;
+use std::ptr;
 
 #[cfg(feature = "file-io")]
 use std::path::Path;
@@ -52,11 +56,64 @@ impl<'a> WrappedSlice<'a> {
   }
 }
 
+/// Output buffer for an in-memory write stream.
+#[derive(Default)]
+struct WrappedVec {
+  offset: usize,
+  buf: Vec<u8>,
+}
+
+impl WrappedVec {
+  fn write(&mut self, data: &[u8]) -> usize {
+    let end = self.offset + data.len();
+    if end > self.buf.len() {
+      self.buf.resize(end, 0);
+    }
+    self.buf[self.offset..end].copy_from_slice(data);
+    self.offset = end;
+    data.len()
+  }
+
+  fn seek(&mut self, new_offset: usize) {
+    // Seeking past the end grows the buffer on the next write.
+    self.offset = new_offset;
+  }
+}
+
+/// A `Write + Seek` output.
+pub(crate) trait WriteSeek: std::io::Write + std::io::Seek {}
+
+impl<W: std::io::Write + std::io::Seek> WriteSeek for W {}
+
+/// Output writer of a writer stream.
+struct WrappedWriter<'a> {
+  writer: &'a mut dyn WriteSeek,
+  /// Position of the writer when the stream was created, openjpeg seeks relative to it.
+  start: u64,
+  /// The first IO error, openjpeg only sees that a write failed.
+  error: &'a Cell<Option<std::io::Error>>,
+}
+
+impl WrappedWriter<'_> {
+  fn check<T>(&self, res: std::io::Result<T>) -> Option<T> {
+    res
+      .map_err(|err| {
+        let prev = self.error.take();
+        self.error.set(prev.or(Some(err)));
+      })
+      .ok()
+  }
+}
+
 pub(crate) struct Stream<'a> {
   stream: *mut sys::opj_stream_t,
   format: J2KFormat,
   is_input: bool,
   buf: Option<&'a [u8]>,
+  /// Path of a file stream.
+  path: Option<PathBuf>,
+  /// Output buffer of a memory stream, owned by the `opj_stream_t`.
+  out: Option<ptr::NonNull<WrappedVec>>,
 }
 
 impl Drop for Stream<'_> {
@@ -71,6 +128,8 @@ impl std::fmt::Debug for Stream<'_> {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     if let Some(slice) = &self.buf {
       f.write_fmt(format_args!("BufStream: len={}", slice.len()))
+    } else if self.out.is_some() {
+      f.write_fmt(format_args!("VecStream"))
     } else {
       f.write_fmt(format_args!("FileStream"))
     }
@@ -114,9 +173,111 @@ extern "C" fn buf_read_stream_seek_fn(nb_bytes: i64, p_data: *mut c_void) -> i32
   }
 }
 
+extern "C" fn vec_write_stream_free_fn(p_data: *mut c_void) {
+  let ptr = p_data as *mut WrappedVec;
+  drop(unsafe { Box::from_raw(ptr) })
+}
+
+extern "C" fn vec_write_stream_write_fn(
+  p_buffer: *mut c_void,
+  nb_bytes: usize,
+  p_data: *mut c_void,
+) -> usize {
+  if p_buffer.is_null() {
+    return usize::MAX;
+  }
+
+  let out = unsafe { &mut *(p_data as *mut WrappedVec) };
+  let in_buf = unsafe { std::slice::from_raw_parts(p_buffer as *const u8, nb_bytes) };
+  out.write(in_buf)
+}
+
+extern "C" fn vec_write_stream_skip_fn(nb_bytes: i64, p_data: *mut c_void) -> i64 {
+  let out = unsafe { &mut *(p_data as *mut WrappedVec) };
+  match out.offset.checked_add_signed(nb_bytes as isize) {
+    Some(offset) => {
+      out.seek(offset);
+      nb_bytes
+    }
+    None => -1,
+  }
+}
+
+extern "C" fn vec_write_stream_seek_fn(nb_bytes: i64, p_data: *mut c_void) -> i32 {
+  let out = unsafe { &mut *(p_data as *mut WrappedVec) };
+  if nb_bytes < 0 {
+    return 0;
+  }
+  out.seek(nb_bytes as usize);
+  1
+}
+
+extern "C" fn writer_stream_free_fn(p_data: *mut c_void) {
+  let ptr = p_data as *mut WrappedWriter;
+  drop(unsafe { Box::from_raw(ptr) })
+}
+
+extern "C" fn writer_stream_write_fn(
+  p_buffer: *mut c_void,
+  nb_bytes: usize,
+  p_data: *mut c_void,
+) -> usize {
+  if p_buffer.is_null() {
+    return usize::MAX;
+  }
+
+  let out = unsafe { &mut *(p_data as *mut WrappedWriter) };
+  let in_buf = unsafe { std::slice::from_raw_parts(p_buffer as *const u8, nb_bytes) };
+  let res = out.writer.write_all(in_buf);
+  match out.check(res) {
+    Some(()) => nb_bytes,
+    None => usize::MAX,
+  }
+}
+
+extern "C" fn writer_stream_skip_fn(nb_bytes: i64, p_data: *mut c_void) -> i64 {
+  let out = unsafe { &mut *(p_data as *mut WrappedWriter) };
+  let res = out.writer.seek(std::io::SeekFrom::Current(nb_bytes));
+  match out.check(res) {
+    Some(_) => nb_bytes,
+    None => -1,
+  }
+}
+
+extern "C" fn writer_stream_seek_fn(nb_bytes: i64, p_data: *mut c_void) -> i32 {
+  let out = unsafe { &mut *(p_data as *mut WrappedWriter) };
+  let offset = match u64::try_from(nb_bytes)
+    .ok()
+    .and_then(|n| out.start.checked_add(n))
+  {
+    Some(offset) => offset,
+    None => return 0,
+  };
+  let res = out.writer.seek(std::io::SeekFrom::Start(offset));
+  out.check(res).is_some() as i32
+}
+
+/// Detect the Jpeg 2000 format from the magic bytes at the start of the file.
+#[cfg(feature = "file-io")]
+fn detect_file_format(path: &Path) -> Option<J2KFormat> {
+  use std::io::Read;
+
+  let mut magic = Vec::with_capacity(JP2_RFC3745_MAGIC.len());
+  std::fs::File::open(path)
+    .ok()?
+    .take(JP2_RFC3745_MAGIC.len() as u64)
+    .read_to_end(&mut magic)
+    .ok()?;
+  j2k_detect_format(&magic).ok()
+}
+
 impl<'a> Stream<'a> {
   pub(crate) fn from_bytes(buf: &'a [u8]) -> Result<Self> {
     let format = j2k_detect_format(buf)?;
+    Self::from_bytes_as(buf, format)
+  }
+
+  pub(crate) fn from_bytes_as(buf: &'a [u8], format: J2KFormat) -> Result<Self> {
     let len = buf.len();
     let data = WrappedSlice::new(buf);
     unsafe {
@@ -133,20 +294,120 @@ impl<'a> Stream<'a> {
         format,
         is_input: true,
         buf: Some(buf),
+        path: None,
+        out: None,
+      })
+    }
+  }
+
+  /// In-memory output stream.  Use `take_bytes` to get the encoded bytes.
+  pub(crate) fn to_bytes(format: J2KFormat) -> Result<Self> {
+    let data = Box::<WrappedVec>::default();
+    unsafe {
+      let stream = sys::opj_stream_default_create(0);
+      if stream.is_null() {
+        return Err(Error::NullPointerError(
+          "Failed to create memory stream: NULL opj_stream_t",
+        ));
+      }
+      let p_data = Box::into_raw(data);
+      sys::opj_stream_set_write_function(stream, Some(vec_write_stream_write_fn));
+      sys::opj_stream_set_skip_function(stream, Some(vec_write_stream_skip_fn));
+      sys::opj_stream_set_seek_function(stream, Some(vec_write_stream_seek_fn));
+      sys::opj_stream_set_user_data(
+        stream,
+        p_data as *mut c_void,
+        Some(vec_write_stream_free_fn),
+      );
+
+      Ok(Self {
+        stream,
+        format,
+        is_input: false,
+        buf: None,
+        path: None,
+        out: ptr::NonNull::new(p_data),
+      })
+    }
+  }
+
+  /// Output stream writing to `writer`.  IO errors are stored in `error`.
+  pub(crate) fn to_writer(
+    writer: &'a mut dyn WriteSeek,
+    error: &'a Cell<Option<std::io::Error>>,
+    format: J2KFormat,
+  ) -> Result<Self> {
+    let start = writer
+      .stream_position()
+      .map_err(|err| Error::Other(err.into()))?;
+    let data = Box::new(WrappedWriter {
+      writer,
+      start,
+      error,
+    });
+    unsafe {
+      let stream = sys::opj_stream_default_create(0);
+      if stream.is_null() {
+        return Err(Error::NullPointerError(
+          "Failed to create writer stream: NULL opj_stream_t",
+        ));
+      }
+      let p_data = Box::into_raw(data);
+      sys::opj_stream_set_write_function(stream, Some(writer_stream_write_fn));
+      sys::opj_stream_set_skip_function(stream, Some(writer_stream_skip_fn));
+      sys::opj_stream_set_seek_function(stream, Some(writer_stream_seek_fn));
+      sys::opj_stream_set_user_data(stream, p_data as *mut c_void, Some(writer_stream_free_fn));
+
+      Ok(Self {
+        stream,
+        format,
+        is_input: false,
+        buf: None,
+        path: None,
+        out: None,
       })
     }
   }
 
+  /// Take the bytes written to a memory stream.
+  pub(crate) fn take_bytes(&mut self) -> Option<Vec<u8>> {
+    self
+      .out
+      .map(|mut out| std::mem::take(&mut unsafe { out.as_mut() }.buf))
+  }
+
   #[cfg(feature = "file-io")]
-  pub(crate) fn new_file<P: AsRef<Path>>(path: P, is_input: bool) -> Result<Self> {
+  pub(crate) fn new_file<P: AsRef<Path>>(
+    path: P,
+    is_input: bool,
+    format: Option<J2KFormat>,
+  ) -> Result<Self> {
     let path = path.as_ref();
     if !path.exists() && is_input {
       return Err(Error::FileNotFoundError(format!("{:?}", path)));
     }
-    let format = j2k_detect_format_from_extension(path.extension())?;
-    let c_path = path
-      .to_str()
-      .and_then(|p| CString::new(p.as_bytes()).ok())
+    let format = match format {
+      Some(format) => format,
+      None if is_input => {
+        // Detect the format from the file contents, fallback to the file extension.
+        match detect_file_format(path) {
+          Some(format) => format,
+          None => j2k_detect_format_from_extension(path.extension())?,
+        }
+      }
+      None => j2k_detect_format_from_extension(path.extension())?,
+    };
+    // On Unix paths are passed as raw bytes, so non-UTF-8 paths work.  Other platforms
+    // (Windows) need UTF-8 paths, openjpeg takes the path as a `*const c_char`.
+    #[cfg(unix)]
+    let path_bytes = {
+      use std::os::unix::ffi::OsStrExt;
+      Some(path.as_os_str().as_bytes())
+    };
+    #[cfg(not(unix))]
+    let path_bytes = path.to_str().map(str::as_bytes);
+    let c_path = path_bytes
+      .and_then(|p| CString::new(p).ok())
       .ok_or_else(|| {
         Error::BadFilenameError(format!("Can't pass filename to openjpeg-sys: {:?}", path))
       })?;
@@ -163,23 +424,69 @@ impl<'a> Stream<'a> {
       format,
       is_input,
       buf: None,
+      path: Some(path.to_path_buf()),
+      out: None,
     })
   }
 
   #[cfg(feature = "file-io")]
   pub(crate) fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
-    Self::new_file(path, true)
+    Self::new_file(path, true, None)
+  }
+
+  #[cfg(feature = "file-io")]
+  pub(crate) fn from_file_as<P: AsRef<Path>>(path: P, format: J2KFormat) -> Result<Self> {
+    Self::new_file(path, true, Some(format))
   }
 
   #[cfg(feature = "file-io")]
   pub(crate) fn to_file<P: AsRef<Path>>(path: P) -> Result<Self> {
-    Self::new_file(path, false)
+    Self::new_file(path, false, None)
   }
 
   pub(crate) fn format(&self) -> J2KFormat {
     self.format
   }
 
+  /// The start of an input stream, for parsing the main header.
+  ///
+  /// Byte streams are returned as is.  For files only the main header is read.
+  pub(crate) fn header_bytes(&self) -> Result<Cow<'_, [u8]>> {
+    if !self.is_input {
+      return Err(Error::CodecError("Not an input stream".into()));
+    }
+    match (self.buf, &self.path) {
+      (Some(buf), _) => Ok(Cow::Borrowed(buf)),
+      #[cfg(feature = "file-io")]
+      (None, Some(path)) => {
+        let buf = read_file_main_header(path, self.format.is_container()).map_err(|err| {
+          Error::CodecError(format!("Failed to read the header of {path:?}: {err}"))
+        })?;
+        Ok(Cow::Owned(buf))
+      }
+      _ => Err(Error::CodecError("Stream has no header bytes".into())),
+    }
+  }
+
+  /// The top-level boxes with a type in `box_types` of a JP2 input stream.
+  pub(crate) fn boxes(&self, box_types: &[&[u8; 4]]) -> Vec<([u8; 4], Cow<'_, [u8]>)> {
+    if !self.is_input || self.format != J2KFormat::JP2 {
+      return Vec::new();
+    }
+    match (self.buf, &self.path) {
+      (Some(buf), _) => BoxIter::new(buf)
+        .filter(|(box_type, _)| box_types.contains(&box_type))
+        .map(|(box_type, range)| (box_type, Cow::Borrowed(&buf[range])))
+        .collect(),
+      #[cfg(feature = "file-io")]
+      (None, Some(path)) => read_file_boxes(path, box_types)
+        .into_iter()
+        .map(|(box_type, content)| (box_type, Cow::Owned(content)))
+        .collect(),
+      _ => Vec::new(),
+    }
+  }
+
   pub(crate) fn is_input(&self) -> bool {
     self.is_input
   }
@@ -0,0 +1,106 @@
+//! Deterministic images for examples and tests, they don't need any sample files.
+//!
+//! Needs the `test-util` feature.
+//!
+//! ```
+//! use jpeg2k::test_patterns;
+//! # use jpeg2k::*;
+//! # use jpeg2k::format::J2KFormat;
+//! # fn main() -> jpeg2k::error::Result<()> {
+//! // Lossless round-trip.
+//! let img = test_patterns::rgb_bars(64, 32)?;
+//! let expected = test_patterns::rgb_bars(64, 32)?;
+//! let decoded = Image::from_bytes(&img.into_bytes(J2KFormat::JP2, EncodeParameters::default())?)?;
+//! for (comp, expected) in decoded.components().iter().zip(expected.components()) {
+//!   assert_eq!(comp.data(), expected.data());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use super::*;
+
+/// Grayscale image with a horizontal gradient, from `0` on the left to the max value
+/// of `prec` bits on the right.
+///
+/// ```
+/// # use jpeg2k::*;
+/// # fn main() -> jpeg2k::error::Result<()> {
+/// let img = jpeg2k::test_patterns::gray_gradient(5, 2, 12)?;
+/// assert_eq!(img.color_space(), ColorSpace::Gray);
+/// assert_eq!(img.components()[0].data(), [0, 1023, 2047, 3071, 4095, 0, 1023, 2047, 3071, 4095]);
+/// # Ok(())
+/// # }
+/// ```
+pub fn gray_gradient(width: u32, height: u32, prec: u32) -> Result<Image> {
+  if !(1..=31).contains(&prec) {
+    return Err(Error::UnsupportedPrecisionError(prec));
+  }
+  let max = (1u64 << prec) - 1;
+  let last = width.saturating_sub(1).max(1) as u64;
+  let row = (0..width as u64)
+    .map(|x| (x * max / last) as i32)
+    .collect::<Vec<_>>();
+  let data = (0..height).flat_map(|_| row.iter().copied()).collect();
+  Image::from_components(
+    width,
+    height,
+    ColorSpace::Gray,
+    vec![ComponentData::new(data, prec)],
+  )
+}
+
+/// 8bit sRGB image with 8 vertical color bars: white, yellow, cyan, green, magenta,
+/// red, blue and black.
+pub fn rgb_bars(width: u32, height: u32) -> Result<Image> {
+  const BARS: [[i32; 3]; 8] = [
+    [255, 255, 255],
+    [255, 255, 0],
+    [0, 255, 255],
+    [0, 255, 0],
+    [255, 0, 255],
+    [255, 0, 0],
+    [0, 0, 255],
+    [0, 0, 0],
+  ];
+  let bar_width = width.div_ceil(BARS.len() as u32).max(1);
+  let components = (0..3)
+    .map(|c| {
+      let row = (0..width)
+        .map(|x| BARS[(x / bar_width) as usize][c])
+        .collect::<Vec<_>>();
+      let data = (0..height).flat_map(|_| row.iter().copied()).collect();
+      ComponentData::new(data, 8)
+    })
+    .collect();
+  Image::from_components(width, height, ColorSpace::SRGB, components)
+}
+
+/// 8bit grayscale checkerboard of `block x block` squares, starting with black (`0`)
+/// in the top left corner and alternating with white (`255`).
+// `u32::is_multiple_of` needs Rust 1.87.
+#[allow(clippy::manual_is_multiple_of)]
+pub fn checkerboard(width: u32, height: u32, block: u32) -> Result<Image> {
+  if block == 0 {
+    return Err(Error::InvalidParametersError(
+      "checkerboard block size must be at least 1".into(),
+    ));
+  }
+  let data = (0..height)
+    .flat_map(|y| {
+      (0..width).map(move |x| {
+        if (x / block + y / block) % 2 == 0 {
+          0
+        } else {
+          255
+        }
+      })
+    })
+    .collect();
+  Image::from_components(
+    width,
+    height,
+    ColorSpace::Gray,
+    vec![ComponentData::new(data, 8)],
+  )
+}
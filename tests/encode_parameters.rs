@@ -0,0 +1,186 @@
+//! Encode round trips of the `EncodeParameters` options.
+
+use jpeg2k::error::{Error, Result};
+use jpeg2k::format::J2KFormat;
+use jpeg2k::*;
+
+/// A grayscale 8bit ramp.
+fn gray_image(width: u32, height: u32) -> Result<Image> {
+  let data = (0..width * height).map(|v| (v % 256) as i32).collect();
+  Image::from_components(
+    width,
+    height,
+    ColorSpace::Gray,
+    vec![ComponentData::new(data, 8)],
+  )
+}
+
+/// Check for a marker in a codestream.  Marker codes can't appear in the compressed data.
+fn has_marker(buf: &[u8], code: u8) -> bool {
+  buf.windows(2).any(|w| w == [0xff, code])
+}
+
+#[test]
+fn plt_tlm() -> Result<()> {
+  let img = gray_image(256, 256)?;
+  let params = EncodeParameters::default().tiles(128, 128);
+  let buf = img.to_bytes(J2KFormat::J2K, params.clone())?;
+  assert!(!has_marker(&buf, 0x58) && !has_marker(&buf, 0x55));
+
+  let params = params.write_plt(true).write_tlm(true);
+  let buf = img.to_bytes(J2KFormat::J2K, params)?;
+  assert!(has_marker(&buf, 0x58) && has_marker(&buf, 0x55));
+  assert_eq!(DumpImage::from_bytes(&buf)?.num_tiles()?, 4);
+  Ok(())
+}
+
+#[test]
+fn sop_eph() -> Result<()> {
+  let img = gray_image(64, 64)?;
+  let buf = img.to_bytes(J2KFormat::J2K, EncodeParameters::default().sop(true))?;
+  assert!(has_marker(&buf, 0x91) && !has_marker(&buf, 0x92));
+  let buf = img.to_bytes(J2KFormat::J2K, EncodeParameters::default().eph(true))?;
+  assert!(!has_marker(&buf, 0x91) && has_marker(&buf, 0x92));
+  assert_eq!(Image::from_bytes(&buf)?.width(), 64);
+  Ok(())
+}
+
+#[test]
+fn resolutions() -> Result<()> {
+  let buf =
+    gray_image(32, 32)?.to_bytes(J2KFormat::J2K, EncodeParameters::default().resolutions(3))?;
+  assert_eq!(
+    Image::decode_pyramid(&buf, DecodeParameters::new())?.len(),
+    3
+  );
+
+  // Clamped to 3 resolutions for 4x4 pixels.
+  let buf = gray_image(4, 4)?.to_bytes(J2KFormat::J2K, EncodeParameters::default())?;
+  assert_eq!(
+    Image::decode_pyramid(&buf, DecodeParameters::new())?.len(),
+    3
+  );
+  Ok(())
+}
+
+#[test]
+fn precincts() -> Result<()> {
+  let img = gray_image(64, 64)?;
+  // Digital cinema: 256x256 precincts, 128x128 for the lowest of the 6 resolutions.
+  let mut sizes = vec![(256, 256); 5];
+  sizes.push((128, 128));
+  let params = EncodeParameters::default().precincts(&sizes);
+  let buf = img.to_bytes(J2KFormat::J2K, params)?;
+  let issues = DumpImage::from_bytes(&buf)?.check_profile(Rsiz::Cinema2K)?;
+  assert!(issues.iter().all(|i| i.parameter != "precinct size"));
+
+  // More sizes than resolutions.
+  let params = EncodeParameters::default()
+    .resolutions(2)
+    .precincts(&[(64, 64); 3]);
+  let err = img.to_bytes(J2KFormat::J2K, params).unwrap_err();
+  assert!(matches!(err, Error::InvalidParametersError(_)), "{err}");
+  let params = EncodeParameters::default().precincts(&[(100, 100)]);
+  let err = img.to_bytes(J2KFormat::J2K, params).unwrap_err();
+  assert!(matches!(err, Error::InvalidParametersError(_)), "{err}");
+  Ok(())
+}
+
+#[test]
+fn comment() -> Result<()> {
+  let img = gray_image(64, 64)?;
+  let params = EncodeParameters::default().comment("Scanned by the city archive");
+  let buf = img.to_bytes(J2KFormat::J2K, params)?;
+  assert!(buf.windows(27).any(|w| w == b"Scanned by the city archive"));
+
+  // The longest comment a COM marker can hold.
+  let params = EncodeParameters::default().comment(&"a".repeat(65531));
+  let buf = img.to_bytes(J2KFormat::J2K, params)?;
+  assert_eq!(Image::from_bytes(&buf)?.width(), 64);
+
+  let params = EncodeParameters::default().comment("a\0b");
+  let err = img.to_bytes(J2KFormat::J2K, params).unwrap_err();
+  assert!(matches!(err, Error::InvalidParametersError(_)), "{err}");
+  let params = EncodeParameters::default().comment(&"a".repeat(65532));
+  let err = img.to_bytes(J2KFormat::J2K, params).unwrap_err();
+  assert!(err
+    .to_string()
+    .contains("comment of 65532 bytes, at most 65531 bytes"));
+  Ok(())
+}
+
+#[test]
+fn roi() -> Result<()> {
+  let img = gray_image(64, 64)?;
+  let buf = img.to_bytes(J2KFormat::J2K, EncodeParameters::default().roi(0, 6))?;
+  assert!(has_marker(&buf, 0x5e));
+  assert_eq!(Image::from_bytes(&buf)?.width(), 64);
+
+  // The image has one component.
+  let err = img
+    .to_bytes(J2KFormat::J2K, EncodeParameters::default().roi(1, 6))
+    .unwrap_err();
+  assert!(matches!(err, Error::InvalidParametersError(_)), "{err}");
+  Ok(())
+}
+
+#[test]
+fn progression_order() -> Result<()> {
+  let params = EncodeParameters::default().progression_order(ProgressionOrder::CPRL);
+  let buf = gray_image(64, 64)?.to_bytes(J2KFormat::J2K, params)?;
+  let issues = DumpImage::from_bytes(&buf)?.check_profile(Rsiz::Cinema2K)?;
+  assert!(!issues
+    .iter()
+    .any(|issue| issue.parameter == "progression order"));
+  Ok(())
+}
+
+#[test]
+fn tiles() -> Result<()> {
+  let img = gray_image(256, 200)?;
+  let params = EncodeParameters::default().tiles(128, 128);
+  let buf = img.to_bytes(J2KFormat::J2K, params.clone())?;
+  let dump = DumpImage::from_bytes(&buf)?;
+  assert_eq!((dump.num_tiles()?, dump.tile_size()?), (4, (128, 128)));
+
+  let buf = img.to_bytes(J2KFormat::J2K, params.tiles(0, 0))?;
+  assert_eq!(DumpImage::from_bytes(&buf)?.num_tiles()?, 1);
+  Ok(())
+}
+
+#[test]
+fn code_block_size() -> Result<()> {
+  let img = gray_image(64, 64)?;
+  let params = EncodeParameters::default().code_block_size(32, 32);
+  let buf = img.to_bytes(J2KFormat::J2K, params)?;
+  let issues = DumpImage::from_bytes(&buf)?.check_profile(Rsiz::Cinema2K)?;
+  assert!(issues.iter().all(|i| i.parameter != "code-block size"));
+
+  // 128x128 is more than 4096 samples.
+  let params = EncodeParameters::default().code_block_size(128, 128);
+  let err = img.to_bytes(J2KFormat::J2K, params).unwrap_err();
+  assert!(matches!(err, Error::CreateCodecError(_)), "{err}");
+  let params = EncodeParameters::default().code_block_size(48, 32);
+  let err = img.to_bytes(J2KFormat::J2K, params).unwrap_err();
+  assert!(matches!(err, Error::CreateCodecError(_)), "{err}");
+  Ok(())
+}
+
+#[test]
+fn fixed_allocation() -> Result<()> {
+  let img = gray_image(64, 64)?;
+  // 2 layers of 3 resolutions: 2 and then 4 bit-planes of the 8bit component.
+  let matrix = [[4; 9], [8; 9]].concat();
+  let params = EncodeParameters::default().fixed_allocation(matrix.clone(), 2, 3);
+  let buf = img.to_bytes(J2KFormat::J2K, params)?;
+  // Deterministic output.
+  let params = EncodeParameters::default().fixed_allocation(matrix, 2, 3);
+  assert_eq!(img.to_bytes(J2KFormat::J2K, params)?, buf);
+  assert_eq!(Image::from_bytes(&buf)?.width(), 64);
+
+  // Wrong matrix size.
+  let params = EncodeParameters::default().fixed_allocation(vec![16; 8], 2, 3);
+  let err = img.to_bytes(J2KFormat::J2K, params).unwrap_err();
+  assert!(matches!(err, Error::InvalidParametersError(_)), "{err}");
+  Ok(())
+}